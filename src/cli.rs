@@ -1,6 +1,17 @@
 //! CLI Data
 use crate::{
-  generate::{errors::*, parameters, utils},
+  caches::CacheError,
+  doctor::DoctorError,
+  generate::{
+    errors::*,
+    parameters,
+    pipelines::TaskRunners,
+    projects::ProjectConfig,
+    specs::{diffs::SpecDiffFormats, fetches::GitSpecSource, lints::LintFormats},
+    utils,
+  },
+  logging::LogFormats,
+  reports::ReportFormats,
   testing,
 };
 use chrono::{DateTime, Utc};
@@ -8,7 +19,7 @@ use clap::{Parser, Subcommand};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Error as SerdeYAMLError;
-use std::{env, io::Error as IOError, ops::Deref, path::PathBuf};
+use std::{env, io::Error as IOError, ops::Deref, path::PathBuf, time::Duration};
 use strum::EnumProperty;
 use thiserror::Error;
 use url::Url;
@@ -17,7 +28,7 @@ use url::Url;
 mod defaults {
   use super::*;
   /// Current working directory
-  pub const CWD: Lazy<PathBuf> = Lazy::new(|| env::current_dir().expect("must get current dir"));
+  pub static CWD: Lazy<PathBuf> = Lazy::new(|| env::current_dir().expect("must get current dir"));
 }
 use defaults::*;
 
@@ -25,6 +36,7 @@ use defaults::*;
 pub struct Cli {
   pub inner_cli: InnerCli,
   pub generation_timestamp: DateTime<Utc>,
+  pub project_config: ProjectConfig,
 }
 impl Deref for Cli {
   type Target = InnerCli;
@@ -33,10 +45,26 @@ impl Deref for Cli {
   }
 }
 impl Cli {
+  /// Get a copy with another output project dir
+  pub fn with_output_project_dir(
+    &self,
+    output_project_dir: PathBuf,
+  ) -> Self {
+    let mut cli = self.clone();
+    cli.inner_cli.output_project_dir_opt = Some(output_project_dir);
+    cli
+  }
   /// Get formatted timestamp string (RFC 3339)
   pub fn get_generation_timestamp_string(&self) -> String {
     self.generation_timestamp.to_rfc3339()
   }
+  /// Get the command whose stdout is the spec, unless a spec file or url is given
+  pub fn get_spec_command_opt(&self) -> Option<&str> {
+    match (&self.local_api_spec_filepath_opt, &self.api_spec_url_opt) {
+      (None, None) => self.project_config.spec_command_opt.as_deref(),
+      _ => None,
+    }
+  }
   /// Instantiate
   pub async fn new() -> Result<Self, CLIError> {
     let mut inner_cli = InnerCli::parse();
@@ -44,9 +72,15 @@ impl Cli {
       command,
       output_project_dir_opt,
       local_api_spec_filepath_opt,
+      site_or_api_name_opt,
+      api_url_opt,
       ..
     } = &mut inner_cli;
     if let Some(SubCommands::TestGeneration { .. }) = command.as_mut() {
+      site_or_api_name_opt.get_or_insert_with(|| testing::TEST_API_NAME.to_string());
+      if api_url_opt.is_none() {
+        let _ = api_url_opt.replace(Url::parse(testing::TEST_API_URL).expect("must parse url"));
+      }
       // use the temp directory
       let temp_subdir_path = utils::get_temp_subdir();
       if local_api_spec_filepath_opt.is_none() {
@@ -57,14 +91,30 @@ impl Cli {
       if output_project_dir_opt.is_none() {
         let _ = output_project_dir_opt.replace(temp_subdir_path);
       }
-    } else if inner_cli.local_api_spec_filepath_opt.is_none()
+    } else if command.is_none() {
+      if site_or_api_name_opt.is_none() {
+        return Err(ParameterError::APINameNeeded.into());
+      }
+      if api_url_opt.is_none() {
+        return Err(ParameterError::APIUrlNeeded.into());
+      }
+    }
+    let mut project_config = inner_cli.try_get_project_config().await?;
+    if let Some(spec_command) = inner_cli.spec_command_opt.as_ref() {
+      project_config.spec_command_opt = Some(spec_command.clone());
+      project_config.spec_command_dir_opt = Some(CWD.clone());
+    }
+    if inner_cli.command.is_none()
+      && inner_cli.local_api_spec_filepath_opt.is_none()
       && inner_cli.api_spec_url_opt.is_none()
+      && project_config.spec_command_opt.is_none()
     {
       return Err(ParameterError::APIUrlNeededIfNoLocalFile.into());
     }
     Ok(Self {
       generation_timestamp: Utc::now(),
       inner_cli,
+      project_config,
     })
   }
 }
@@ -74,21 +124,45 @@ impl Cli {
 pub enum CLIError {
   #[error(transparent)]
   IOError(#[from] IOError),
+  #[error("Cancelled")]
+  Cancelled,
+  #[error(transparent)]
+  BatchError(#[from] BatchError),
+  #[error(transparent)]
+  CacheError(#[from] CacheError),
   #[error(transparent)]
   CargoConfigError(#[from] CargoConfigError),
   #[error(transparent)]
+  ChangelogGenerationError(#[from] ChangelogGenerationError),
+  #[error(transparent)]
   CrateScaffoldingError(#[from] CrateScaffoldingError),
   #[error(transparent)]
+  DoctorError(#[from] DoctorError),
+  #[error(transparent)]
+  LockError(#[from] LockError),
+  #[error(transparent)]
   MakefileGenerationError(#[from] MakefileGenerationError),
   #[error(transparent)]
   ParameterError(#[from] ParameterError),
   #[error(transparent)]
+  PipelineError(#[from] PipelineError),
+  #[error(transparent)]
   ProcessError(#[from] utils::ProcessError),
   #[error(transparent)]
+  ProjectConfigError(#[from] ProjectConfigError),
+  #[error(transparent)]
   READMEGenerationError(#[from] READMEGenerationError),
   #[error(transparent)]
+  SerdeJSONError(#[from] serde_json::Error),
+  #[error(transparent)]
   SerdeYAMLError(#[from] SerdeYAMLError),
   #[error(transparent)]
+  SpecError(#[from] SpecError),
+  #[error(transparent)]
+  TransactionError(#[from] TransactionError),
+  #[error(transparent)]
+  VersionError(#[from] VersionError),
+  #[error(transparent)]
   YAMLGenerationError(#[from] YAMLGenerationError),
   #[error(transparent)]
   TestingError(#[from] testing::TestingError),
@@ -113,6 +187,75 @@ pub enum SubCommands {
     #[arg(short = 'u', long = "generator-crate-repo-url")]
     generator_crate_repo_url_opt: Option<Url>,
   },
+  /// Swaps the crate with the backup kept by the last staged generation
+  ///
+  /// Running it again redoes the generation.
+  #[command(rename_all = "kebab-case", verbatim_doc_comment)]
+  Rollback,
+  /// Scaffolds or regenerates every API listed in a manifest
+  ///
+  /// A failing API does not stop the others. Each API runs as its own `olg` process from the
+  /// manifest's dir, logging to `.olg/batch/<name>.log` there.
+  #[command(rename_all = "kebab-case", verbatim_doc_comment)]
+  Batch {
+    /// The batch manifest yaml file
+    manifest_path: PathBuf,
+    /// How many APIs to generate at once (overrides the manifest)
+    #[arg(long = "concurrency")]
+    concurrency_opt: Option<usize>,
+  },
+  /// Compares two specs, classifying changes as breaking or not for the client crate
+  ///
+  /// Also run on `--regenerate` against the crate's previous spec.
+  #[command(rename_all = "kebab-case", verbatim_doc_comment)]
+  SpecDiff {
+    /// The old spec file
+    old_spec_path: PathBuf,
+    /// The new spec file
+    new_spec_path: PathBuf,
+    /// Report format
+    #[arg(long = "format", value_enum, default_value_t = SpecDiffFormats::Text)]
+    format: SpecDiffFormats,
+  },
+  /// Checks a spec for what makes the generator produce poor code
+  ///
+  /// Rule severities come from the `lint` section of the project config. Fails on errors.
+  /// Also run when the spec is prepared for generation.
+  #[command(rename_all = "kebab-case", verbatim_doc_comment)]
+  Lint {
+    /// The spec file
+    spec_path: PathBuf,
+    /// Report format
+    #[arg(long = "format", value_enum, default_value_t = LintFormats::Text)]
+    format: LintFormats,
+  },
+  /// Checks that the tools used for generation are installed
+  #[command(rename_all = "kebab-case")]
+  Doctor {
+    /// Try to install the missing tools that can be installed automatically
+    #[arg(long = "install")]
+    install: bool,
+  },
+  /// Lists or prunes the cache of specs and generator jars shared by every crate
+  #[command(rename_all = "kebab-case")]
+  Cache {
+    #[command(subcommand)]
+    command: CacheCommands,
+  },
+}
+
+/// Subcommands for [SubCommands::Cache]
+#[derive(Clone, Debug, Deserialize, Serialize, Subcommand)]
+pub enum CacheCommands {
+  /// Lists the cached artifacts, most recently used first
+  List,
+  /// Removes cached artifacts
+  #[command(rename_all = "kebab-case")]
+  Prune {
+    /// Only remove the artifacts not used for this many days
+    #[arg(long = "older-than")]
+    older_than_days_opt: Option<u32>,
+  },
 }
 
 /// =================== OpenAPI client  crate generator ====================
@@ -143,21 +286,29 @@ pub enum SubCommands {
 #[derive(Clone, Debug, Deserialize, Parser, Serialize)]
 #[command(author, version, about, verbatim_doc_comment)]
 pub struct InnerCli {
-  /// The site or app name. Will be used to determine generated crate name
-  #[arg(long = "name")]
-  pub site_or_api_name: String,
-  /// The app URL. It's just there for documentation and referencing.
-  #[arg(long = "api-url")]
-  pub api_url: Url,
+  /// The site or app name. Will be used to determine generated crate name (required to generate)
+  #[arg(long = "name", value_name = "SITE_OR_API_NAME")]
+  pub site_or_api_name_opt: Option<String>,
+  /// The app URL. It's just there for documentation and referencing (required to generate)
+  #[arg(long = "api-url", value_name = "API_URL")]
+  pub api_url_opt: Option<Url>,
   /// Autogenerate after scaffolding the crate. (enabled by default)
-  #[arg(long = "autogenerate", default_value_t = true)]
+  #[arg(long = "autogenerate", action = clap::ArgAction::Set, default_value_t = true)]
   pub autogenerate: bool,
   /// The api spec URL. If provided, the generator will fetch the json or yaml OpenAPI specification from here.
+  /// Use `git:<path-or-url>#<ref>:<file>` to read it from a git repository at a given ref.
   #[arg(long = "spec-url")]
   pub api_spec_url_opt: Option<Url>,
-  /// API Spec as a local file. If provided, this overrides the [Self::api_spec_url_opt]
+  /// API Spec as a local file, or `-` for stdin. If provided, this overrides the [Self::api_spec_url_opt]
   #[arg(long = "spec-file")]
   pub local_api_spec_filepath_opt: Option<PathBuf>,
+  /// Shell command whose stdout is the API spec. It is recorded in the crate's project config, so
+  /// that `--regenerate` runs it again
+  #[arg(
+    long = "spec-command",
+    conflicts_with_all = ["local_api_spec_filepath_opt", "api_spec_url_opt"]
+  )]
+  pub spec_command_opt: Option<String>,
   /// Optional library name to override default generated crate name
   #[arg(long = "lib_name")]
   pub lib_name_opt: Option<String>,
@@ -165,23 +316,80 @@ pub struct InnerCli {
   #[arg(long = "authors")]
   pub extra_authors: Option<String>,
   /// The optional output project dir
-  #[arg(long = "output")]
+  #[arg(long = "output", global = true)]
   output_project_dir_opt: Option<PathBuf>,
+  /// How to run the generation tasks after scaffolding
+  #[arg(long = "runner", value_enum, default_value_t = TaskRunners::Native)]
+  pub runner: TaskRunners,
+  /// Export a cargo-make makefile into the crate (always exported for the `cargo-make` runner)
+  #[arg(long = "export-makefile", action = clap::ArgAction::Set, default_value_t = true)]
+  pub export_makefile: bool,
+  /// Optional timeout in seconds for each external process
+  #[arg(long = "timeout")]
+  pub process_timeout_secs_opt: Option<u64>,
+  /// Log more details (repeat for more)
+  #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count, global = true)]
+  pub verbose: u8,
+  /// Log less (repeat for less)
+  #[arg(short = 'q', long = "quiet", action = clap::ArgAction::Count, global = true)]
+  pub quiet: u8,
+  /// Log output format
+  #[arg(long = "log-format", value_enum, default_value_t = LogFormats::Text, global = true)]
+  pub log_format: LogFormats,
+  /// Print a machine-readable report of the run to stdout when it ends
+  #[arg(long = "report", value_enum)]
+  pub report_format_opt: Option<ReportFormats>,
+  /// Write a machine-readable (JSON) report of the run to this file
+  #[arg(long = "report-file")]
+  pub report_file_path_opt: Option<PathBuf>,
+  /// Regenerate an existing crate in the output dir instead of scaffolding a new one
+  #[arg(long = "regenerate")]
+  pub regenerate: bool,
+  /// Generate in a staging dir that is swapped in only once every task succeeded (enabled by default)
+  #[arg(long = "staging", action = clap::ArgAction::Set, default_value_t = true)]
+  pub staging: bool,
+  /// Use only the cached specs and generator jars, without touching the network
+  #[arg(long = "offline", global = true)]
+  pub offline: bool,
+  /// Rewrite the crate's olg.lock instead of checking the generation against it
+  #[arg(long = "update-lock")]
+  pub update_lock: bool,
+  /// Print what a run would do (dirs, files, tasks and the upstream dry run) without touching the disk
+  #[arg(long = "plan")]
+  pub plan: bool,
+  /// Optional project config yaml file (hooks etc.). Defaults to `olg.yaml` in the output dir if present
+  #[arg(long = "config")]
+  pub project_config_path_opt: Option<PathBuf>,
   #[command(subcommand)]
   pub command: Option<SubCommands>,
 }
 impl InnerCli {
   /// Temp dir
   pub const TEMP_DIR_NAME: &'static str = "temp";
+  /// Spec file path that reads the spec from stdin
+  pub const STDIN_SPEC_FILE_PATH: &'static str = "-";
+  /// Check whether the spec is read from stdin
+  pub fn is_spec_from_stdin(&self) -> bool {
+    self
+      .local_api_spec_filepath_opt
+      .as_ref()
+      .is_some_and(|path| path.as_os_str() == Self::STDIN_SPEC_FILE_PATH)
+  }
   /// Get  authors strings
   pub fn get_extra_authors(&self) -> Vec<String> {
     Self::parse_authors_string(&self.extra_authors.clone().unwrap_or_default())
   }
+  /// Get the site or app name, validated by [Cli::new] for generation
+  pub fn get_site_or_api_name(&self) -> String {
+    self.site_or_api_name_opt.clone().unwrap_or_default()
+  }
+  /// Get the app URL, validated by [Cli::new] for generation
+  pub fn get_api_url(&self) -> Url {
+    self.api_url_opt.clone().expect("must get api url")
+  }
   /// Get a default project library name
   fn get_default_lib_name(&self) -> String {
-    let Self {
-      site_or_api_name, ..
-    } = self;
+    let site_or_api_name = self.get_site_or_api_name();
     format!("{site_or_api_name}_openapi_client")
   }
   /// Get a default project spec file name
@@ -229,6 +437,37 @@ impl InnerCli {
       .to_string_lossy()
       .to_string()
   }
+  /// Get the project config from [Self::project_config_path_opt] or the output project dir
+  pub async fn try_get_project_config(&self) -> Result<ProjectConfig, ProjectConfigError> {
+    match self.project_config_path_opt.as_ref() {
+      Some(project_config_path) => ProjectConfig::read_from_path(project_config_path).await,
+      None => {
+        let project_config_path = self.get_output_project_subpath(&Paths::ProjectConfigFile);
+        if project_config_path.is_file() {
+          ProjectConfig::read_from_path(project_config_path).await
+        } else {
+          Ok(ProjectConfig::default())
+        }
+      }
+    }
+  }
+  /// Get the timeout for each external process
+  pub fn get_process_timeout_opt(&self) -> Option<Duration> {
+    self.process_timeout_secs_opt.map(Duration::from_secs)
+  }
+  /// Get the path of the log file for a step in the crate's logs dir
+  pub fn get_log_file_path(
+    &self,
+    step_name: &str,
+  ) -> PathBuf {
+    self
+      .get_output_project_subpath(&Paths::LogsDir)
+      .join(format!("{step_name}.log"))
+  }
+  /// Check whether the makefile should be written into the crate
+  pub fn should_export_makefile(&self) -> bool {
+    self.export_makefile || self.runner == TaskRunners::CargoMake
+  }
   /// Parse  an authors string
   pub fn parse_authors_string(s: &str) -> Vec<String> {
     s.split(";")
@@ -238,16 +477,31 @@ impl InnerCli {
   }
   /// Get spec file name as specified by [Self::api_spec_url]
   pub fn try_get_spec_file_name(&self) -> Result<String, ParameterError> {
-    if let Some(local_api_spec_filepath) = self.local_api_spec_filepath_opt.as_ref() {
-      Ok(local_api_spec_filepath.to_string_lossy().to_string())
-    } else {
-      let api_spec_url = self.api_spec_url_opt.clone().expect("must get spec url");
-      parameters::try_file_name_from_path_url(&api_spec_url).map(|mut s| {
+    if self.is_spec_from_stdin() {
+      Ok(self.get_default_spec_file_name())
+    } else if let Some(local_api_spec_filepath) = self.local_api_spec_filepath_opt.as_ref() {
+      Ok(
+        local_api_spec_filepath
+          .file_name()
+          .map(|file_name| file_name.to_string_lossy().to_string())
+          .unwrap_or_else(|| self.get_default_spec_file_name()),
+      )
+    } else if let Some(api_spec_url) = self.api_spec_url_opt.as_ref() {
+      if let Some(git_spec_source) = GitSpecSource::try_from_url(api_spec_url)? {
+        return git_spec_source
+          .file_path
+          .file_name()
+          .map(|file_name| file_name.to_string_lossy().to_string())
+          .ok_or(ParameterError::InvalidGitSpecUrl);
+      }
+      parameters::try_file_name_from_path_url(api_spec_url).map(|mut s| {
         if s.is_empty() {
           s.push_str(&self.get_default_spec_file_name())
         }
         s
       })
+    } else {
+      Ok(self.get_default_spec_file_name())
     }
   }
 }
@@ -261,16 +515,28 @@ pub enum Paths {
   #[error(".git dir")]
   #[strum(props(path = ".git"))]
   GitDir,
+  #[error("olg.lock lockfile")]
+  #[strum(props(path = "olg.lock"))]
+  LockFile,
   #[error("Cargo Make make file")]
   #[strum(props(path = "Makefile.toml"))]
   CargoMakefile,
   #[error("Cargo.toml file")]
   #[strum(props(path = "Cargo.toml"))]
   CargoTomlFile,
+  #[error("CHANGELOG.md file")]
+  #[strum(props(path = "CHANGELOG.md"))]
+  ChangelogMdFile,
+  #[error("olg.yaml project config file")]
+  #[strum(props(path = "olg.yaml"))]
+  ProjectConfigFile,
   #[error("README.md file")]
   #[strum(props(path = "README.md"))]
   ReadmeMdFile,
   #[error("temp dir")]
   #[strum(props(path = "temp"))]
   TempDir,
+  #[error("logs dir")]
+  #[strum(props(path = "temp/logs"))]
+  LogsDir,
 }
@@ -0,0 +1,472 @@
+//! Human-readable error diagnostics and stable exit codes
+//!
+//! Errors are shown with their cause chain and, when known, a suggested fix. The process exits
+//! with one of the [ExitCodes], which scripts may rely on.
+use crate::{
+  caches::{Cache, CacheError},
+  cli::CLIError,
+  doctor::DoctorError,
+  generate::{errors::*, makefiles::TaskNames, projects::HookStages, specs::fetches::SpecFetcher},
+};
+use serde::Serialize;
+use std::{
+  error::Error,
+  fmt::{self, Display},
+  io::ErrorKind,
+  process::ExitCode,
+};
+
+/// Stable exit codes
+///
+/// | Code | Meaning                                                      |
+/// |------|--------------------------------------------------------------|
+/// | 0    | Success                                                      |
+/// | 1    | Internal error                                               |
+/// | 2    | Bad arguments or project config                              |
+/// | 3    | The spec is invalid                                          |
+/// | 4    | A required tool is missing                                   |
+/// | 5    | Scaffolding or code generation failed                        |
+/// | 6    | Post-processing (fixes, manifest, readme, hooks) failed      |
+/// | 7    | A file could not be read or written                          |
+/// | 8    | The spec could not be fetched or read                        |
+/// | 130  | Cancelled with Ctrl-C                                        |
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, strum::AsRefStr)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+#[repr(u8)]
+pub enum ExitCodes {
+  Success = 0,
+  Internal = 1,
+  BadArguments = 2,
+  SpecInvalid = 3,
+  ToolMissing = 4,
+  GenerationFailed = 5,
+  PostProcessingFailed = 6,
+  FileSystem = 7,
+  SpecUnavailable = 8,
+  Cancelled = 130,
+}
+impl From<ExitCodes> for ExitCode {
+  fn from(exit_code: ExitCodes) -> Self {
+    ExitCode::from(exit_code as u8)
+  }
+}
+
+/// A user-facing description of an error
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+  pub message: String,
+  pub causes: Vec<String>,
+  #[serde(rename = "suggestion")]
+  pub suggestion_opt: Option<String>,
+  pub exit_code: ExitCodes,
+}
+impl Display for Diagnostic {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "error: {}", self.message)?;
+    for cause in self.causes.iter() {
+      write!(f, "\n  caused by: {}", cause.replace('\n', "\n    "))?;
+    }
+    if let Some(suggestion) = self.suggestion_opt.as_ref() {
+      write!(f, "\n  help: {suggestion}")?;
+    }
+    Ok(())
+  }
+}
+impl From<&CLIError> for Diagnostic {
+  fn from(e: &CLIError) -> Self {
+    let (exit_code, suggestion_opt) = classify_cli_error(e);
+    Self {
+      message: e.to_string(),
+      causes: get_error_causes(e),
+      suggestion_opt,
+      exit_code,
+    }
+  }
+}
+
+/// Get the messages of the sources of an error, outermost first
+pub fn get_error_causes(e: &dyn Error) -> Vec<String> {
+  let mut causes = vec![];
+  let mut source_opt = e.source();
+  while let Some(source) = source_opt {
+    let cause = source.to_string();
+    if causes.last() != Some(&cause) {
+      causes.push(cause);
+    }
+    source_opt = source.source();
+  }
+  causes
+}
+
+/// Format an error with its causes on one line
+pub fn format_error_chain(e: &dyn Error) -> String {
+  std::iter::once(e.to_string())
+    .chain(get_error_causes(e))
+    .collect::<Vec<_>>()
+    .join(": ")
+}
+
+/// A classification with an optional suggestion
+type Classification = (ExitCodes, Option<String>);
+
+/// Suggestion for when a tool is missing
+fn suggest_tool_install(tool: &str) -> String {
+  match tool {
+    "cargo-make" => "Run `olg doctor --install` to install it".to_string(),
+    "openapi-generator-cli" => {
+      "Run `cargo make openapi-cli-bash-install` in the crate, then `olg doctor` to check"
+        .to_string()
+    }
+    _ => format!("Install `{tool}`, then run `olg doctor` to check"),
+  }
+}
+
+/// Classify a [CLIError]
+fn classify_cli_error(e: &CLIError) -> Classification {
+  match e {
+    CLIError::IOError(_) => (ExitCodes::FileSystem, None),
+    CLIError::BatchError(BatchError::Failed { .. }) => (
+      ExitCodes::GenerationFailed,
+      Some("See the log files listed in the summary".to_string()),
+    ),
+    CLIError::BatchError(BatchError::IOError(_)) => (ExitCodes::FileSystem, None),
+    CLIError::BatchError(_) => (
+      ExitCodes::BadArguments,
+      Some("Check the batch manifest".to_string()),
+    ),
+    CLIError::CacheError(e) => classify_cache_error(e),
+    CLIError::Cancelled => (ExitCodes::Cancelled, None),
+    CLIError::CargoConfigError(_) | CLIError::READMEGenerationError(_) => {
+      (ExitCodes::PostProcessingFailed, None)
+    }
+    CLIError::ChangelogGenerationError(ChangelogGenerationError::IOError(_)) => {
+      (ExitCodes::FileSystem, None)
+    }
+    CLIError::ChangelogGenerationError(_) => (ExitCodes::PostProcessingFailed, None),
+    CLIError::CrateScaffoldingError(e) => classify_scaffolding_error(e),
+    CLIError::DoctorError(DoctorError::CrateScaffoldingError(e)) => classify_scaffolding_error(e),
+    CLIError::DoctorError(DoctorError::MissingTools(tools)) => (
+      ExitCodes::ToolMissing,
+      Some(
+        tools
+          .iter()
+          .map(|tool| suggest_tool_install(tool))
+          .collect::<Vec<_>>()
+          .join("\n  help: "),
+      ),
+    ),
+    CLIError::LockError(e) => classify_lock_error(e),
+    CLIError::MakefileGenerationError(_) | CLIError::TestingError(_) => {
+      (ExitCodes::GenerationFailed, None)
+    }
+    CLIError::ParameterError(_) => (
+      ExitCodes::BadArguments,
+      Some("Run `olg --help` to see the expected arguments".to_string()),
+    ),
+    CLIError::PipelineError(e) => classify_pipeline_error(e),
+    CLIError::ProcessError(e) => classify_process_error(e, ExitCodes::GenerationFailed),
+    CLIError::ProjectConfigError(e) => classify_project_config_error(e),
+    CLIError::SerdeJSONError(_) | CLIError::SerdeYAMLError(_) => (ExitCodes::Internal, None),
+    CLIError::SpecError(e) => classify_spec_error(e),
+    CLIError::TransactionError(TransactionError::MissingBackup(_)) => (
+      ExitCodes::BadArguments,
+      Some("Only crates generated with `--staging true` have a backup".to_string()),
+    ),
+    CLIError::TransactionError(TransactionError::MissingCrateDir(_)) => (
+      ExitCodes::BadArguments,
+      Some("Pass the crate dir with `--output`".to_string()),
+    ),
+    CLIError::TransactionError(TransactionError::IOError(_)) => (ExitCodes::FileSystem, None),
+    CLIError::TransactionError(TransactionError::CargoConfigError(_)) => (
+      ExitCodes::GenerationFailed,
+      Some("Check the manifest of the enclosing cargo workspace".to_string()),
+    ),
+    CLIError::VersionError(VersionError::IOError(_)) => (ExitCodes::FileSystem, None),
+    CLIError::VersionError(_) => (ExitCodes::PostProcessingFailed, None),
+    CLIError::YAMLGenerationError(YAMLGenerationError::IOError(_)) => (
+      ExitCodes::SpecUnavailable,
+      Some("Check that the `--spec-file` path exists and is readable".to_string()),
+    ),
+    CLIError::YAMLGenerationError(YAMLGenerationError::SpecError(e)) => classify_spec_error(e),
+    CLIError::YAMLGenerationError(YAMLGenerationError::SpecCommandFailed(e)) => {
+      classify_process_error(e, ExitCodes::SpecUnavailable)
+    }
+    CLIError::YAMLGenerationError(YAMLGenerationError::EmptySpec(_)) => (
+      ExitCodes::SpecUnavailable,
+      Some("Check that the spec is piped to stdin or printed by the spec command".to_string()),
+    ),
+    CLIError::YAMLGenerationError(_) => (ExitCodes::GenerationFailed, None),
+  }
+}
+
+/// Classify a [CrateScaffoldingError]
+fn classify_scaffolding_error(e: &CrateScaffoldingError) -> Classification {
+  match e {
+    CrateScaffoldingError::NonEmptyTargetDir(_) => (
+      ExitCodes::BadArguments,
+      Some("Pass an empty or missing directory with `--output`".to_string()),
+    ),
+    CrateScaffoldingError::CargoConfigError(_) => (
+      ExitCodes::GenerationFailed,
+      Some("Check the manifest of the enclosing cargo workspace".to_string()),
+    ),
+    CrateScaffoldingError::IOError(_) | CrateScaffoldingError::MissingCrateDir(_) => {
+      (ExitCodes::FileSystem, None)
+    }
+    CrateScaffoldingError::CargoMakeInstallFailed { .. } => (
+      ExitCodes::ToolMissing,
+      Some("Install it manually with `cargo install cargo-make`".to_string()),
+    ),
+    CrateScaffoldingError::CargoInitFailed { source, .. } => {
+      classify_process_error(source, ExitCodes::GenerationFailed)
+    }
+    CrateScaffoldingError::ProcessError(e) => {
+      classify_process_error(e, ExitCodes::GenerationFailed)
+    }
+    CrateScaffoldingError::YAMLGenerationError(_)
+    | CrateScaffoldingError::CargoMakeTaskFailed(_) => (ExitCodes::GenerationFailed, None),
+  }
+}
+
+/// Classify a [PipelineError]
+fn classify_pipeline_error(e: &PipelineError) -> Classification {
+  match e {
+    PipelineError::MissingTool(tool) => (ExitCodes::ToolMissing, Some(suggest_tool_install(tool))),
+    PipelineError::TaskFailed { task_name, source } => {
+      let default_exit_code = get_task_exit_code(*task_name);
+      match classify_pipeline_error(source) {
+        classification @ (ExitCodes::ToolMissing | ExitCodes::Cancelled, _) => classification,
+        (_, suggestion_opt) => (default_exit_code, suggestion_opt),
+      }
+    }
+    PipelineError::ProcessError(e) => classify_process_error(e, ExitCodes::GenerationFailed),
+    PipelineError::ProjectConfigError(e) => classify_project_config_error(e),
+    PipelineError::ParameterError(_) => (ExitCodes::BadArguments, None),
+    PipelineError::SpecError(e) => classify_spec_error(e),
+    PipelineError::MissingTask(_)
+    | PipelineError::MissingTaskArgs(_)
+    | PipelineError::UnknownTask(_) => (ExitCodes::BadArguments, None),
+    PipelineError::UnsupportedTask(task_name) => (
+      ExitCodes::BadArguments,
+      Some(format!(
+        "Use `--runner cargo-make` or run `cargo make {}` in the crate",
+        task_name.as_ref()
+      )),
+    ),
+    PipelineError::IOError(_) => (ExitCodes::FileSystem, None),
+    PipelineError::CargoConfigError(_) | PipelineError::READMEGenerationError(_) => {
+      (ExitCodes::PostProcessingFailed, None)
+    }
+    PipelineError::MakefileGenerationError(_) => (ExitCodes::GenerationFailed, None),
+    PipelineError::LockError(e) => classify_lock_error(e),
+  }
+}
+
+/// Get the exit code for a failed task
+fn get_task_exit_code(task_name: TaskNames) -> ExitCodes {
+  match task_name {
+    TaskNames::SpecPrepare | TaskNames::SpecValidate => ExitCodes::SpecInvalid,
+    TaskNames::SpecDownload | TaskNames::SpecDownloadDefault => ExitCodes::SpecUnavailable,
+    TaskNames::OpenapiCliCheck | TaskNames::OpenapiCliBashInstall => ExitCodes::ToolMissing,
+    TaskNames::CargoFixGenerated
+    | TaskNames::GenerateAll
+    | TaskNames::HookPostGenerate
+    | TaskNames::HookPostFix => ExitCodes::PostProcessingFailed,
+    TaskNames::CrateScaffold
+    | TaskNames::HookPreDownload
+    | TaskNames::HookPostDownload
+    | TaskNames::HookPreGenerate
+    | TaskNames::LibCodeGenerate
+    | TaskNames::LibCodeGenerateDryRun
+    | TaskNames::LockCheck
+    | TaskNames::OutputDirCreate => ExitCodes::GenerationFailed,
+  }
+}
+
+/// Classify a [LockError]
+fn classify_lock_error(e: &LockError) -> Classification {
+  match e {
+    LockError::Mismatch(_) => (
+      ExitCodes::GenerationFailed,
+      Some(
+        "Pass `--update-lock` to accept the changes, or set `lock-mismatch: warn` in the project config"
+          .to_string(),
+      ),
+    ),
+    LockError::IOError(_) => (ExitCodes::FileSystem, None),
+    LockError::SpecError(e) => classify_spec_error(e),
+    LockError::ParameterError(_) => (ExitCodes::BadArguments, None),
+    LockError::SerdeYAMLError(_) | LockError::TomlDeError(_) | LockError::TomlSerError(_) => (
+      ExitCodes::BadArguments,
+      Some("Check or delete the crate's olg.lock".to_string()),
+    ),
+  }
+}
+
+/// Classify a [SpecError]
+fn classify_spec_error(e: &SpecError) -> Classification {
+  match e {
+    SpecError::IOError(_) | SpecError::MissingOriginalSpec(_) => (ExitCodes::FileSystem, None),
+    SpecError::FetchFailed { .. } | SpecError::UnresolvedRef { .. } => (
+      ExitCodes::SpecUnavailable,
+      Some(format!(
+        "Check that the spec and the files and urls it refs are reachable, and the credentials \
+         in `{}`, `{}` and `{}` or the `{}` file",
+        SpecFetcher::TOKEN_ENV,
+        SpecFetcher::USERNAME_ENV,
+        SpecFetcher::PASSWORD_ENV,
+        SpecFetcher::CREDENTIALS_FILE_ENV
+      )),
+    ),
+    SpecError::MissingSecretQuery { env, .. } => (
+      ExitCodes::BadArguments,
+      Some(format!(
+        "Set `{env}` to the value of the query parameter, or run olg with the full `--spec-url`"
+      )),
+    ),
+    SpecError::NotCached(_) => (
+      ExitCodes::SpecUnavailable,
+      Some("Run once without `--offline` to cache it".to_string()),
+    ),
+    SpecError::CacheError(e) => classify_cache_error(e),
+    SpecError::ParameterError(_) => (ExitCodes::BadArguments, None),
+    SpecError::ProcessError(e) => classify_process_error(e, ExitCodes::SpecUnavailable),
+    SpecError::InvalidCredentialsFile { .. } => (
+      ExitCodes::BadArguments,
+      Some(format!(
+        "Check the credentials file named by `{}`",
+        SpecFetcher::CREDENTIALS_FILE_ENV
+      )),
+    ),
+    SpecError::InvalidPathGlob { .. } | SpecError::NoOperationsLeft => (
+      ExitCodes::BadArguments,
+      Some("Check the `spec.filter` section of the project config".to_string()),
+    ),
+    SpecError::InvalidOverlayTarget { .. } | SpecError::UnknownPatchFormat(_) => (
+      ExitCodes::BadArguments,
+      Some(
+        "Check the files listed in the `spec.patches` section of the project config".to_string(),
+      ),
+    ),
+    SpecError::OverlayTargetNotFound { .. } | SpecError::PatchFailed { .. } => (
+      ExitCodes::SpecInvalid,
+      Some("The upstream spec may have been fixed: update or drop the patch".to_string()),
+    ),
+    SpecError::CircularRef(_)
+    | SpecError::InvalidRef(_)
+    | SpecError::NotAMapping(_)
+    | SpecError::SerdeJSONError(_)
+    | SpecError::SerdeYAMLError(_) => (ExitCodes::SpecInvalid, None),
+    SpecError::LintFailed(_) => (
+      ExitCodes::SpecInvalid,
+      Some(
+        "Fix the issues, or lower the severity of their rules in the `lint` section of the project config"
+          .to_string(),
+      ),
+    ),
+  }
+}
+
+/// Classify a [CacheError]
+fn classify_cache_error(e: &CacheError) -> Classification {
+  match e {
+    CacheError::IOError(_) | CacheError::SerdeJSONError(_) => (
+      ExitCodes::FileSystem,
+      Some("Check the cache dir, or run `olg cache prune` to empty it".to_string()),
+    ),
+    CacheError::NoCacheDir => (
+      ExitCodes::BadArguments,
+      Some(format!("Set `{}` to the cache dir to use", Cache::DIR_ENV)),
+    ),
+  }
+}
+
+/// Classify a [ProjectConfigError]
+fn classify_project_config_error(e: &ProjectConfigError) -> Classification {
+  match e {
+    ProjectConfigError::IOError(_) | ProjectConfigError::SerdeYAMLError(_) => (
+      ExitCodes::BadArguments,
+      Some("Check the project config file given with `--config` (or `olg.yaml`)".to_string()),
+    ),
+    ProjectConfigError::HookFailed { stage, source } => {
+      let default_exit_code = match stage {
+        HookStages::PostGenerate | HookStages::PostFix => ExitCodes::PostProcessingFailed,
+        HookStages::PreDownload | HookStages::PostDownload | HookStages::PreGenerate => {
+          ExitCodes::GenerationFailed
+        }
+      };
+      classify_process_error(source, default_exit_code)
+    }
+  }
+}
+
+/// Classify a [ProcessError], falling back to a default exit code
+fn classify_process_error(
+  e: &ProcessError,
+  default_exit_code: ExitCodes,
+) -> Classification {
+  match e {
+    ProcessError::Cancelled { .. } => (ExitCodes::Cancelled, None),
+    ProcessError::IOError(e) if e.kind() == ErrorKind::NotFound => (
+      ExitCodes::ToolMissing,
+      Some("Run `olg doctor` to check the required tools".to_string()),
+    ),
+    ProcessError::IOError(_) => (default_exit_code, None),
+    ProcessError::TimedOut { .. } => (
+      default_exit_code,
+      Some("Raise the limit with `--timeout <SECONDS>`".to_string()),
+    ),
+    ProcessError::Failed { stderr_tail, .. } if stderr_tail.contains("no such command: `make`") => {
+      (
+        ExitCodes::ToolMissing,
+        Some(suggest_tool_install("cargo-make")),
+      )
+    }
+    ProcessError::Failed { .. } => (
+      default_exit_code,
+      Some("See the logs in the crate's `temp/logs` dir for details".to_string()),
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  #[test]
+  fn failed_tasks_map_to_stable_exit_codes() {
+    let failed = |task_name| {
+      CLIError::from(PipelineError::TaskFailed {
+        task_name,
+        source: Box::new(PipelineError::ProcessError(ProcessError::Failed {
+          command_line: "x".to_string(),
+          cwd: PathBuf::from("."),
+          exit_code_opt: Some(1),
+          stderr_tail: "bad".to_string(),
+        })),
+      })
+    };
+    let diagnostic = Diagnostic::from(&failed(TaskNames::SpecValidate));
+    assert_eq!(diagnostic.exit_code, ExitCodes::SpecInvalid);
+    assert_eq!(diagnostic.message, "Task `spec-validate` failed");
+    assert_eq!(diagnostic.causes.len(), 1);
+    assert_eq!(
+      Diagnostic::from(&failed(TaskNames::CargoFixGenerated)).exit_code,
+      ExitCodes::PostProcessingFailed
+    );
+    let missing = CLIError::from(PipelineError::TaskFailed {
+      task_name: TaskNames::LibCodeGenerate,
+      source: Box::new(PipelineError::MissingTool("cargo-make".to_string())),
+    });
+    let diagnostic = Diagnostic::from(&missing);
+    assert_eq!(diagnostic.exit_code, ExitCodes::ToolMissing);
+    assert!(diagnostic
+      .to_string()
+      .contains("help: Run `olg doctor --install`"));
+  }
+}
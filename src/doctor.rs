@@ -0,0 +1,98 @@
+//! Checks for the tools used during generation
+use crate::generate::{
+  crate_scaffolds::{install_cargo_make, CrateScaffoldingError},
+  makefiles::MakefileEnv,
+  utils::find_in_path,
+};
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Doctor errors
+#[derive(Debug, Error)]
+pub enum DoctorError {
+  #[error(transparent)]
+  CrateScaffoldingError(#[from] CrateScaffoldingError),
+  #[error("Missing required tools: {}", .0.join(", "))]
+  MissingTools(Vec<String>),
+}
+
+/// A tool used during generation
+#[derive(Clone, Debug)]
+pub struct ToolCheck {
+  /// Executable name
+  pub tool: &'static str,
+  /// What the tool is needed for
+  pub purpose: &'static str,
+  /// Whether generation fails without it
+  pub is_required: bool,
+  /// Where the tool was found
+  pub path_opt: Option<PathBuf>,
+}
+impl ToolCheck {
+  /// Look a tool up in the `PATH`
+  fn new(
+    tool: &'static str,
+    purpose: &'static str,
+    is_required: bool,
+  ) -> Self {
+    Self {
+      tool,
+      purpose,
+      is_required,
+      path_opt: find_in_path(tool),
+    }
+  }
+}
+
+impl std::fmt::Display for ToolCheck {
+  /// One row of the `olg doctor` table
+  fn fmt(
+    &self,
+    f: &mut std::fmt::Formatter<'_>,
+  ) -> std::fmt::Result {
+    let (state, location) = match (self.path_opt.as_ref(), self.is_required) {
+      (Some(path), _) => ("found", path.display().to_string()),
+      (None, true) => ("missing", format!("needed for {}", self.purpose)),
+      (None, false) => ("missing", format!("optional, needed for {}", self.purpose)),
+    };
+    write!(f, "{state:<9}{:<28}{location}", self.tool)
+  }
+}
+
+/// Check the tools used during generation
+pub fn check_tools() -> Vec<ToolCheck> {
+  vec![
+    ToolCheck::new("cargo", "scaffolding and fixing the crate", true),
+    ToolCheck::new(
+      MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT,
+      "generating the library code",
+      true,
+    ),
+    ToolCheck::new("java", "running the openapi generator", true),
+    ToolCheck::new(
+      "cargo-make",
+      "the `cargo-make` runner and the exported makefile",
+      false,
+    ),
+  ]
+}
+
+/// Print the state of the tools as a table, installing cargo-make if asked to
+pub async fn run_doctor(install: bool) -> Result<(), DoctorError> {
+  let mut missing_tools = vec![];
+  for mut check in check_tools() {
+    if check.path_opt.is_none() && install && check.tool == "cargo-make" {
+      install_cargo_make().await?;
+      check.path_opt = find_in_path(check.tool);
+    }
+    if check.path_opt.is_none() && check.is_required {
+      missing_tools.push(check.tool.to_string());
+    }
+    println!("{check}");
+  }
+  if missing_tools.is_empty() {
+    Ok(())
+  } else {
+    Err(DoctorError::MissingTools(missing_tools))
+  }
+}
@@ -1,4 +1,5 @@
 //! Filesystem operations
+use crate::reports;
 use fs_err::tokio as fs;
 use std::{io::Error as IOError, path::Path};
 
@@ -10,11 +11,41 @@ pub async fn write(
 ) -> Result<(), IOError> {
   let bytes = contents.as_ref().len();
   let pp = path.as_ref().as_os_str();
-  fs::write(&path, contents).await?;
-  let wrote_message = format!(" Wrote {bytes} bytes to {pp:?}");
+  fs::write(&path, &contents).await?;
+  reports::record_file_written(&path, &contents);
+  let wrote_message = format!("Wrote {bytes} bytes to {pp:?}");
   match message {
-    Some(s) => println!("{}: {wrote_message}", s.as_ref()),
-    None => println!("{wrote_message}"),
+    Some(s) => tracing::info!(path = ?pp, bytes, "{}: {wrote_message}", s.as_ref()),
+    None => tracing::info!(path = ?pp, bytes, "{wrote_message}"),
+  }
+  Ok(())
+}
+
+/// Copy a dir recursively, skipping entries with the given file names
+pub async fn copy_dir_all(
+  from: impl AsRef<Path>,
+  to: impl AsRef<Path>,
+  skipped_names: &[&str],
+) -> Result<(), IOError> {
+  let mut pending_dirs = vec![(from.as_ref().to_path_buf(), to.as_ref().to_path_buf())];
+  while let Some((from_dir, to_dir)) = pending_dirs.pop() {
+    fs::create_dir_all(&to_dir).await?;
+    let mut entries = fs::read_dir(&from_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+      if skipped_names.iter().any(|name| entry.file_name() == *name) {
+        continue;
+      }
+      let to_path = to_dir.join(entry.file_name());
+      let file_type = entry.file_type().await?;
+      if file_type.is_dir() {
+        pending_dirs.push((entry.path(), to_path));
+      } else if file_type.is_symlink() {
+        let target = fs::read_link(entry.path()).await?;
+        tokio::fs::symlink(target, to_path).await?;
+      } else {
+        fs::copy(entry.path(), to_path).await?;
+      }
+    }
   }
   Ok(())
 }
@@ -1,22 +1,39 @@
 //! Code generation
+pub mod batches;
 pub mod cargos;
+pub mod changelogs;
 pub mod crate_scaffolds;
+pub mod locks;
 pub mod makefiles;
 pub mod parameters;
+pub mod pipelines;
+pub mod plans;
+pub mod projects;
 pub mod readmes;
+pub mod specs;
+pub mod transactions;
 
 pub mod utils;
+pub mod versions;
 // pub use utils::*;
 pub mod yamls;
 
 pub mod errors {
   use super::*;
+  pub use batches::BatchError;
   pub use cargos::CargoConfigError;
+  pub use changelogs::ChangelogGenerationError;
   pub use crate_scaffolds::CrateScaffoldingError;
+  pub use locks::LockError;
   pub use makefiles::MakefileGenerationError;
   pub use parameters::ParameterError;
+  pub use pipelines::PipelineError;
+  pub use projects::ProjectConfigError;
   pub use readmes::READMEGenerationError;
+  pub use specs::SpecError;
+  pub use transactions::TransactionError;
   pub use utils::ProcessError;
+  pub use versions::VersionError;
   pub use yamls::YAMLGenerationError;
 }
 pub use errors::*;
@@ -0,0 +1,424 @@
+//! Batch generation of many API clients from one manifest
+//!
+//! ```yaml
+//! concurrency: 2
+//! apis:
+//!   - name: pets
+//!     api-url: https://pets.example
+//!     spec-url: https://pets.example/openapi.yaml
+//!   - name: store
+//!     api-url: https://store.example
+//!     spec-file: specs/store.yaml
+//!     output: clients/store
+//!     args: ["--runner", "cargo-make"]
+//! ```
+//!
+//! Paths are relative to the manifest's dir. Each API runs as its own `olg` process, with
+//! `--regenerate` when its output dir already holds a crate. Names must be safe in file names, and
+//! output dirs unique.
+use crate::{
+  cli::Paths,
+  process::{ProcessError, ProcessJob},
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Error as SerdeYAMLError;
+use std::{
+  env,
+  fmt::{self, Display},
+  io::Error as IOError,
+  path::{Component, Path, PathBuf},
+  time::Instant,
+};
+use strum::EnumProperty;
+use thiserror::Error;
+use tracing::{info, warn};
+use url::Url;
+
+/// Batch errors
+#[derive(Debug, Error)]
+pub enum BatchError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error("Batch manifest lists no APIs")]
+  NoAPIs,
+  #[error("Batch manifest lists the API name `{0}` more than once")]
+  DuplicateAPIName(String),
+  #[error("Batch API name `{0}` must only use letters, digits, `-` and `_`")]
+  InvalidAPIName(String),
+  #[error("Batch APIs `{first}` and `{second}` both generate into {output_dir:?}")]
+  DuplicateOutputDir {
+    first: String,
+    second: String,
+    output_dir: PathBuf,
+  },
+  #[error("{} of {} APIs failed: {}", .failed.len(), .total, .failed.join(", "))]
+  Failed { failed: Vec<String>, total: usize },
+}
+
+/// A batch manifest
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatchManifest {
+  /// How many APIs to generate at once
+  #[serde(default, rename = "concurrency")]
+  pub concurrency_opt: Option<usize>,
+  pub apis: Vec<BatchAPI>,
+}
+impl BatchManifest {
+  /// Default number of APIs generated at once
+  pub const DEFAULT_CONCURRENCY: usize = 1;
+
+  /// Read a manifest from a yaml file
+  pub async fn read_from_path(path: impl AsRef<Path>) -> Result<Self, BatchError> {
+    let contents = fs_err::tokio::read_to_string(path.as_ref()).await?;
+    let manifest: Self = serde_yaml::from_str(&contents)?;
+    manifest.validate()?;
+    Ok(manifest)
+  }
+
+  /// Check that the APIs have safe, unique names and generate into different dirs
+  ///
+  /// Names end up in log file paths, and two APIs generating into one dir at once would clash.
+  pub fn validate(&self) -> Result<(), BatchError> {
+    if self.apis.is_empty() {
+      return Err(BatchError::NoAPIs);
+    }
+    for (i, api) in self.apis.iter().enumerate() {
+      let is_valid_name = !api.name.is_empty()
+        && api
+          .name
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+      if !is_valid_name {
+        return Err(BatchError::InvalidAPIName(api.name.to_string()));
+      }
+      if self.apis[..i].iter().any(|other| other.name == api.name) {
+        return Err(BatchError::DuplicateAPIName(api.name.to_string()));
+      }
+      let output_dir = normalize_path(&api.get_output_dir());
+      if let Some(other) = self.apis[..i]
+        .iter()
+        .find(|other| normalize_path(&other.get_output_dir()) == output_dir)
+      {
+        return Err(BatchError::DuplicateOutputDir {
+          first: other.name.to_string(),
+          second: api.name.to_string(),
+          output_dir,
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Resolve `.` and `..` in a path without touching the file system
+fn normalize_path(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir if normalized.file_name().is_some() => {
+        normalized.pop();
+      }
+      component => normalized.push(component),
+    }
+  }
+  normalized
+}
+
+/// An API listed in a [BatchManifest]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatchAPI {
+  pub name: String,
+  pub api_url: Url,
+  #[serde(default, rename = "spec-url")]
+  pub spec_url_opt: Option<Url>,
+  #[serde(default, rename = "spec-file")]
+  pub spec_file_opt: Option<PathBuf>,
+  /// Defaults to the API name
+  #[serde(default, rename = "output")]
+  pub output_opt: Option<PathBuf>,
+  #[serde(default, rename = "lib-name")]
+  pub lib_name_opt: Option<String>,
+  #[serde(default, rename = "authors")]
+  pub authors_opt: Option<String>,
+  #[serde(default, rename = "config")]
+  pub config_opt: Option<PathBuf>,
+  /// Extra `olg` args overriding the defaults
+  #[serde(default)]
+  pub args: Vec<String>,
+}
+impl BatchAPI {
+  /// Get the output dir, relative to the manifest's dir
+  pub fn get_output_dir(&self) -> PathBuf {
+    self
+      .output_opt
+      .clone()
+      .unwrap_or_else(|| PathBuf::from(&self.name))
+  }
+
+  /// Get the `olg` args generating this API from the manifest's dir
+  pub fn get_args(
+    &self,
+    manifest_dir: &Path,
+  ) -> Vec<String> {
+    let output_dir = self.get_output_dir();
+    let mut args = vec![
+      format!("--name={}", self.name),
+      format!("--api-url={}", self.api_url),
+      format!("--output={}", output_dir.to_string_lossy()),
+    ];
+    let optional_args = [
+      ("--spec-url", self.spec_url_opt.as_ref().map(Url::to_string)),
+      (
+        "--spec-file",
+        self
+          .spec_file_opt
+          .as_ref()
+          .map(|p| p.to_string_lossy().to_string()),
+      ),
+      ("--lib_name", self.lib_name_opt.clone()),
+      ("--authors", self.authors_opt.clone()),
+      (
+        "--config",
+        self
+          .config_opt
+          .as_ref()
+          .map(|p| p.to_string_lossy().to_string()),
+      ),
+    ];
+    for (flag, value_opt) in optional_args {
+      if let Some(value) = value_opt {
+        args.push(format!("{flag}={value}"));
+      }
+    }
+    let cargo_toml_path = manifest_dir.join(&output_dir).join(
+      Paths::CargoTomlFile
+        .get_str("path")
+        .expect("must get Cargo.toml"),
+    );
+    if cargo_toml_path.is_file() {
+      args.push("--regenerate".to_string());
+    }
+    args.extend(self.args.iter().cloned());
+    args
+  }
+}
+
+/// The outcome of generating one API
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchOutcome {
+  pub name: String,
+  pub output_dir: PathBuf,
+  pub log_file_path: PathBuf,
+  pub duration_ms: u64,
+  #[serde(rename = "exit_code")]
+  pub exit_code_opt: Option<i32>,
+  #[serde(rename = "error")]
+  pub error_opt: Option<String>,
+}
+impl BatchOutcome {
+  /// Check whether the API was generated
+  pub fn is_success(&self) -> bool {
+    self.error_opt.is_none()
+  }
+}
+
+/// The outcomes of a batch, in manifest order
+#[derive(Clone, Debug, Serialize)]
+pub struct BatchSummary {
+  pub outcomes: Vec<BatchOutcome>,
+}
+impl BatchSummary {
+  /// Get the names of the failed APIs
+  pub fn get_failed_names(&self) -> Vec<String> {
+    self
+      .outcomes
+      .iter()
+      .filter(|outcome| !outcome.is_success())
+      .map(|outcome| outcome.name.to_string())
+      .collect()
+  }
+
+  /// Fail if any API failed
+  pub fn into_result(self) -> Result<(), BatchError> {
+    let failed = self.get_failed_names();
+    if failed.is_empty() {
+      Ok(())
+    } else {
+      Err(BatchError::Failed {
+        failed,
+        total: self.outcomes.len(),
+      })
+    }
+  }
+}
+impl Display for BatchSummary {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    let failed_count = self.get_failed_names().len();
+    writeln!(
+      f,
+      "Batch summary: {} succeeded, {failed_count} failed",
+      self.outcomes.len() - failed_count
+    )?;
+    let name_width = self
+      .outcomes
+      .iter()
+      .map(|o| o.name.len())
+      .max()
+      .unwrap_or(0);
+    for outcome in self.outcomes.iter() {
+      let status = if outcome.is_success() { "ok" } else { "failed" };
+      let seconds = outcome.duration_ms as f64 / 1000.0;
+      write!(
+        f,
+        "  {status:<6}  {:<name_width$}  {seconds:>7.1}s  {:?}",
+        outcome.name, outcome.output_dir
+      )?;
+      if let Some(exit_code) = outcome.exit_code_opt.filter(|_| !outcome.is_success()) {
+        write!(f, "  exit code {exit_code}")?;
+      }
+      if !outcome.is_success() {
+        write!(f, "  (see {:?})", outcome.log_file_path)?;
+      }
+      writeln!(f)?;
+    }
+    Ok(())
+  }
+}
+
+/// Generate every API of a manifest
+///
+/// See [BatchSummary::into_result] to fail when any API failed.
+pub async fn run_batch(
+  manifest_path: impl AsRef<Path>,
+  concurrency_opt: Option<usize>,
+) -> Result<BatchSummary, BatchError> {
+  let manifest = BatchManifest::read_from_path(manifest_path.as_ref()).await?;
+  let manifest_dir = match manifest_path.as_ref().parent() {
+    Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+    _ => env::current_dir()?,
+  };
+  let concurrency = concurrency_opt
+    .or(manifest.concurrency_opt)
+    .unwrap_or(BatchManifest::DEFAULT_CONCURRENCY)
+    .max(1);
+  let olg_path = env::current_exe()?;
+  info!(
+    "Generating {} APIs, {concurrency} at a time",
+    manifest.apis.len()
+  );
+  let outcomes = stream::iter(manifest.apis.iter())
+    .map(|api| run_batch_api(&olg_path, &manifest_dir, api))
+    .buffered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+  Ok(BatchSummary { outcomes })
+}
+
+/// Generate one API in its own process
+async fn run_batch_api(
+  olg_path: &Path,
+  manifest_dir: &Path,
+  api: &BatchAPI,
+) -> BatchOutcome {
+  let log_file_path = manifest_dir
+    .join(".olg")
+    .join("batch")
+    .join(format!("{}.log", api.name));
+  let started = Instant::now();
+  let result = ProcessJob::new(olg_path.to_string_lossy())
+    .args(api.get_args(manifest_dir))
+    .cwd(manifest_dir)
+    .description_opt(Some(format!("Generating `{}`", api.name)))
+    .log_file_path_opt(Some(log_file_path.clone()))
+    .run()
+    .await;
+  let (exit_code_opt, error_opt) = match result {
+    Ok(output) => (output.exit_code_opt, None),
+    Err(e) => {
+      warn!("Generating `{}` failed", api.name);
+      let exit_code_opt = match &e {
+        ProcessError::Failed { exit_code_opt, .. } => *exit_code_opt,
+        _ => None,
+      };
+      (exit_code_opt, Some(e.to_string()))
+    }
+  };
+  BatchOutcome {
+    name: api.name.to_string(),
+    output_dir: manifest_dir.join(api.get_output_dir()),
+    log_file_path,
+    duration_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+    exit_code_opt,
+    error_opt,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn batch_api_args_include_overrides_last() {
+    let manifest: BatchManifest = serde_yaml::from_str(
+      "
+      apis:
+        - name: store
+          api-url: https://store.example
+          spec-file: specs/store.yaml
+          args: [--runner, cargo-make]
+      ",
+    )
+    .expect("must parse manifest");
+    let args = manifest.apis[0].get_args(Path::new("/nonexistent"));
+    assert_eq!(
+      args,
+      vec![
+        "--name=store",
+        "--api-url=https://store.example/",
+        "--output=store",
+        "--spec-file=specs/store.yaml",
+        "--runner",
+        "cargo-make",
+      ]
+    );
+  }
+
+  #[test]
+  fn batch_manifests_reject_unsafe_names_and_shared_output_dirs() {
+    let parse = |apis: &str| {
+      serde_yaml::from_str::<BatchManifest>(&format!("apis: {apis}")).expect("must parse manifest")
+    };
+    let manifest = parse(
+      "[{ name: pets, api-url: https://pets.example }, \
+       { name: store_v2, api-url: https://store.example, output: clients/store }]",
+    );
+    assert!(manifest.validate().is_ok());
+    for name in ["../x", "a/b", ".hidden", ""] {
+      let manifest = parse(&format!(
+        "[{{ name: '{name}', api-url: https://pets.example }}]"
+      ));
+      assert!(
+        matches!(manifest.validate(), Err(BatchError::InvalidAPIName(_))),
+        "{name}"
+      );
+    }
+    let manifest = parse(
+      "[{ name: pets, api-url: https://pets.example, output: clients/pets }, \
+       { name: store, api-url: https://store.example, output: ./clients/x/../pets/ }]",
+    );
+    assert!(matches!(
+      manifest.validate(),
+      Err(BatchError::DuplicateOutputDir { first, second, output_dir })
+        if first == "pets" && second == "store" && output_dir == Path::new("clients/pets")
+    ));
+  }
+}
@@ -7,10 +7,16 @@ use crate::{
 };
 use cargo_toml::{Edition, Error as CargoTomlError, Product, *};
 use serde::{Deserialize, Serialize};
-use std::io::Error as IOError;
+use std::{
+  collections::BTreeMap,
+  io::Error as IOError,
+  path::{Path, PathBuf},
+};
 use strum::EnumProperty;
 use thiserror::Error;
 use toml::ser::Error as TomlSerError;
+use toml_edit::{Array, Document, TomlError as TomlEditError};
+use tracing::info;
 
 /// Errors
 #[derive(Error, Debug)]
@@ -23,6 +29,10 @@ pub enum CargoConfigError {
   UpdateRustEditionError(Edition),
   #[error(transparent)]
   IOError(#[from] IOError),
+  #[error(transparent)]
+  TomlEditError(#[from] TomlEditError),
+  #[error("Workspace manifest at {0:?} has a malformed `workspace.members`")]
+  MalformedWorkspaceMembers(PathBuf),
 }
 
 /// Cargo toml modifier
@@ -46,7 +56,7 @@ impl CargoConfigurator {
   pub fn new(cli: &Cli) -> Result<Self, CargoConfigError> {
     let mut generation_authors = InnerCli::parse_authors_string(env!("CARGO_PKG_AUTHORS"));
     generation_authors.extend(cli.get_extra_authors().drain(0..));
-    let original_api_name = cli.inner_cli.site_or_api_name.to_string();
+    let original_api_name = cli.get_site_or_api_name();
     let this_crate_name = utils::get_this_crate_name().to_string();
     let this_crate_ver = utils::get_this_crate_ver().to_string();
     let generation_timestamp_string = cli.get_generation_timestamp_string();
@@ -105,9 +115,19 @@ impl CargoConfigurator {
 
   /// Update a cargo.toml file **AFTER** cargo check --edition
   pub async fn update_cargo_manifest_post_fix_edition(&self) -> Result<(), CargoConfigError> {
-    let cargo_toml_path = Paths::CargoTomlFile
-      .get_str("path")
-      .expect("must get Cargo.toml path");
+    self.update_cargo_manifest_post_fix_edition_in(".").await
+  }
+
+  /// Update a cargo.toml file in the given crate dir **AFTER** cargo check --edition
+  pub async fn update_cargo_manifest_post_fix_edition_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+  ) -> Result<(), CargoConfigError> {
+    let cargo_toml_path = &crate_dir.as_ref().join(
+      Paths::CargoTomlFile
+        .get_str("path")
+        .expect("must get Cargo.toml path"),
+    );
     let cargo_manifest = &mut Manifest::<String>::from_path_with_metadata(cargo_toml_path)?;
     let Manifest { package, lib, .. } = cargo_manifest;
     let p = package.get_or_insert_with(Default::default);
@@ -129,10 +149,20 @@ impl CargoConfigurator {
 
   /// Update a cargo.toml file **AFTER** code generation
   pub async fn update_cargo_manifest_post_generation(&self) -> Result<(), CargoConfigError> {
+    self.update_cargo_manifest_post_generation_in(".").await
+  }
+
+  /// Update a cargo.toml file in the given crate dir **AFTER** code generation
+  pub async fn update_cargo_manifest_post_generation_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+  ) -> Result<(), CargoConfigError> {
     // dbg!(self);
-    let cargo_toml_path = Paths::CargoTomlFile
-      .get_str("path")
-      .expect("must get Cargo.toml path");
+    let cargo_toml_path = &crate_dir.as_ref().join(
+      Paths::CargoTomlFile
+        .get_str("path")
+        .expect("must get Cargo.toml path"),
+    );
     let cargo_manifest = &mut Manifest::<String>::from_path_with_metadata(cargo_toml_path)?;
     let Manifest {
       package,
@@ -152,7 +182,7 @@ impl CargoConfigurator {
       ));
     p.keywords
       .get_mut()?
-      .extend(vv![strings self.original_api_name.as_str(), "OpenAPI", "web",].into_iter());
+      .extend(vv![strings self.original_api_name.as_str(), "OpenAPI", "web",]);
     p.categories_mut()
       .extend(vv![strings "web-programming", "api-bindings", "authentication", ]);
     let mut this_crate_dependency: Dependency = Dependency::Detailed(DependencyDetail {
@@ -171,19 +201,35 @@ impl CargoConfigurator {
               .path
               .replace(generator_crate_local_path.to_string_lossy().to_string());
           }
-          None => match generator_crate_repo_url_opt {
-            Some(generator_crate_repo_url) => {
+          None => {
+            if let Some(generator_crate_repo_url) = generator_crate_repo_url_opt {
               this_crate_dependency
                 .detail_mut()
                 .git
                 .replace(generator_crate_repo_url.as_str().to_string());
             }
-            None => {}
-          },
+          }
         },
+        SubCommands::Batch { .. }
+        | SubCommands::Cache { .. }
+        | SubCommands::Doctor { .. }
+        | SubCommands::Rollback
+        | SubCommands::SpecDiff { .. }
+        | SubCommands::Lint { .. } => {}
       }
     }
     dev_dependencies.insert(self.this_crate_name.to_string(), this_crate_dependency);
+    if let Some(workspace) = CargoWorkspace::find_enclosing(crate_dir.as_ref())? {
+      let mut inherited_names = workspace.inherit_dependencies(&mut cargo_manifest.dependencies);
+      inherited_names.extend(workspace.inherit_dependencies(&mut cargo_manifest.dev_dependencies));
+      if !inherited_names.is_empty() {
+        info!(
+          "Inheriting dependencies from the workspace at {:?}: {}",
+          workspace.root_dir,
+          inherited_names.join(", ")
+        );
+      }
+    }
     fs::write(
       cargo_toml_path,
       toml::to_string_pretty(cargo_manifest)?,
@@ -193,3 +239,209 @@ impl CargoConfigurator {
     Ok(())
   }
 }
+
+/// A cargo workspace enclosing a generated crate
+#[derive(Clone, Debug)]
+pub struct CargoWorkspace {
+  /// Dir of the workspace manifest
+  pub root_dir: PathBuf,
+  /// Path of the crate relative to the root dir, with `/` separators
+  pub member_path: String,
+  manifest: Manifest,
+}
+impl CargoWorkspace {
+  /// Find the workspace enclosing a crate dir, if any
+  ///
+  /// Crates listed in the workspace's `exclude` are not considered members.
+  pub fn find_enclosing(crate_dir: impl AsRef<Path>) -> Result<Option<Self>, CargoConfigError> {
+    let crate_dir = std::env::current_dir()?.join(crate_dir);
+    let crate_dir = crate_dir.canonicalize().unwrap_or(crate_dir);
+    let cargo_toml_file_name = Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml");
+    for root_dir in crate_dir.ancestors().skip(1) {
+      let manifest_path = root_dir.join(cargo_toml_file_name);
+      if !manifest_path.is_file() {
+        continue;
+      }
+      let manifest = Manifest::from_slice(&std::fs::read(&manifest_path)?)?;
+      let Some(workspace) = manifest.workspace.as_ref() else {
+        continue;
+      };
+      let member_path = crate_dir
+        .strip_prefix(root_dir)
+        .expect("must be an ancestor")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+      // Excluded paths exclude every crate under them, as in cargo
+      if workspace
+        .exclude
+        .iter()
+        .any(|excluded_path| Path::new(&member_path).starts_with(excluded_path))
+      {
+        return Ok(None);
+      }
+      return Ok(Some(Self {
+        root_dir: root_dir.to_path_buf(),
+        member_path,
+        manifest,
+      }));
+    }
+    Ok(None)
+  }
+
+  /// Check whether the crate is matched by `workspace.members`
+  pub fn is_listed_member(&self) -> bool {
+    self
+      .manifest
+      .workspace
+      .iter()
+      .flat_map(|workspace| workspace.members.iter())
+      .any(|member| self.is_matched_by(member))
+  }
+
+  /// Check whether a `workspace.members` entry matches the crate
+  fn is_matched_by(
+    &self,
+    member: &str,
+  ) -> bool {
+    let member = member.trim_end_matches('/');
+    member == self.member_path
+      || glob::Pattern::new(member).is_ok_and(|pattern| pattern.matches(&self.member_path))
+  }
+
+  /// Add the crate to `workspace.members`, keeping the manifest's formatting
+  pub async fn add_member(&self) -> Result<(), CargoConfigError> {
+    if self.is_listed_member() {
+      return Ok(());
+    }
+    let manifest_path = self.root_dir.join(
+      Paths::CargoTomlFile
+        .get_str("path")
+        .expect("must get Cargo.toml"),
+    );
+    let mut document = fs_err::tokio::read_to_string(&manifest_path)
+      .await?
+      .parse::<Document>()?;
+    let workspace = document["workspace"]
+      .or_insert(toml_edit::table())
+      .as_table_like_mut()
+      .ok_or_else(|| CargoConfigError::MalformedWorkspaceMembers(manifest_path.clone()))?;
+    let members = workspace
+      .entry("members")
+      .or_insert(toml_edit::value(Array::new()))
+      .as_array_mut()
+      .ok_or_else(|| CargoConfigError::MalformedWorkspaceMembers(manifest_path.clone()))?;
+    // `cargo init` may have added the crate since the workspace was found
+    if members.iter().any(|member| {
+      member
+        .as_str()
+        .is_some_and(|member| self.is_matched_by(member))
+    }) {
+      return Ok(());
+    }
+    members.push(self.member_path.as_str());
+    fs::write(
+      &manifest_path,
+      document.to_string(),
+      Some(&format!(
+        "Added `{}` to the workspace members",
+        self.member_path
+      )),
+    )
+    .await?;
+    Ok(())
+  }
+
+  /// Make dependencies defined in `workspace.dependencies` inherit from the workspace
+  ///
+  /// Features and optionality are kept. Inherited dependencies cannot disable default features,
+  /// so those that do are only inherited when the workspace dependency does too. Returns the
+  /// names of the inherited dependencies.
+  pub fn inherit_dependencies(
+    &self,
+    dependencies: &mut BTreeMap<String, Dependency>,
+  ) -> Vec<String> {
+    let Some(workspace) = self.manifest.workspace.as_ref() else {
+      return vec![];
+    };
+    let mut inherited_names = vec![];
+    for (name, dependency) in dependencies.iter_mut() {
+      let Some(workspace_dependency) = workspace.dependencies.get(name) else {
+        continue;
+      };
+      let has_workspace_default_features = match workspace_dependency {
+        Dependency::Detailed(detail) => detail.default_features,
+        _ => true,
+      };
+      let (features, optional) = match dependency {
+        Dependency::Inherited(_) => continue,
+        Dependency::Simple(_) => (vec![], false),
+        Dependency::Detailed(detail) if detail.path.is_some() || detail.git.is_some() => continue,
+        Dependency::Detailed(detail)
+          if !detail.default_features && has_workspace_default_features =>
+        {
+          continue
+        }
+        Dependency::Detailed(detail) => (detail.features.clone(), detail.optional),
+      };
+      *dependency = Dependency::Inherited(InheritedDependencyDetail {
+        features,
+        optional,
+        workspace: true,
+      });
+      inherited_names.push(name.to_string());
+    }
+    inherited_names
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn workspace_members_are_added_and_inherit_dependencies() {
+    let root_dir = std::env::temp_dir().join(format!("olg_workspace_{}", std::process::id()));
+    let crate_dir = root_dir.join("clients").join("pets");
+    fs_err::create_dir_all(&crate_dir).expect("must create dirs");
+    fs_err::write(
+      root_dir.join("Cargo.toml"),
+      "[workspace]\nmembers = [\"core\"] # keep me\nexclude = [\"vendor\"]\n\n[workspace.dependencies]\nserde = \"1\"\ntokio = \"1\"\n",
+    )
+    .expect("must write manifest");
+    let workspace = CargoWorkspace::find_enclosing(&crate_dir)
+      .expect("must read workspace")
+      .expect("must find workspace");
+    assert_eq!(workspace.member_path, "clients/pets");
+    assert!(!workspace.is_listed_member());
+    workspace.add_member().await.expect("must add member");
+    let manifest = fs_err::read_to_string(root_dir.join("Cargo.toml")).expect("must read");
+    assert!(manifest.contains(r#"members = ["core", "clients/pets"] # keep me"#));
+    let tokio_dependency = Dependency::Detailed(DependencyDetail {
+      version: Some("1".to_string()),
+      default_features: false,
+      ..Default::default()
+    });
+    let mut dependencies = BTreeMap::from([
+      ("serde".to_string(), Dependency::Simple("1.0".to_string())),
+      ("tokio".to_string(), tokio_dependency.clone()),
+      ("url".to_string(), Dependency::Simple("2".to_string())),
+    ]);
+    assert_eq!(
+      workspace.inherit_dependencies(&mut dependencies),
+      vec!["serde"]
+    );
+    assert!(matches!(dependencies["serde"], Dependency::Inherited(_)));
+    assert_eq!(dependencies["tokio"], tokio_dependency);
+    assert!(matches!(dependencies["url"], Dependency::Simple(_)));
+    let vendored_crate_dir = root_dir.join("vendor").join("pets");
+    fs_err::create_dir_all(&vendored_crate_dir).expect("must create dirs");
+    assert!(CargoWorkspace::find_enclosing(&vendored_crate_dir)
+      .expect("must read workspace")
+      .is_none());
+    fs_err::remove_dir_all(root_dir).expect("must clean up");
+  }
+}
@@ -0,0 +1,357 @@
+//! CHANGELOG file generation
+//!
+//! Every generation adds a dated entry at the top of the changelog, with the spec version change,
+//! the operations and models added, removed or changed according to the [spec diff](SpecDiff),
+//! and the tool versions used. Entries are wrapped in markers named after the generation
+//! timestamp, so writing the same generation twice replaces its entry.
+
+use crate::{
+  cli::{Cli, Paths},
+  generate::{
+    specs::diffs::{self, SpecChange, SpecChangeKinds, SpecDiff},
+    utils::*,
+    versions,
+  },
+};
+use fs_err::tokio as fs;
+use serde_json::Value;
+use std::{
+  fmt::{self, Display, Formatter},
+  io::Error as IOError,
+  path::Path,
+  string::FromUtf8Error,
+};
+use strum::EnumProperty;
+use thiserror::Error;
+
+/// Errors that can happen with changelog generation
+#[derive(Debug, Error)]
+pub enum ChangelogGenerationError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  FromUtf8Error(#[from] FromUtf8Error),
+}
+
+/// What a changelog item is about
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ChangelogSubjects {
+  Operation,
+  Model,
+}
+
+/// The changes to one operation or model
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ChangelogItem {
+  subject: ChangelogSubjects,
+  /// Like `GET /pets` or `Pet`
+  name: String,
+  /// The kind and description of each change
+  changes: Vec<(SpecChangeKinds, String)>,
+}
+impl ChangelogItem {
+  /// Group the changes of a spec diff by operation and model
+  fn from_spec_diff(spec_diff: &SpecDiff) -> Vec<Self> {
+    let mut items: Vec<Self> = vec![];
+    for SpecChange {
+      kind,
+      location,
+      description,
+      ..
+    } in &spec_diff.changes
+    {
+      let (subject, name, detail_opt) = match location.strip_prefix("schemas.") {
+        Some(rest) => match rest.split_once('.') {
+          Some((name, property)) => (ChangelogSubjects::Model, name, Some(property)),
+          None => (ChangelogSubjects::Model, rest, None),
+        },
+        None => {
+          let end = location
+            .match_indices(' ')
+            .nth(1)
+            .map_or(location.len(), |(i, _)| i);
+          let detail = location[end..].trim();
+          let detail_opt = (!detail.is_empty()).then_some(detail);
+          (ChangelogSubjects::Operation, &location[..end], detail_opt)
+        }
+      };
+      let change = match detail_opt {
+        Some(detail) => format!("{detail}: {description}"),
+        None => description.clone(),
+      };
+      match items
+        .iter_mut()
+        .find(|item| item.subject == subject && item.name == name)
+      {
+        Some(item) => item.changes.push((*kind, change)),
+        None => items.push(Self {
+          subject,
+          name: name.to_string(),
+          changes: vec![(*kind, change)],
+        }),
+      }
+    }
+    items
+  }
+
+  /// Get the line of the item
+  fn to_line(&self) -> String {
+    let name = &self.name;
+    match self.changes.as_slice() {
+      [(SpecChangeKinds::Added, _)] => format!("- Added `{name}`"),
+      [(SpecChangeKinds::Removed, _)] => format!("- Removed `{name}`"),
+      changes => changes
+        .iter()
+        .fold(format!("- Changed `{name}`"), |s, (_, change)| {
+          format!("{s}\n  - {change}")
+        }),
+    }
+  }
+}
+
+/// A changelog entry for one generation
+#[derive(Clone, Debug)]
+pub struct ChangelogEntry {
+  /// RFC 3339 generation timestamp, also naming the entry markers
+  pub timestamp: String,
+  pub date: String,
+  pub crate_version_opt: Option<String>,
+  pub previous_spec_version_opt: Option<String>,
+  pub spec_version_opt: Option<String>,
+  pub regenerated: bool,
+  /// None for the first generation, or when there is no previous spec to compare
+  pub spec_diff_opt: Option<SpecDiff>,
+  pub olg_version: String,
+  pub openapi_generator_version: String,
+}
+impl ChangelogEntry {
+  /// Get the name of the entry markers
+  fn get_section_name(&self) -> String {
+    format!("entry:{}", self.timestamp)
+  }
+}
+impl Display for ChangelogEntry {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    match self.crate_version_opt.as_ref() {
+      Some(crate_version) => writeln!(f, "## {crate_version} - {}", self.date)?,
+      None => writeln!(f, "## {}", self.date)?,
+    }
+    writeln!(
+      f,
+      "\nGenerated at {} with olg v{} and openapi-generator {}.\n",
+      self.timestamp, self.olg_version, self.openapi_generator_version
+    )?;
+    let unknown = "unknown".to_string();
+    let spec_version = self.spec_version_opt.as_ref().unwrap_or(&unknown);
+    let Some(spec_diff) = self.spec_diff_opt.as_ref() else {
+      return match self.regenerated {
+        true => write!(
+          f,
+          "Regenerated from spec version {spec_version}, with no previous spec to compare."
+        ),
+        false => write!(f, "Initial generation from spec version {spec_version}."),
+      };
+    };
+    match self.previous_spec_version_opt.as_ref() {
+      Some(previous_spec_version) if previous_spec_version != spec_version => write!(
+        f,
+        "Spec version changed from {previous_spec_version} to {spec_version}."
+      )?,
+      _ => write!(f, "Spec version {spec_version}.")?,
+    }
+    let items = ChangelogItem::from_spec_diff(spec_diff);
+    if items.is_empty() {
+      return write!(f, "\n\n{spec_diff}.");
+    }
+    for (subject, title) in [
+      (ChangelogSubjects::Operation, "Operations"),
+      (ChangelogSubjects::Model, "Models"),
+    ] {
+      let lines = items
+        .iter()
+        .filter(|item| item.subject == subject)
+        .map(ChangelogItem::to_line)
+        .collect::<Vec<_>>();
+      if !lines.is_empty() {
+        write!(f, "\n\n### {title}\n\n{}", lines.join("\n"))?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Changelog generation
+#[derive(Debug)]
+pub struct ChangelogGenerator {
+  entry: ChangelogEntry,
+}
+impl ChangelogGenerator {
+  /// Title at the top of the changelog
+  const TITLE: &'static str = "# Changelog";
+
+  /// Instantiate for the generation of the crate, looking up its versions
+  pub async fn new(
+    cli: &Cli,
+    previous_spec_opt: Option<&Value>,
+    spec_diff_opt: Option<&SpecDiff>,
+  ) -> Self {
+    let get_spec_version = |spec: &Value| {
+      spec
+        .pointer("/info/version")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+    };
+    let spec_version_opt = diffs::read_crate_spec(cli)
+      .await
+      .as_ref()
+      .and_then(get_spec_version);
+    Self {
+      entry: ChangelogEntry {
+        timestamp: cli.get_generation_timestamp_string(),
+        date: cli.generation_timestamp.format("%Y-%m-%d").to_string(),
+        crate_version_opt: versions::read_crate_version(cli)
+          .await
+          .map(|version| version.to_string()),
+        previous_spec_version_opt: previous_spec_opt.and_then(get_spec_version),
+        spec_version_opt,
+        regenerated: cli.regenerate,
+        spec_diff_opt: spec_diff_opt.cloned(),
+        olg_version: get_this_crate_ver().to_string(),
+        openapi_generator_version: get_openapi_generator_version(
+          cli.get_output_project_dir(),
+          cli.offline,
+        )
+        .await,
+      },
+    }
+  }
+
+  /// Add or replace the entry in changelog contents, keeping the newest entry first
+  pub fn update_changelog_contents(
+    &self,
+    contents: &str,
+  ) -> String {
+    let entries = contents.trim_start();
+    let entries = entries.strip_prefix(Self::TITLE).unwrap_or(entries);
+    let entries = replace_marked_section(
+      entries,
+      &self.entry.get_section_name(),
+      &self.entry.to_string(),
+      true,
+    );
+    format!("{}\n\n{}", Self::TITLE, entries.trim_start())
+  }
+
+  /// Write out to the changelog file in the given crate dir
+  pub async fn update_changelog_md_file_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+  ) -> Result<(), ChangelogGenerationError> {
+    let changelog_path = &crate_dir.as_ref().join(
+      Paths::ChangelogMdFile
+        .get_str("path")
+        .expect("must get CHANGELOG.md path"),
+    );
+    let contents = match fs::read(&changelog_path).await {
+      Ok(contents) => String::from_utf8(contents)?,
+      Err(_) => String::new(),
+    };
+    crate::fs::write(
+      &changelog_path,
+      self.update_changelog_contents(&contents),
+      Some("CHANGELOG.md"),
+    )
+    .await?;
+    Ok(())
+  }
+}
+
+/// Add an entry for this generation to the changelog of the crate
+pub async fn update_crate_changelog(
+  cli: &Cli,
+  previous_spec_opt: Option<&Value>,
+  spec_diff_opt: Option<&SpecDiff>,
+) -> Result<(), ChangelogGenerationError> {
+  ChangelogGenerator::new(cli, previous_spec_opt, spec_diff_opt)
+    .await
+    .update_changelog_md_file_in(cli.get_output_project_dir())
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn changelog_entries_are_added_once() {
+    let old_spec = json!({
+      "info": { "version": "1.0.0" },
+      "paths": { "/pets": { "get": {}, "delete": {} } },
+      "components": {
+        "schemas": {
+          "Pet": { "type": "object", "properties": { "name": { "type": "string" } } }
+        }
+      },
+    });
+    let new_spec = json!({
+      "info": { "version": "1.1.0" },
+      "paths": {
+        "/pets": {
+          "get": {
+            "parameters": [{ "name": "owner", "in": "query", "required": true }]
+          },
+          "post": {}
+        }
+      },
+      "components": { "schemas": { "Pet": { "type": "object" }, "Owner": { "type": "object" } } },
+    });
+    let generator = ChangelogGenerator {
+      entry: ChangelogEntry {
+        timestamp: "2024-05-01T10:00:00+00:00".to_string(),
+        date: "2024-05-01".to_string(),
+        crate_version_opt: Some("2.0.0".to_string()),
+        previous_spec_version_opt: Some("1.0.0".to_string()),
+        spec_version_opt: Some("1.1.0".to_string()),
+        regenerated: true,
+        spec_diff_opt: Some(SpecDiff::new(&old_spec, &new_spec)),
+        olg_version: "0.1.0".to_string(),
+        openapi_generator_version: "7.0.0".to_string(),
+      },
+    };
+    let previous = "# Changelog\n\n## 1.0.0 - 2024-01-01\n";
+    let contents = generator.update_changelog_contents(previous);
+    assert_eq!(
+      contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n"),
+      trim_lines(
+        "
+        # Changelog
+        <!-- olg:entry:2024-05-01T10:00:00+00:00:start -->
+        ## 2.0.0 - 2024-05-01
+        Generated at 2024-05-01T10:00:00+00:00 with olg v0.1.0 and openapi-generator 7.0.0.
+        Spec version changed from 1.0.0 to 1.1.0.
+        ### Operations
+        - Changed `GET /pets`
+          - parameter query.owner: required parameter added
+        - Removed `DELETE /pets`
+        - Added `POST /pets`
+        ### Models
+        - Changed `Pet`
+          - name: property removed
+        - Added `Owner`
+        <!-- olg:entry:2024-05-01T10:00:00+00:00:end -->
+        ## 1.0.0 - 2024-01-01
+        "
+      ),
+      "{contents}"
+    );
+    assert_eq!(generator.update_changelog_contents(&contents), contents);
+  }
+}
@@ -3,17 +3,19 @@
 use crate::{
   cli::{Cli, Paths, SubCommands},
   generate::{
+    cargos::{CargoConfigError, CargoWorkspace},
     makefiles::TaskNames,
     utils::{run_cargo_job, ProcessError},
     yamls,
   },
+  process::ProcessJob,
 };
 use fs_err::tokio as fs;
 use futures::future::TryFutureExt;
 use std::{io::Error as IOError, path::PathBuf};
 use strum::EnumProperty;
 use thiserror::Error;
-use tokio::process;
+use tracing::info;
 
 /// Crate scaffolding errors
 #[derive(Debug, Error)]
@@ -21,6 +23,8 @@ pub enum CrateScaffoldingError {
   #[error(transparent)]
   IOError(#[from] IOError),
   #[error(transparent)]
+  CargoConfigError(#[from] CargoConfigError),
+  #[error(transparent)]
   ProcessError(#[from] ProcessError),
   #[error(transparent)]
   YAMLGenerationError(#[from] yamls::YAMLGenerationError),
@@ -32,13 +36,17 @@ pub enum CrateScaffoldingError {
   MissingCrateDir(PathBuf),
   #[error(transparent)]
   CargoMakeTaskFailed(#[from] TaskNames),
-  #[error("Cargo init project at `{crate_dir}` failed with `{error_string}`  ")]
+  #[error("Cargo init project at `{crate_dir:?}` failed")]
   CargoInitFailed {
     crate_dir: PathBuf,
-    error_string: String,
+    #[source]
+    source: ProcessError,
+  },
+  #[error("Installing `cargo-make` failed")]
+  CargoMakeInstallFailed {
+    #[source]
+    source: ProcessError,
   },
-  #[error("Installing `cargo-make` failed with `{error_string}`  ")]
-  CargoMakeInstallFailed { error_string: String },
 }
 
 /// Create the test generation folder
@@ -62,7 +70,12 @@ async fn create_crate_folder_and_check_empty(cli: &Cli) -> Result<(), CrateScaff
 }
 
 /// Initialize the crate
-async fn init_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
+///
+/// Workspace members get no version control of their own.
+async fn init_crate(
+  cli: &Cli,
+  workspace_opt: Option<&CargoWorkspace>,
+) -> Result<(), CrateScaffoldingError> {
   let dir_path = &cli.get_output_project_dir();
   // dbg!(cli);
   async { Ok(dir_path.is_dir()) }
@@ -71,31 +84,22 @@ async fn init_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
         Err(CrateScaffoldingError::MissingCrateDir(dir_path.clone()))
       } else {
         let dir_path_string = dir_path.to_string_lossy().to_string();
-        process::Command::new("cargo")
-          .args(&[
-            "init".to_string(),
-            "--lib".to_string(),
-            "--color".to_string(),
-            "always".to_string(),
-            dir_path_string.to_string(),
-          ])
-          .output()
+        let vcs_args = match workspace_opt {
+          Some(_) => vec!["--vcs", "none"],
+          None => vec![],
+        };
+        ProcessJob::new("cargo")
+          .args(["init", "--lib", "--color", "always", &dir_path_string])
+          .args(vcs_args)
+          .log_file_path_opt(Some(cli.get_log_file_path("cargo-init")))
+          .timeout_opt(cli.get_process_timeout_opt())
+          .run()
           .await
-          .map_err(CrateScaffoldingError::from)
-          .and_then(|output| {
-            if output.status.success() {
-              let success_string = String::from_utf8(output.stdout).unwrap_or_default();
-              println!("Initialized crate at `{dir_path_string}` with output  {success_string}");
-              Ok(())
-            } else {
-              let e = CrateScaffoldingError::CargoInitFailed {
-                crate_dir: dir_path.clone(),
-                error_string: format!("{output:#?}"),
-              };
-              eprintln!("{e:?}");
-              Err(e)
-            }
+          .map_err(|source| CrateScaffoldingError::CargoInitFailed {
+            crate_dir: dir_path.clone(),
+            source,
           })
+          .map(|_| info!("Initialized crate at `{dir_path_string}`"))
       }
     })
     .await
@@ -109,19 +113,8 @@ pub async fn install_cargo_make() -> Result<(), CrateScaffoldingError> {
     None,
   )
   .await
-  .map_err(CrateScaffoldingError::from)
-  .and_then(|output| {
-    if output.status.success() {
-      println!("Installed cargo make");
-      Ok(())
-    } else {
-      let e = CrateScaffoldingError::CargoMakeInstallFailed {
-        error_string: format!("{output:#?}"),
-      };
-      eprintln!("{e:?}");
-      Err(e)
-    }
-  })
+  .map_err(|source| CrateScaffoldingError::CargoMakeInstallFailed { source })
+  .map(|_| info!("Installed cargo make"))
 }
 
 /// Do all crate scaffolding jobs
@@ -133,9 +126,19 @@ pub async fn scaffold_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
     create_crate_folder_and_check_empty(cli).await?;
     false
   };
-  init_crate(cli).await?;
+  let workspace_opt = CargoWorkspace::find_enclosing(cli.get_output_project_dir())?;
+  init_crate(cli, workspace_opt.as_ref()).await?;
   setup_tree_in_crate(cli).await?;
-  setup_git_in_crate(cli).await?;
+  match workspace_opt.as_ref() {
+    Some(workspace) => {
+      workspace.add_member().await?;
+      info!(
+        "Scaffolded `{}` as a member of the workspace at {:?}",
+        workspace.member_path, workspace.root_dir
+      );
+    }
+    None => setup_git_in_crate(cli).await?,
+  }
   if is_test {
     yamls::create_testing_spec_file(cli).await?;
   }
@@ -157,6 +160,11 @@ async fn setup_git_in_crate(cli: &Cli) -> Result<(), CrateScaffoldingError> {
     .get_str("path")
     .expect("must get temp dir path");
   let gitignore_path = cli.get_output_project_subpath(&Paths::GitignoreFile);
-  fs::write(&gitignore_path, &format!("\n/{crate_temp_dir_str}")).await?;
+  crate::fs::write(
+    &gitignore_path,
+    &format!("\n/{crate_temp_dir_str}"),
+    Some(".gitignore"),
+  )
+  .await?;
   Ok(())
 }
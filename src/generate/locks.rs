@@ -0,0 +1,387 @@
+//! Lockfiles
+//!
+//! `olg.lock` pins what a crate was generated from: the spec source and the hash of the spec as
+//! downloaded (with the commit it was read at, for git spec sources), the olg and openapi-generator
+//! versions, and the hash of the project config. The
+//! `lock-check` task compares the current generation with it ahead of code generation and fails,
+//! or warns with `lock-mismatch: warn`, when they differ. The lock is written by the first
+//! generation and rewritten with `--update-lock`, which also skips the check.
+use crate::{
+  caches::Cache,
+  cli::{Cli, Paths},
+  generate::{
+    errors::ParameterError,
+    specs::{fetches, SpecDocument, SpecError, SpecPreparer},
+    utils::*,
+  },
+  reports,
+};
+use fs_err::tokio as fs;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Error as SerdeYAMLError;
+use std::{
+  env,
+  fmt::{self, Display, Formatter},
+  io::Error as IOError,
+  path::{Path, PathBuf},
+};
+use strum::EnumProperty;
+use thiserror::Error;
+use toml::{de::Error as TomlDeError, ser::Error as TomlSerError};
+use tracing::info;
+
+/// Lockfile errors
+#[derive(Debug, Error)]
+pub enum LockError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error(transparent)]
+  SpecError(#[from] SpecError),
+  #[error(transparent)]
+  TomlDeError(#[from] TomlDeError),
+  #[error(transparent)]
+  TomlSerError(#[from] TomlSerError),
+  #[error("Generation does not match olg.lock: {}", LockMismatch::join(.0))]
+  Mismatch(Vec<LockMismatch>),
+}
+
+/// What to do when the generation does not match the lock
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockMismatchActions {
+  /// Refuse to generate
+  #[default]
+  Error,
+  /// Record a warning and generate
+  Warn,
+}
+
+/// The contents of `olg.lock`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lock {
+  /// The spec url, redacted, local path, `stdin` or spec command
+  pub spec_source: String,
+  /// Hash of the spec as downloaded or copied, before preparation
+  pub spec_hash: String,
+  pub olg_version: String,
+  pub openapi_generator_version: String,
+  /// Hash of the project config
+  pub config_hash: String,
+  /// The commit the spec was read at, for git spec sources
+  #[serde(
+    default,
+    rename = "spec-commit",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub spec_commit_opt: Option<String>,
+}
+impl Lock {
+  /// Comment at the top of the lockfile
+  const HEADER: &'static str =
+    "# Generated by olg to pin what the crate is generated from. Update it with `--update-lock`.";
+
+  /// List what differs from another lock
+  pub fn get_mismatches(
+    &self,
+    locked: &Self,
+  ) -> Vec<LockMismatch> {
+    [
+      ("spec source", &self.spec_source, &locked.spec_source),
+      ("spec hash", &self.spec_hash, &locked.spec_hash),
+      ("olg version", &self.olg_version, &locked.olg_version),
+      (
+        "openapi-generator version",
+        &self.openapi_generator_version,
+        &locked.openapi_generator_version,
+      ),
+      ("config hash", &self.config_hash, &locked.config_hash),
+    ]
+    .into_iter()
+    .filter(|(_, current, locked)| current != locked)
+    .map(|(field, current, locked)| LockMismatch {
+      field: field.to_string(),
+      current: current.to_string(),
+      locked: locked.to_string(),
+    })
+    .collect()
+  }
+
+  /// Serialize with the header
+  pub fn to_toml_string(&self) -> Result<String, LockError> {
+    Ok(format!("{}\n{}", Self::HEADER, toml::to_string(self)?))
+  }
+}
+
+/// A lock entry that does not match the generation
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LockMismatch {
+  pub field: String,
+  pub current: String,
+  pub locked: String,
+}
+impl LockMismatch {
+  /// Join mismatches in a sentence
+  fn join(mismatches: &[Self]) -> String {
+    mismatches
+      .iter()
+      .map(ToString::to_string)
+      .collect::<Vec<_>>()
+      .join(", ")
+  }
+}
+impl Display for LockMismatch {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    write!(
+      f,
+      "{} is `{}` but locked to `{}`",
+      self.field, self.current, self.locked
+    )
+  }
+}
+
+/// Checks and writes the lockfile of a crate
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Locker {
+  /// The spec file path, relative to the crate dir
+  pub spec_file_path: PathBuf,
+  /// The path of the spec before preparation, relative to the crate dir
+  pub original_spec_file_path: PathBuf,
+  pub spec_source: String,
+  pub config_hash: String,
+  pub on_mismatch: LockMismatchActions,
+}
+impl Locker {
+  /// Env var that skips the check when set to `true`, for the cargo-make task
+  pub const UPDATE_LOCK_ENV: &'static str = "OLG_UPDATE_LOCK";
+
+  /// Instantiate
+  pub fn new(cli: &Cli) -> Result<Self, LockError> {
+    let spec_preparer = SpecPreparer::new(cli)?;
+    let spec_source = match (
+      cli.api_spec_url_opt.as_ref(),
+      cli.local_api_spec_filepath_opt.as_ref(),
+    ) {
+      (Some(api_spec_url), _) => fetches::redact_url(api_spec_url).to_string(),
+      (None, Some(_)) if cli.is_spec_from_stdin() => "stdin".to_string(),
+      (None, Some(local_api_spec_filepath)) => {
+        local_api_spec_filepath.to_string_lossy().to_string()
+      }
+      (None, None) => match cli.get_spec_command_opt() {
+        Some(spec_command) => format!("command: {spec_command}"),
+        None => spec_preparer.spec_file_path.to_string_lossy().to_string(),
+      },
+    };
+    Ok(Self {
+      original_spec_file_path: spec_preparer.get_original_spec_file_path(),
+      spec_file_path: spec_preparer.spec_file_path,
+      spec_source,
+      config_hash: format!(
+        "sha256:{}",
+        reports::sha256_hex(serde_yaml::to_string(&cli.project_config)?)
+      ),
+      on_mismatch: cli.project_config.lock_mismatch,
+    })
+  }
+
+  /// Get the path of the lockfile in a crate dir
+  fn get_lock_file_path(crate_dir: impl AsRef<Path>) -> PathBuf {
+    crate_dir.as_ref().join(
+      Paths::LockFile
+        .get_str("path")
+        .expect("must get olg.lock path"),
+    )
+  }
+
+  /// Get the lock of the current generation
+  pub async fn get_current_lock_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+    offline: bool,
+  ) -> Result<Lock, LockError> {
+    let spec_file_path = crate_dir.as_ref().join(&self.spec_file_path);
+    let spec = SpecDocument::read_from_path(&spec_file_path).await?;
+    let spec_file_path = match spec.value.get(SpecPreparer::ORIGINAL_SPEC_KEY) {
+      Some(_) => crate_dir.as_ref().join(&self.original_spec_file_path),
+      None => spec_file_path,
+    };
+    let spec_contents = fs::read(&spec_file_path).await?;
+    Ok(Lock {
+      spec_source: self.spec_source.clone(),
+      spec_hash: format!("sha256:{}", reports::sha256_hex(spec_contents)),
+      olg_version: get_this_crate_ver().to_string(),
+      openapi_generator_version: get_openapi_generator_version(crate_dir.as_ref(), offline).await,
+      config_hash: self.config_hash.clone(),
+      spec_commit_opt: fetches::read_spec_commit(&spec_file_path).await,
+    })
+  }
+
+  /// Read the lock of a crate dir, if any
+  pub async fn read_lock_in(crate_dir: impl AsRef<Path>) -> Result<Option<Lock>, LockError> {
+    let lock_file_path = Self::get_lock_file_path(crate_dir);
+    if !lock_file_path.is_file() {
+      return Ok(None);
+    }
+    let contents = fs::read_to_string(&lock_file_path).await?;
+    Ok(Some(toml::from_str(&contents)?))
+  }
+
+  /// Check the lock of the current dir, unless [Self::UPDATE_LOCK_ENV] is `true`
+  pub async fn check_lock(&self) -> Result<(), LockError> {
+    let update_lock = env::var(Self::UPDATE_LOCK_ENV).is_ok_and(|value| value == "true");
+    self
+      .check_lock_in(".", update_lock, Cache::is_offline_from_env())
+      .await
+  }
+
+  /// Check that the generation in a crate dir matches its lock
+  pub async fn check_lock_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+    update_lock: bool,
+    offline: bool,
+  ) -> Result<(), LockError> {
+    if update_lock {
+      info!("Skipping the lock check, the lock will be updated");
+      return Ok(());
+    }
+    let Some(locked) = Self::read_lock_in(crate_dir.as_ref()).await? else {
+      info!("No lock to check, one will be written");
+      return Ok(());
+    };
+    let mismatches = self
+      .get_current_lock_in(crate_dir, offline)
+      .await?
+      .get_mismatches(&locked);
+    match (mismatches.is_empty(), self.on_mismatch) {
+      (true, _) => {
+        info!("Generation matches olg.lock");
+        Ok(())
+      }
+      (false, LockMismatchActions::Error) => Err(LockError::Mismatch(mismatches)),
+      (false, LockMismatchActions::Warn) => {
+        for mismatch in mismatches {
+          reports::record_warning(format!("Generation does not match olg.lock: {mismatch}"));
+        }
+        Ok(())
+      }
+    }
+  }
+
+  /// Write the lock of the current generation in a crate dir
+  pub async fn write_lock_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+    offline: bool,
+  ) -> Result<(), LockError> {
+    let lock = self
+      .get_current_lock_in(crate_dir.as_ref(), offline)
+      .await?;
+    crate::fs::write(
+      Self::get_lock_file_path(crate_dir),
+      lock.to_toml_string()?,
+      Some("Lock"),
+    )
+    .await?;
+    Ok(())
+  }
+}
+
+/// Write the lock of the crate after generation, when it has none or `--update-lock` is passed
+pub async fn update_crate_lock(cli: &Cli) -> Result<(), LockError> {
+  let crate_dir = cli.get_output_project_dir();
+  if !cli.update_lock && Locker::read_lock_in(&crate_dir).await?.is_some() {
+    return Ok(());
+  }
+  Locker::new(cli)?
+    .write_lock_in(crate_dir, cli.offline)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lock_mismatches_are_listed() {
+    let locked = Lock {
+      spec_source: "https://pets.example/openapi.yaml".to_string(),
+      spec_hash: "sha256:abc".to_string(),
+      olg_version: "0.2.0".to_string(),
+      openapi_generator_version: "7.0.0".to_string(),
+      config_hash: "sha256:def".to_string(),
+      spec_commit_opt: None,
+    };
+    let toml_string = locked.to_toml_string().expect("must serialize");
+    assert!(toml_string.contains("\nspec-hash = \"sha256:abc\"\n"));
+    assert_eq!(
+      toml::from_str::<Lock>(&toml_string).ok().as_ref(),
+      Some(&locked)
+    );
+    let current = Lock {
+      spec_hash: "sha256:abd".to_string(),
+      openapi_generator_version: "7.1.0".to_string(),
+      ..locked.clone()
+    };
+    assert!(current.get_mismatches(&current).is_empty());
+    assert_eq!(
+      LockError::Mismatch(current.get_mismatches(&locked)).to_string(),
+      "Generation does not match olg.lock: spec hash is `sha256:abd` but locked to `sha256:abc`, \
+       openapi-generator version is `7.1.0` but locked to `7.0.0`"
+    );
+  }
+
+  #[tokio::test]
+  async fn lock_mismatches_warn_or_fail() {
+    let crate_dir = std::env::temp_dir().join(format!("olg_locks_{}", std::process::id()));
+    fs::create_dir_all(&crate_dir)
+      .await
+      .expect("must create crate dir");
+    fs::write(crate_dir.join("pets.yaml"), "openapi: 3.0.3\n")
+      .await
+      .expect("must write spec");
+    let mut locker = Locker {
+      spec_file_path: PathBuf::from("pets.yaml"),
+      original_spec_file_path: PathBuf::from("pets.original.yaml"),
+      spec_source: "pets.yaml".to_string(),
+      config_hash: "sha256:def".to_string(),
+      on_mismatch: LockMismatchActions::Error,
+    };
+    locker
+      .write_lock_in(&crate_dir, true)
+      .await
+      .expect("must write lock");
+    locker
+      .check_lock_in(&crate_dir, false, true)
+      .await
+      .expect("must match lock");
+    fs::write(crate_dir.join("pets.yaml"), "openapi: 3.1.0\n")
+      .await
+      .expect("must write spec");
+    assert!(matches!(
+      locker.check_lock_in(&crate_dir, false, true).await,
+      Err(LockError::Mismatch(mismatches))
+        if mismatches.len() == 1 && mismatches[0].field == "spec hash"
+    ));
+    locker
+      .check_lock_in(&crate_dir, true, true)
+      .await
+      .expect("must skip check when updating lock");
+    locker.on_mismatch = LockMismatchActions::Warn;
+    let (result, report) =
+      reports::RunReport::record(locker.check_lock_in(&crate_dir, false, true)).await;
+    result.expect("must only warn");
+    assert_eq!(report.warnings.len(), 1);
+    assert!(report.warnings[0].starts_with("Generation does not match olg.lock: spec hash is"));
+    fs::remove_dir_all(&crate_dir)
+      .await
+      .expect("must remove crate dir");
+  }
+}
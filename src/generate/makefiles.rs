@@ -2,22 +2,28 @@
 use crate::{
   cli::{Cli, InnerCli, Paths, SubCommands},
   fs,
-  generate::{errors::*, utils::*, *},
-  vv,
+  generate::{errors::*, projects::HookStages, specs::fetches, utils::*, *},
+  reports, vv,
 };
 use cargo_make::types::*;
 use cli as cargo_make;
 use futures::TryFutureExt;
-use once_cell::sync::Lazy;
+use heck::{ToShoutySnakeCase, ToSnakeCase};
 use proc_macro2::Span;
 // use quote::quote;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Error as SerdeYAMLError;
-use std::{collections::HashMap, io::Error as IOError};
-use strum::EnumProperty;
+use std::{
+  collections::{BTreeMap, HashMap},
+  io::Error as IOError,
+  path::{Path, PathBuf},
+};
+use strum::{EnumProperty, IntoEnumIterator};
 use syn::Ident;
 use thiserror::Error;
-use toml::ser::Error as TomlSerError;
+use toml::{de::Error as TomlDeError, ser::Error as TomlSerError, Value};
+use toml_edit::{Document, Item, Table as TomlEditTable, TomlError as TomlEditError};
+use url::Url;
 
 /// The makefile specification
 #[derive(Debug, Deserialize, Serialize)]
@@ -37,44 +43,291 @@ impl TryFrom<&Cli> for MakefileSpec {
         NamedTask::make_lib_code_generator_task(Some(true)),
         NamedTask::make_openapi_cli_check_task(),
         NamedTask::make_openapi_cli_install_task(),
-        NamedTask::make_output_dir_clean_task(),
         NamedTask::make_output_dir_create_task(),
         NamedTask::make_spec_download_task(),
         NamedTask::make_spec_validate_task(),
       ];
-      if cli.inner_cli.api_spec_url_opt.is_some() {
-        named_tasks.push(NamedTask::make_spec_download_default_task());
+      if let Some(api_spec_url) = cli.inner_cli.api_spec_url_opt.as_ref() {
+        named_tasks.push(NamedTask::make_spec_download_default_task(
+          cli,
+          api_spec_url,
+        )?);
       }
-      Ok(Self {
-        env,
-        tasks: HashMap::from_iter(
-          named_tasks
-            .drain(0..)
-            .map(|NamedTask { name, task }| (name, task)),
-        ),
-      })
+      let mut tasks = HashMap::from_iter(
+        named_tasks
+          .drain(0..)
+          .map(|NamedTask { name, task }| (name, task)),
+      );
+      for stage in HookStages::iter() {
+        let hooks = cli.project_config.get_hooks(stage);
+        if !hooks.is_empty() {
+          let NamedTask { name, task } = NamedTask::make_hook_task(stage, hooks);
+          tasks.insert(name, task);
+          Self::wire_hook_task(&mut tasks, stage);
+        }
+      }
+      let NamedTask { name, task } = NamedTask::make_spec_prepare_task(cli)?;
+      tasks.insert(name, task);
+      Self::wire_spec_prepare_task(&mut tasks);
+      let NamedTask { name, task } = NamedTask::make_lock_check_task(cli)?;
+      tasks.insert(name, task);
+      Self::wire_lock_check_task(&mut tasks);
+      Ok(Self { env, tasks })
     })
   }
 }
 impl MakefileSpec {
+  /// Get the env
+  pub fn get_env(&self) -> &MakefileEnv {
+    &self.env
+  }
+
+  /// Get a task by name
+  pub fn get_task(
+    &self,
+    task_name: &TaskNames,
+  ) -> Option<&Task> {
+    self.tasks.get(task_name)
+  }
+
+  /// Add a hook task to the dependencies of the tasks surrounding its stage
+  ///
+  /// Pre-stage hooks become dependencies of the stage task. Post-stage hooks are placed right
+  /// after the stage task in the dependencies of the task that runs next (post-download hooks run
+  /// ahead of code generation).
+  fn wire_hook_task(
+    tasks: &mut HashMap<TaskNames, Task>,
+    stage: HookStages,
+  ) {
+    let hook_task_name = stage.get_task_name();
+    let (dependent_task_names, after_opt) = match stage {
+      HookStages::PreDownload => (
+        vec![TaskNames::SpecDownload, TaskNames::SpecDownloadDefault],
+        None,
+      ),
+      HookStages::PostDownload | HookStages::PreGenerate => {
+        (vec![TaskNames::LibCodeGenerate], None)
+      }
+      HookStages::PostGenerate => (
+        vec![TaskNames::GenerateAll],
+        Some(TaskNames::LibCodeGenerate),
+      ),
+      HookStages::PostFix => (
+        vec![TaskNames::GenerateAll],
+        Some(TaskNames::CargoFixGenerated),
+      ),
+    };
+    for dependent_task_name in dependent_task_names {
+      if let Some(task) = tasks.get_mut(&dependent_task_name) {
+        let dependencies = task.dependencies.get_or_insert_with(Vec::new);
+        let hook_dependency = DependencyIdentifier::Name(hook_task_name.as_ref().to_string());
+        let position = after_opt
+          .and_then(|after| {
+            dependencies.iter().position(|dependency| {
+              matches!(dependency, DependencyIdentifier::Name(name) if name == after.as_ref())
+            })
+          })
+          .map(|i| i + 1)
+          .unwrap_or(dependencies.len());
+        dependencies.insert(position, hook_dependency);
+      }
+    }
+  }
+
+  /// Add the spec preparation task to the dependencies of the tasks reading the spec
+  ///
+  /// It runs after the post-download hooks, ahead of validation and code generation.
+  fn wire_spec_prepare_task(tasks: &mut HashMap<TaskNames, Task>) {
+    let spec_prepare_dependency =
+      DependencyIdentifier::Name(TaskNames::SpecPrepare.as_ref().to_string());
+    for dependent_task_name in [TaskNames::LibCodeGenerate, TaskNames::SpecValidate] {
+      if let Some(task) = tasks.get_mut(&dependent_task_name) {
+        let dependencies = task.dependencies.get_or_insert_with(Vec::new);
+        let position = dependencies
+          .iter()
+          .position(|dependency| {
+            matches!(dependency, DependencyIdentifier::Name(name) if name == TaskNames::HookPostDownload.as_ref())
+          })
+          .map(|i| i + 1)
+          .unwrap_or(0);
+        dependencies.insert(position, spec_prepare_dependency.clone());
+      }
+    }
+  }
+
+  /// Add the lock check task to the dependencies of code generation, after the spec preparation
+  fn wire_lock_check_task(tasks: &mut HashMap<TaskNames, Task>) {
+    if let Some(task) = tasks.get_mut(&TaskNames::LibCodeGenerate) {
+      let dependencies = task.dependencies.get_or_insert_with(Vec::new);
+      let position = dependencies
+        .iter()
+        .position(|dependency| {
+          matches!(dependency, DependencyIdentifier::Name(name) if name == TaskNames::SpecPrepare.as_ref())
+        })
+        .map(|i| i + 1)
+        .unwrap_or(0);
+      dependencies.insert(
+        position,
+        DependencyIdentifier::Name(TaskNames::LockCheck.as_ref().to_string()),
+      );
+    }
+  }
+
   /// Write makefile to makefile
+  ///
+  /// If a makefile already exists, the generated tasks and env entries are merged into it
+  /// (see [Self::merge_into_document]) so that user-defined tasks, config sections and
+  /// comments survive.
   pub async fn write_to_makefile(
     &self,
     cli: &Cli,
   ) -> Result<(), MakefileGenerationError> {
-    async { toml::to_string_pretty(self).map_err(MakefileGenerationError::from) }
+    let output_dir_path = cli.get_output_project_dir();
+    let output_file_name = MakefileEnv::MAKEFILE_NAME;
+    let output_file_path = output_dir_path.join(output_file_name);
+    async { Self::read_existing_makefile_document(&output_file_path).await }
+      .and_then(|existing_document_opt| async {
+        let (document, conflicts) =
+          self.merge_into_document(existing_document_opt.unwrap_or_default())?;
+        for conflict in conflicts.iter() {
+          reports::record_warning(conflict.to_string());
+        }
+        Ok(document.to_string())
+      })
       .and_then(|toml_string| async {
-        let output_dir_path = cli.get_output_project_dir();
-        let output_file_name = MakefileEnv::MAKEFILE_NAME;
-        let output_file_path = output_dir_path.join(output_file_name);
         fs::write(&output_file_path, toml_string, Some("Makefile")).await?;
-        // println!("Wrote makefile to `{output_file_path:?}`");
         Ok(())
       })
       .await
   }
+
+  /// Read an existing makefile as a [Document] if there is one
+  async fn read_existing_makefile_document(
+    path: &Path
+  ) -> Result<Option<Document>, MakefileGenerationError> {
+    if path.is_file() {
+      let contents = fs_err::tokio::read_to_string(path).await?;
+      Ok(Some(contents.parse()?))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Merge this specification into an existing makefile [Document]
+  ///
+  /// - Tasks named in [TaskNames] are regenerated, unless the user defined a task of the same
+  ///   name, which is kept. Generated tasks that are no longer generated are removed.
+  /// - Generated env entries are overwritten; every other task, env entry, section and comment
+  ///   is kept.
+  ///
+  /// Returns the merged document along with the user-defined tasks that were kept instead of a
+  /// generated task of the same name.
+  pub fn merge_into_document(
+    &self,
+    mut existing: Document,
+  ) -> Result<(Document, Vec<MakefileTaskConflict>), MakefileGenerationError> {
+    let mut conflicts = vec![];
+    let generated = toml::to_string_pretty(self)?.parse::<Document>()?;
+    let mut next_position = get_max_table_position(existing.as_table()) + 1;
+    let mut get_position = |existing_item_opt: Option<&Item>| {
+      existing_item_opt
+        .and_then(Item::as_table)
+        .and_then(TomlEditTable::position)
+        .unwrap_or_else(|| {
+          next_position += 1;
+          next_position
+        })
+    };
+    for (section_name, generated_item) in generated.iter() {
+      let Some(generated_section) = generated_item.as_table() else {
+        existing[section_name] = generated_item.clone();
+        continue;
+      };
+      if !existing.get(section_name).is_some_and(Item::is_table) {
+        let mut section = generated_section.clone();
+        section.clear();
+        section.set_position(get_position(None));
+        existing.insert(section_name, Item::Table(section));
+      }
+      let existing_section = existing[section_name]
+        .as_table_mut()
+        .expect("must be table");
+      if section_name == "tasks" {
+        for task_name in TaskNames::iter() {
+          let key = task_name.as_ref();
+          let Some(generated_task) = generated_section.get(key) else {
+            continue;
+          };
+          let existing_task_opt = existing_section.get(key);
+          if existing_task_opt.is_some_and(|task| !Self::is_generated_task_item(task)) {
+            conflicts.push(MakefileTaskConflict(task_name));
+            continue;
+          }
+          let position = get_position(existing_task_opt);
+          existing_section.insert(key, with_table_position(generated_task, position));
+        }
+        // Generated tasks that are no longer generated, like `output-dir-clean`, are dropped
+        existing_section.retain(|key, task| {
+          generated_section.contains_key(key) || !Self::is_generated_task_item(task)
+        });
+      } else {
+        for (key, generated_value) in generated_section.iter() {
+          let position = get_position(existing_section.get(key));
+          existing_section.insert(key, with_table_position(generated_value, position));
+        }
+      }
+    }
+    Ok((existing, conflicts))
+  }
+
+  /// Check whether a makefile task item was generated by this crate
+  fn is_generated_task_item(task: &Item) -> bool {
+    task
+      .get("category")
+      .and_then(Item::as_str)
+      .map(|category| category.starts_with(get_this_crate_name()))
+      .unwrap_or(false)
+  }
+}
+
+/// Get the last position of a table and its nested tables in their document
+fn get_max_table_position(table: &TomlEditTable) -> usize {
+  table
+    .iter()
+    .filter_map(|(_, item)| item.as_table())
+    .map(get_max_table_position)
+    .chain(table.position())
+    .max()
+    .unwrap_or(0)
 }
 
+/// Copy an item from another document, placing it and its nested tables at a position
+fn with_table_position(
+  item: &Item,
+  position: usize,
+) -> Item {
+  let mut item = item.clone();
+  let mut pending_tables = item.as_table_mut().into_iter().collect::<Vec<_>>();
+  while let Some(table) = pending_tables.pop() {
+    table.set_position(position);
+    pending_tables.extend(table.iter_mut().filter_map(|(_, item)| item.as_table_mut()));
+  }
+  item
+}
+
+/// A value read from yaml in a rust script task, with the methods awaited on it
+struct RustScriptStep {
+  /// The path of its type in this crate
+  type_path: &'static str,
+  yaml: String,
+  method_calls: &'static [&'static str],
+}
+
+/// A user-defined makefile task that shares its name with a generated task, and is kept
+#[derive(Clone, Copy, Debug, Error, Eq, PartialEq)]
+#[error("User-defined makefile task `{}` shares its name with a generated task, and was kept instead of it", .0.as_ref())]
+pub struct MakefileTaskConflict(pub TaskNames);
+
 /// Makefile generation errors
 #[derive(Error, Debug)]
 pub enum MakefileGenerationError {
@@ -85,13 +338,19 @@ pub enum MakefileGenerationError {
   #[error(transparent)]
   CargoConfigError(#[from] CargoConfigError),
   #[error(transparent)]
+  LockError(#[from] LockError),
+  #[error(transparent)]
   ParameterError(#[from] ParameterError),
   #[error(transparent)]
   READMEGenerationError(#[from] READMEGenerationError),
   #[error(transparent)]
   SerdeYAMLError(#[from] SerdeYAMLError),
   #[error(transparent)]
+  TomlDeError(#[from] TomlDeError),
+  #[error(transparent)]
   TomlSerError(#[from] TomlSerError),
+  #[error(transparent)]
+  TomlEditError(#[from] TomlEditError),
 }
 /// Makefile env
 #[derive(Debug, Deserialize, Serialize)]
@@ -109,7 +368,6 @@ pub struct MakefileEnv {
   pub open_api_generator_cli_script: EnvValue,
   pub open_api_generator_config_file: EnvValue,
   pub open_api_generator_config_path: EnvValue,
-  pub spec_file_download_dir: EnvValue,
   pub spec_file_name: EnvValue,
   pub spec_file_path: EnvValue,
   pub spec_file_url: EnvValue,
@@ -118,18 +376,17 @@ impl TryFrom<&Cli> for MakefileEnv {
   type Error = MakefileGenerationError;
   fn try_from(cli: &Cli) -> Result<Self, Self::Error> {
     let InnerCli {
-      site_or_api_name,
-      api_url,
-      api_spec_url_opt,
-      ..
+      api_spec_url_opt, ..
     } = &cli.inner_cli;
+    let site_or_api_name = cli.get_site_or_api_name();
+    let api_url = cli.get_api_url();
     let lib_name = cli.get_lib_name();
     let spec_file_name = cli.try_get_spec_file_name()?;
     let output_project_dir_string = cli.get_output_project_dir_string();
     let output_project_temp_dir_string = Paths::TempDir.get_str("path").expect("must get temp dir");
     let api_spec_url_string = api_spec_url_opt
       .as_ref()
-      .map(|api_url| api_url.to_string())
+      .map(|api_url| fetches::redact_url(api_url).to_string())
       .unwrap_or_default();
     Ok(Self {
       api_url: EnvValue::Value(api_url.to_string()),
@@ -159,7 +416,6 @@ impl TryFrom<&Cli> for MakefileEnv {
       open_api_generator_config_path: EnvValue::Value(
         "${OPEN_API_GENERATOR_CONFIG_FILE}".to_string(),
       ),
-      spec_file_download_dir: EnvValue::Value("${OUTPUT_TEMP_DIR}/specdl".to_string()),
       spec_file_name: EnvValue::Value(spec_file_name),
       spec_file_path: EnvValue::Value(r#"${SPEC_FILE_NAME}"#.to_string()),
       spec_file_url: EnvValue::Value(api_spec_url_string),
@@ -167,6 +423,61 @@ impl TryFrom<&Cli> for MakefileEnv {
   }
 }
 impl MakefileEnv {
+  /// Resolve env values to strings, expanding `${VAR}` references
+  ///
+  /// Script values (like `OUTPUT_DIR`, which runs `pwd`) resolve to the given output dir.
+  pub fn try_resolve(
+    &self,
+    output_dir: &Path,
+  ) -> Result<BTreeMap<String, String>, MakefileGenerationError> {
+    let table = match Value::try_from(self)? {
+      Value::Table(table) => table,
+      _ => unreachable!("makefile env must serialize to a table"),
+    };
+    let raw = BTreeMap::from_iter(table.into_iter().map(|(key, value)| {
+      let value = match value {
+        Value::String(s) => s,
+        _ => output_dir.to_string_lossy().to_string(),
+      };
+      (key, value)
+    }));
+    raw
+      .iter()
+      .map(|(key, value)| Ok((key.to_string(), Self::expand_value(value, &raw, 0)?)))
+      .collect()
+  }
+
+  /// Expand `${VAR}` references in a value using the given env
+  pub fn expand_value(
+    value: &str,
+    env: &BTreeMap<String, String>,
+    depth: usize,
+  ) -> Result<String, MakefileGenerationError> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+      let end = rest[start..]
+        .find('}')
+        .map(|end| start + end)
+        .ok_or_else(|| MakefileGenerationError::EnvMissingKey(rest.to_string()))?;
+      let key = &rest[start + 2..end];
+      let key_value = env
+        .get(key)
+        .ok_or_else(|| MakefileGenerationError::EnvMissingKey(key.to_string()))?;
+      expanded.push_str(&rest[..start]);
+      if depth < Self::MAX_EXPANSION_DEPTH {
+        expanded.push_str(&Self::expand_value(key_value, env, depth + 1)?);
+      } else {
+        expanded.push_str(key_value);
+      }
+      rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+  }
+
+  /// Maximum depth of nested `${VAR}` references
+  const MAX_EXPANSION_DEPTH: usize = 8;
   /// Default config file name for OpenAPI Generator
   pub const OPEN_API_GENERATOR_CONFIG_FILE: &'static str = "generator_config.yaml";
   /// Default download url for OpenAPI Generator CLI artifact
@@ -178,8 +489,9 @@ impl MakefileEnv {
   /// Default Makefile name
   pub const MAKEFILE_NAME: &'static str = "Makefile.toml";
   /// Default Makefile task category
-  pub const DEFAULT_TASK_CATEGORY: Lazy<String> =
-    Lazy::new(|| format!("{} {}", get_this_crate_name(), get_this_crate_ver_pretty()));
+  pub fn default_task_category() -> String {
+    format!("{} {}", get_this_crate_name(), get_this_crate_ver_pretty())
+  }
 }
 
 /// A named [Task] specification
@@ -190,7 +502,7 @@ pub struct NamedTask {
 }
 impl NamedTask {
   /// Code generator options
-  const CODE_GENERATION_OPTS: Lazy<Vec<String>> = Lazy::new(|| {
+  fn code_generation_opts() -> Vec<String> {
     vv![strings
       "generate",
       "--generator-name", "rust",
@@ -199,11 +511,11 @@ impl NamedTask {
       "--config", "${OPEN_API_GENERATOR_CONFIG_PATH}",
       // "-Dcolor",
     ]
-  });
+  }
 
   /// Get default category
   pub fn default_category() -> Option<String> {
-    Some(MakefileEnv::DEFAULT_TASK_CATEGORY.to_string())
+    Some(MakefileEnv::default_task_category())
   }
 
   /// Makes a task that does cargo fix  
@@ -238,28 +550,21 @@ impl NamedTask {
         description: Some(r#"Setup ${LIB_NAME} project'."#.to_string()),
         dependencies: Some(vv![ as_ref dep_names
           TaskNames::OutputDirCreate,
-          TaskNames::OutputDirClean,
         ]),
         ..Default::default()
       },
     }
   }
 
-  /// Makes a task that does all of the generation steps
-  pub fn make_generate_all_task(cli: &Cli) -> Result<Self, MakefileGenerationError> {
-    // dbg!(cli);
-    let name = TaskNames::GenerateAll;
-    let cargo_configurator = cargos::CargoConfigurator::new(cli)?;
-    let cargo_configurator_yaml = serde_yaml::to_string(&cargo_configurator)?;
-    let readme_generator = readmes::READMEGenerator::new(cli)?;
-    let readme_generator_yaml = serde_yaml::to_string(&readme_generator)?;
-    let this_crate_name = cargo_configurator.this_crate_name.to_string();
-    let this_crate_ident = Ident::new(&this_crate_name, Span::call_site());
-    let this_crate_ver = cargo_configurator.this_crate_ver.to_string();
-    // let cargo_toml_path  = Paths::CargoTomlFile.get_str("path").expect("must get cargo toml path");
+  /// Get the dependency on this crate for rust script tasks
+  ///
+  /// Test generations depend on the generator crate's local path or repo when given.
+  fn get_this_crate_dependency_string(cli: &Cli) -> String {
+    let this_crate_name = get_this_crate_name();
+    let this_crate_ver = get_this_crate_ver();
     let default_crate_dependency_string =
       format!("{this_crate_name} = {{ version = \"{this_crate_ver}\" }}");
-    let this_crate_dependency_string = if let Some(SubCommands::TestGeneration {
+    if let Some(SubCommands::TestGeneration {
       generator_crate_local_path_opt: generator_path_opt,
       generator_crate_repo_url_opt: generator_repo_opt,
       ..
@@ -288,7 +593,38 @@ impl NamedTask {
       }
     } else {
       default_crate_dependency_string
-    };
+    }
+  }
+
+  /// Make the lines of a rust script task that reads values of this crate from yaml and awaits
+  /// methods on them, in order
+  fn make_rust_script_lines(
+    cli: &Cli,
+    steps: &[RustScriptStep],
+  ) -> Vec<String> {
+    let this_crate_dependency_string = Self::get_this_crate_dependency_string(cli);
+    let this_crate_ident = Ident::new(get_this_crate_name(), Span::call_site());
+    let mut yaml_consts = vec![];
+    let mut statements = vec![];
+    for RustScriptStep {
+      type_path,
+      yaml,
+      method_calls,
+    } in steps
+    {
+      let type_name = type_path.rsplit("::").next().unwrap_or(type_path);
+      let const_name = format!("{}_YAML", type_name.to_shouty_snake_case());
+      let var_name = type_name.to_snake_case();
+      yaml_consts.push(format!(
+        "pub const {const_name}: &'static str = r#\"{yaml}\"#;"
+      ));
+      statements.push(format!(
+        "let {var_name}: {this_crate_ident}::{type_path} = serde_yaml::from_str(yaml_specs::{const_name})?;"
+      ));
+      for method_call in method_calls.iter() {
+        statements.push(format!("{var_name}.{method_call}().await?;"));
+      }
+    }
     let mut script_lines = trim_lines_vec(&format!(
       r##"
       //! ```cargo
@@ -297,32 +633,46 @@ impl NamedTask {
       //! serde_yaml = {{ version = "0.9.19" }}
       //! tokio = {{ version = "1.26.0", features = ["full"] }}
       //! ```
+      use {this_crate_ident}::cli::CLIError;
     "##
     ));
-    let mut script_body = trim_lines_vec(&format! {r###"
-      use {this_crate_ident}::{{ 
-        cli::CLIError, 
-        generate::{{
-          cargos::CargoConfigurator,
-          readmes::READMEGenerator 
-        }} 
-      }};
-      mod yaml_specs {{
-        pub const CARGO_CONFIGURATOR_YAML: &'static str = r#"{cargo_configurator_yaml}"#;
-        pub const README_GENERATOR_YAML: &'static str = r#"{readme_generator_yaml}"#;
-      }}
-      #[tokio::main]
-      async fn main() -> Result<(), CLIError> {{
-        let cargo_configurator: CargoConfigurator = serde_yaml::from_str(yaml_specs::CARGO_CONFIGURATOR_YAML)?;
-        cargo_configurator.update_cargo_manifest_post_generation().await?;
-        cargo_configurator.update_cargo_manifest_post_fix_edition().await?;
-        let readme_generator: READMEGenerator = serde_yaml::from_str(yaml_specs::README_GENERATOR_YAML)?;
-        readme_generator.update_readme_md_file().await?;
-        println!("updates complete");
-        Ok(())
-      }}
-    "###});
-    script_lines.extend(script_body.drain(0..));
+    script_lines.push("mod yaml_specs {".to_string());
+    script_lines.extend(yaml_consts);
+    script_lines.push("}".to_string());
+    script_lines.push("#[tokio::main]".to_string());
+    script_lines.push("async fn main() -> Result<(), CLIError> {".to_string());
+    script_lines.extend(statements);
+    script_lines.push("Ok(())".to_string());
+    script_lines.push("}".to_string());
+    script_lines
+  }
+
+  /// Makes a task that does all of the generation steps
+  pub fn make_generate_all_task(cli: &Cli) -> Result<Self, MakefileGenerationError> {
+    // dbg!(cli);
+    let name = TaskNames::GenerateAll;
+    let cargo_configurator = cargos::CargoConfigurator::new(cli)?;
+    let cargo_configurator_yaml = serde_yaml::to_string(&cargo_configurator)?;
+    let readme_generator = readmes::READMEGenerator::new(cli)?;
+    let readme_generator_yaml = serde_yaml::to_string(&readme_generator)?;
+    let script_lines = Self::make_rust_script_lines(
+      cli,
+      &[
+        RustScriptStep {
+          type_path: "generate::cargos::CargoConfigurator",
+          yaml: cargo_configurator_yaml,
+          method_calls: &[
+            "update_cargo_manifest_post_generation",
+            "update_cargo_manifest_post_fix_edition",
+          ],
+        },
+        RustScriptStep {
+          type_path: "generate::readmes::READMEGenerator",
+          yaml: readme_generator_yaml,
+          method_calls: &["update_readme_md_file"],
+        },
+      ],
+    );
     Ok(Self {
       name,
       task: Task {
@@ -342,9 +692,31 @@ impl NamedTask {
     })
   }
 
+  /// Makes a task that runs the project hook commands for a stage
+  pub fn make_hook_task(
+    stage: HookStages,
+    hooks: &[String],
+  ) -> Self {
+    let stage_name = stage.as_ref();
+    let mut script_lines = vv![strings
+      "set -e",
+      format!("export {}={stage_name}", HookStages::ENV_KEY),
+    ];
+    script_lines.extend(hooks.iter().cloned());
+    Self {
+      name: stage.get_task_name(),
+      task: Task {
+        category: Self::default_category(),
+        description: Some(format!("Run ${{LIB_NAME}} project `{stage_name}` hooks")),
+        script: Some(ScriptValue::Text(script_lines)),
+        ..Default::default()
+      },
+    }
+  }
+
   /// Makes a task that generates the code lib from the openapi spec
   pub fn make_lib_code_generator_task(is_dry_run: Option<bool>) -> Self {
-    let mut args = Self::CODE_GENERATION_OPTS.clone();
+    let mut args = Self::code_generation_opts();
     let mut name = TaskNames::LibCodeGenerate;
     if let Some(true) = is_dry_run {
       args.push("--dry-run".to_string());
@@ -491,20 +863,6 @@ impl NamedTask {
     }
   }
 
-  /// Makes a task that cleans a library directory
-  pub fn make_output_dir_clean_task() -> Self {
-    Self {
-      name: TaskNames::OutputDirClean,
-      task: Task {
-        category: Self::default_category(),
-        description: Some(r#"Setup ${LIB_NAME} output dir at ${OUTPUT_DIR}'."#.to_string()),
-        command: Some("rm".to_string()),
-        args: Some(vv![strings "-rf", "${OUTPUT_DIR}/*", ]),
-        ..Default::default()
-      },
-    }
-  }
-
   /// Makes a task that sets up a library directory
   pub fn make_output_dir_create_task() -> Self {
     Self {
@@ -520,19 +878,41 @@ impl NamedTask {
   }
 
   /// Makes a task that downloads default spec if known
-  pub fn make_spec_download_default_task() -> Self {
-    Self {
+  ///
+  /// The url is redacted, credentials and secret query values are read from the env when the
+  /// task runs. Local git repositories are made absolute, as the task runs from the crate dir.
+  pub fn make_spec_download_default_task(
+    cli: &Cli,
+    api_spec_url: &Url,
+  ) -> Result<Self, MakefileGenerationError> {
+    let api_spec_url = match fetches::GitSpecSource::try_from_url(api_spec_url)? {
+      Some(git_spec_source) => git_spec_source.with_absolute_repository().to_url(),
+      None => api_spec_url.clone(),
+    };
+    let spec_downloader = fetches::SpecDownloader {
+      spec_url: fetches::redact_url(&api_spec_url),
+      spec_file_path: PathBuf::from(cli.try_get_spec_file_name()?),
+    };
+    let script_lines = Self::make_rust_script_lines(
+      cli,
+      &[RustScriptStep {
+        type_path: "generate::specs::fetches::SpecDownloader",
+        yaml: serde_yaml::to_string(&spec_downloader)?,
+        method_calls: &["download_spec"],
+      }],
+    );
+    Ok(Self {
       name: TaskNames::SpecDownloadDefault,
       task: Task {
         category: Self::default_category(),
         description: Some(
           r#"Downloads ${API_NAME} Open API specification from '${API_URL}'."#.to_string(),
         ),
-        command: Some("wget".to_string()),
-        args: Some(vv![ strings "${SPEC_FILE_URL}", "-O", "${SPEC_FILE_PATH}", ]),
+        script_runner: Some("@rust".to_string()),
+        script: Some(ScriptValue::Text(script_lines)),
         ..Default::default()
       },
-    }
+    })
   }
   /// Makes a task that downloads spec if known
   pub fn make_spec_download_task() -> Self {
@@ -549,6 +929,50 @@ impl NamedTask {
       },
     }
   }
+  /// Makes a task that prepares the spec for generation
+  pub fn make_spec_prepare_task(cli: &Cli) -> Result<Self, MakefileGenerationError> {
+    let spec_preparer = specs::SpecPreparer::new(cli)?;
+    let script_lines = Self::make_rust_script_lines(
+      cli,
+      &[RustScriptStep {
+        type_path: "generate::specs::SpecPreparer",
+        yaml: serde_yaml::to_string(&spec_preparer)?,
+        method_calls: &["prepare_spec_file"],
+      }],
+    );
+    Ok(Self {
+      name: TaskNames::SpecPrepare,
+      task: Task {
+        category: Self::default_category(),
+        description: Some("Prepare ${API_NAME} Open API specification for generation".to_string()),
+        script_runner: Some("@rust".to_string()),
+        script: Some(ScriptValue::Text(script_lines)),
+        ..Default::default()
+      },
+    })
+  }
+  /// Makes a task that checks the generation against the lockfile
+  pub fn make_lock_check_task(cli: &Cli) -> Result<Self, MakefileGenerationError> {
+    let locker = locks::Locker::new(cli)?;
+    let script_lines = Self::make_rust_script_lines(
+      cli,
+      &[RustScriptStep {
+        type_path: "generate::locks::Locker",
+        yaml: serde_yaml::to_string(&locker)?,
+        method_calls: &["check_lock"],
+      }],
+    );
+    Ok(Self {
+      name: TaskNames::LockCheck,
+      task: Task {
+        category: Self::default_category(),
+        description: Some("Check ${API_NAME} generation against olg.lock".to_string()),
+        script_runner: Some("@rust".to_string()),
+        script: Some(ScriptValue::Text(script_lines)),
+        ..Default::default()
+      },
+    })
+  }
   /// Makes a task that validates the spec
   pub fn make_spec_validate_task() -> Self {
     let name = TaskNames::SpecValidate;
@@ -580,6 +1004,8 @@ impl NamedTask {
   PartialOrd,
   Serialize,
   strum::AsRefStr,
+  strum::EnumIter,
+  strum::EnumString,
 )]
 #[strum(serialize_all = "kebab-case")]
 #[serde(rename_all = "kebab-case")]
@@ -590,22 +1016,159 @@ pub enum TaskNames {
   CrateScaffold,
   #[error("A task that does all of the generation steps ")]
   GenerateAll,
+  #[error("A task that runs the project hooks before the spec is downloaded")]
+  HookPreDownload,
+  #[error("A task that runs the project hooks after the spec is downloaded")]
+  HookPostDownload,
+  #[error("A task that runs the project hooks before the code lib is generated")]
+  HookPreGenerate,
+  #[error("A task that runs the project hooks after the code lib is generated")]
+  HookPostGenerate,
+  #[error("A task that runs the project hooks after the generated code is fixed")]
+  HookPostFix,
   #[error("A task that generates the code lib from the openapi spec")]
   LibCodeGenerate,
   #[error("A task that does a DRY RUN of generating the code lib from the openapi spec")]
   LibCodeGenerateDryRun,
+  #[error("A task that checks the generation against the lockfile")]
+  LockCheck,
   #[error("A task that checks openapi-generator cli artifact")]
   OpenapiCliCheck,
   #[error("A task that installs openapi-generator cli artifact")]
   OpenapiCliBashInstall,
-  #[error("A task that cleans a library directory")]
-  OutputDirClean,
   #[error("A task that sets up a library directory")]
   OutputDirCreate,
   #[error("A task that downloads default spec if known")]
   SpecDownloadDefault,
   #[error("A task that downloads spec if known")]
   SpecDownload,
+  #[error("A task that prepares the spec for generation")]
+  SpecPrepare,
   #[error("A task that validates the spec")]
   SpecValidate,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{generate::specs::SpecError, testing::make_test_cli};
+
+  #[test]
+  fn merge_keeps_user_tasks_and_comments_and_reports_conflicts() {
+    let spec = MakefileSpec::try_from(&make_test_cli()).expect("must make spec");
+    let existing: Document = r#"
+      # Tuned for CI
+      [config]
+      skip_core_tasks = true
+      [env]
+      # Read by my-task
+      MY_VAR = "mine"
+      LIB_NAME = "stale"
+      [tasks.my-task]
+      command = "echo"
+      [tasks.spec-validate]
+      command = "my-validator"
+      [tasks.spec-download-default]
+      category = "openapi_lib_generator v0.0.1"
+      command = "wget"
+      [tasks.output-dir-clean]
+      category = "openapi_lib_generator v0.0.1"
+      command = "rm"
+      "#
+    .parse()
+    .expect("must parse");
+    let (merged, conflicts) = spec.merge_into_document(existing).expect("must merge");
+    assert_eq!(
+      conflicts,
+      vec![MakefileTaskConflict(TaskNames::SpecValidate)]
+    );
+    assert_eq!(merged["config"]["skip_core_tasks"].as_bool(), Some(true));
+    assert_eq!(merged["env"]["MY_VAR"].as_str(), Some("mine"));
+    assert_eq!(
+      merged["env"]["LIB_NAME"].as_str(),
+      Some("pets_openapi_client")
+    );
+    let tasks = merged["tasks"].as_table().expect("must get tasks");
+    assert!(tasks.contains_key("my-task"));
+    assert!(tasks.contains_key(TaskNames::GenerateAll.as_ref()));
+    assert!(!tasks.contains_key(TaskNames::SpecDownloadDefault.as_ref()));
+    assert!(!tasks.contains_key("output-dir-clean"));
+    assert_eq!(
+      tasks[TaskNames::SpecValidate.as_ref()]["command"].as_str(),
+      Some("my-validator")
+    );
+    let merged_string = merged.to_string();
+    assert!(merged_string.contains("# Tuned for CI"));
+    assert!(merged_string.contains("# Read by my-task"));
+    let reparsed: Document = merged_string.parse().expect("must reparse");
+    assert!(reparsed["tasks"][TaskNames::GenerateAll.as_ref()].is_table());
+  }
+
+  #[test]
+  fn hooks_are_wired_as_dependencies() {
+    let mut cli = make_test_cli();
+    for stage in [HookStages::PostGenerate, HookStages::PostFix] {
+      cli
+        .project_config
+        .hooks
+        .insert(stage, vec!["echo hook".to_string()]);
+    }
+    let spec = MakefileSpec::try_from(&cli).expect("must make spec");
+    let dependency_names = spec.tasks[&TaskNames::GenerateAll]
+      .dependencies
+      .iter()
+      .flatten()
+      .map(|dependency| match dependency {
+        DependencyIdentifier::Name(name) => name.to_string(),
+        other => panic!("unexpected dependency {other:?}"),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      dependency_names,
+      vec![
+        "lib-code-generate",
+        "hook-post-generate",
+        "cargo-fix-generated",
+        "hook-post-fix"
+      ]
+    );
+    assert!(spec.tasks.contains_key(&TaskNames::HookPostFix));
+    assert!(!spec.tasks.contains_key(&TaskNames::HookPreDownload));
+  }
+
+  #[test]
+  fn spec_download_task_fills_secret_query_from_env() {
+    let cli = make_test_cli();
+    let api_spec_url =
+      Url::parse("https://specs.example/pets.yaml?api_key=abc&v=2").expect("must parse");
+    let task =
+      NamedTask::make_spec_download_default_task(&cli, &api_spec_url).expect("must make task");
+    let Some(ScriptValue::Text(script_lines)) = task.task.script else {
+      panic!("must get script lines");
+    };
+    let script = script_lines.join("\n");
+    let yaml = script
+      .split_once("r#\"")
+      .and_then(|(_, rest)| rest.split_once("\"#;"))
+      .map(|(yaml, _)| yaml)
+      .expect("must embed yaml");
+    assert!(!yaml.contains("abc"));
+    let spec_downloader: fetches::SpecDownloader =
+      serde_yaml::from_str(yaml).expect("must deserialize");
+    let env_vars = fetches::get_secret_query_env_vars(&api_spec_url);
+    assert_eq!(
+      env_vars,
+      vec![("OLG_SPEC_QUERY_API_KEY".to_string(), "abc".to_string())]
+    );
+    assert!(matches!(
+      fetches::fill_secret_query_from(&spec_downloader.spec_url, |_| None),
+      Err(SpecError::MissingSecretQuery { .. })
+    ));
+    let env = env_vars.into_iter().collect::<BTreeMap<_, _>>();
+    assert_eq!(
+      fetches::fill_secret_query_from(&spec_downloader.spec_url, |key| env.get(key).cloned())
+        .expect("must fill"),
+      api_spec_url
+    );
+  }
+}
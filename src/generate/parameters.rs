@@ -11,10 +11,18 @@ pub enum ParameterError {
   APIPathNeedsSegments(Url),
   #[error("API path segments has no last")]
   APIPathSegmentsNeedsLast,
+  #[error("Must provide the site or API name with `--name`")]
+  APINameNeeded,
+  #[error("Must provide the API url with `--api-url`")]
+  APIUrlNeeded,
   #[error("Must provide API spec url if no local file given")]
   APIUrlNeededIfNoLocalFile,
+  #[error("Cannot regenerate: no crate at {0:?}")]
+  NoCrateToRegenerate(std::path::PathBuf),
   #[error("Must provide a path to create the testing yaml spec")]
   TestingYAMLSpecPathMissing,
+  #[error("Git spec urls must be of the form `git:<path-or-url>#<ref>:<file>`")]
+  InvalidGitSpecUrl,
 }
 
 /// Get file name from path
@@ -22,10 +30,10 @@ pub fn try_file_name_from_path_url(path_url: &Url) -> Result<String, ParameterEr
   path_url
     .path_segments()
     .ok_or_else(|| ParameterError::APIPathNeedsSegments(path_url.clone()))
-    .and_then(|path_segments| {
+    .and_then(|mut path_segments| {
       path_segments
-        .last()
-        .ok_or_else(|| ParameterError::APIPathSegmentsNeedsLast)
+        .next_back()
+        .ok_or(ParameterError::APIPathSegmentsNeedsLast)
         .map(ToString::to_string)
     })
 }
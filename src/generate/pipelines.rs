@@ -0,0 +1,424 @@
+//! Task pipelines
+//!
+//! The steps defined by [TaskNames] can run through cargo-make (using the generated makefile) or
+//! natively, in process, so that cargo-make is optional.
+use crate::{
+  caches,
+  cli::{Cli, SubCommands},
+  generate::{
+    cargos::CargoConfigurator,
+    errors::*,
+    locks::Locker,
+    makefiles::{MakefileEnv, MakefileSpec, TaskNames},
+    projects::HookStages,
+    readmes::READMEGenerator,
+    specs::{fetches::SpecDownloader, SpecPreparer},
+    utils::*,
+  },
+  process::ProcessJob,
+  reports,
+};
+use cargo_make::types::{DependencyIdentifier, Task};
+use cli as cargo_make;
+use fs_err::tokio as tokio_fs;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{BTreeMap, HashSet},
+  io::Error as IOError,
+  path::PathBuf,
+  str::FromStr,
+};
+use strum::IntoEnumIterator;
+use thiserror::Error;
+use tracing::info;
+use url::Url;
+
+/// Pipeline errors
+#[derive(Debug, Error)]
+pub enum PipelineError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  CargoConfigError(#[from] CargoConfigError),
+  #[error(transparent)]
+  LockError(#[from] LockError),
+  #[error(transparent)]
+  MakefileGenerationError(#[from] MakefileGenerationError),
+  #[error(transparent)]
+  ProcessError(#[from] ProcessError),
+  #[error(transparent)]
+  ProjectConfigError(#[from] ProjectConfigError),
+  #[error(transparent)]
+  READMEGenerationError(#[from] READMEGenerationError),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error(transparent)]
+  SpecError(#[from] SpecError),
+  #[error("Task `{}` is not defined for this crate", .0.as_ref())]
+  MissingTask(TaskNames),
+  #[error("Task `{}` needs arguments and cannot run natively", .0.as_ref())]
+  MissingTaskArgs(TaskNames),
+  #[error("Missing tool `{0}`")]
+  MissingTool(String),
+  #[error("Task `{}` failed", .task_name.as_ref())]
+  TaskFailed {
+    task_name: TaskNames,
+    #[source]
+    source: Box<PipelineError>,
+  },
+  #[error("Task `{}` is not supported by the native runner. Try `cargo make {}`", .0.as_ref(), .0.as_ref())]
+  UnsupportedTask(TaskNames),
+  #[error("Unknown task dependency `{0}`")]
+  UnknownTask(String),
+}
+
+/// Ways of running tasks
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskRunners {
+  /// Run tasks in process
+  #[default]
+  Native,
+  /// Run tasks through the generated cargo-make makefile
+  CargoMake,
+}
+
+/// Get the tasks a run of the [Cli] executes after scaffolding, in order
+pub fn get_generation_task_names(cli: &Cli) -> Vec<TaskNames> {
+  match cli.command.as_ref() {
+    Some(SubCommands::TestGeneration { .. }) => vec![TaskNames::GenerateAll],
+    Some(
+      SubCommands::Batch { .. }
+      | SubCommands::Cache { .. }
+      | SubCommands::Doctor { .. }
+      | SubCommands::Rollback
+      | SubCommands::SpecDiff { .. }
+      | SubCommands::Lint { .. },
+    ) => {
+      vec![]
+    }
+    None if !cli.autogenerate => vec![],
+    None if cli.local_api_spec_filepath_opt.is_some() || cli.get_spec_command_opt().is_some() => {
+      vec![TaskNames::GenerateAll]
+    }
+    None if cli.api_spec_url_opt.is_some() => {
+      vec![TaskNames::SpecDownloadDefault, TaskNames::GenerateAll]
+    }
+    None => vec![],
+  }
+}
+
+/// Run a task with the runner selected in the [Cli]
+pub async fn run_task(
+  cli: &Cli,
+  task_name: TaskNames,
+) -> Result<(), PipelineError> {
+  match cli.runner {
+    TaskRunners::Native => NativeTaskRunner::try_new(cli)?.run_task(task_name).await,
+    TaskRunners::CargoMake => {
+      if find_in_path("cargo-make").is_none() {
+        return Err(PipelineError::MissingTool("cargo-make".to_string()));
+      }
+      reports::record_stage(task_name.as_ref(), run_cargo_make_task(cli, task_name))
+        .await
+        .map_err(|e| PipelineError::TaskFailed {
+          task_name,
+          source: Box::new(e.into()),
+        })?;
+      Ok(())
+    }
+  }
+}
+
+/// Runs the steps defined by [TaskNames] natively
+///
+/// Tasks and their dependencies come from the same [MakefileSpec] that is exported as the
+/// makefile, and are run in dependency order, each at most once.
+pub struct NativeTaskRunner<'a> {
+  cli: &'a Cli,
+  crate_dir: PathBuf,
+  env: BTreeMap<String, String>,
+  makefile_spec: MakefileSpec,
+}
+impl<'a> NativeTaskRunner<'a> {
+  /// Instantiate
+  pub fn try_new(cli: &'a Cli) -> Result<Self, PipelineError> {
+    let crate_dir = cli.get_output_project_dir();
+    let makefile_spec = MakefileSpec::try_from(cli)?;
+    let env = makefile_spec.get_env().try_resolve(&crate_dir)?;
+    Ok(Self {
+      cli,
+      crate_dir,
+      env,
+      makefile_spec,
+    })
+  }
+
+  /// Get a task by name
+  fn get_task(
+    &self,
+    task_name: TaskNames,
+  ) -> Result<&Task, PipelineError> {
+    self
+      .makefile_spec
+      .get_task(&task_name)
+      .ok_or(PipelineError::MissingTask(task_name))
+  }
+
+  /// Get the order in which a task and its dependencies run
+  pub fn get_execution_plan(
+    &self,
+    task_name: TaskNames,
+  ) -> Result<Vec<TaskNames>, PipelineError> {
+    let mut plan = vec![];
+    self.extend_execution_plan(task_name, &mut plan, &mut HashSet::new())?;
+    Ok(plan)
+  }
+
+  /// Add a task and its dependencies to an execution plan, depth first
+  fn extend_execution_plan(
+    &self,
+    task_name: TaskNames,
+    plan: &mut Vec<TaskNames>,
+    visited: &mut HashSet<TaskNames>,
+  ) -> Result<(), PipelineError> {
+    if !visited.insert(task_name) {
+      return Ok(());
+    }
+    for dependency in self.get_task(task_name)?.dependencies.iter().flatten() {
+      let dependency_name = match dependency {
+        DependencyIdentifier::Name(name) => name.to_string(),
+        DependencyIdentifier::Definition(definition) => definition.name.to_string(),
+      };
+      let dependency_task_name = TaskNames::from_str(&dependency_name)
+        .map_err(|_| PipelineError::UnknownTask(dependency_name))?;
+      self.extend_execution_plan(dependency_task_name, plan, visited)?;
+    }
+    plan.push(task_name);
+    Ok(())
+  }
+
+  /// Run a task after its dependencies
+  pub async fn run_task(
+    &self,
+    task_name: TaskNames,
+  ) -> Result<(), PipelineError> {
+    for planned_task_name in self.get_execution_plan(task_name)? {
+      reports::record_stage(
+        planned_task_name.as_ref(),
+        self.run_task_action(planned_task_name),
+      )
+      .await
+      .map_err(|e| PipelineError::TaskFailed {
+        task_name: planned_task_name,
+        source: Box::new(e),
+      })?;
+    }
+    Ok(())
+  }
+
+  /// Run the action of a single task, without its dependencies
+  async fn run_task_action(
+    &self,
+    task_name: TaskNames,
+  ) -> Result<(), PipelineError> {
+    let crate_dir = &self.crate_dir;
+    info!("Running task `{}` natively", task_name.as_ref());
+    match task_name {
+      TaskNames::GenerateAll => self.run_post_generation().await,
+      TaskNames::CrateScaffold => Ok(()),
+      TaskNames::OpenapiCliCheck => self.check_openapi_cli().map(|_| ()),
+      TaskNames::OpenapiCliBashInstall => Err(PipelineError::UnsupportedTask(task_name)),
+      TaskNames::OutputDirCreate => {
+        tokio_fs::create_dir_all(self.get_env_path("OUTPUT_DIR")).await?;
+        Ok(())
+      }
+      TaskNames::SpecDownloadDefault => {
+        // The env has the redacted url, so prefer the one passed to olg
+        let spec_url = match self.cli.api_spec_url_opt.clone() {
+          Some(spec_url) => spec_url,
+          None => {
+            let url = self.get_env_value("SPEC_FILE_URL");
+            Url::parse(url).map_err(|_| SpecError::InvalidRef(url.to_string()))?
+          }
+        };
+        SpecDownloader {
+          spec_url,
+          spec_file_path: PathBuf::from(self.get_env_value("SPEC_FILE_PATH")),
+        }
+        .download_spec_in(crate_dir, self.cli.offline)
+        .await?;
+        Ok(())
+      }
+      TaskNames::SpecDownload => Err(PipelineError::MissingTaskArgs(task_name)),
+      TaskNames::SpecPrepare => {
+        SpecPreparer::new(self.cli)?
+          .prepare_spec_file_in(crate_dir)
+          .await?;
+        Ok(())
+      }
+      TaskNames::LockCheck => {
+        Locker::new(self.cli)?
+          .check_lock_in(crate_dir, self.cli.update_lock, self.cli.offline)
+          .await?;
+        Ok(())
+      }
+      TaskNames::HookPreDownload
+      | TaskNames::HookPostDownload
+      | TaskNames::HookPreGenerate
+      | TaskNames::HookPostGenerate
+      | TaskNames::HookPostFix => {
+        let stage = HookStages::iter()
+          .find(|stage| stage.get_task_name() == task_name)
+          .expect("must get hook stage");
+        self
+          .cli
+          .project_config
+          .run_hooks(stage, crate_dir, self.cli.get_process_timeout_opt())
+          .await?;
+        Ok(())
+      }
+      TaskNames::LibCodeGenerate | TaskNames::LibCodeGenerateDryRun => {
+        self.check_openapi_cli()?;
+        self.run_task_command(task_name).await
+      }
+      TaskNames::CargoFixGenerated | TaskNames::SpecValidate => {
+        self.run_task_command(task_name).await
+      }
+    }
+  }
+
+  /// Run the command of a task with its args expanded
+  async fn run_task_command(
+    &self,
+    task_name: TaskNames,
+  ) -> Result<(), PipelineError> {
+    let generator_env = caches::get_generator_env(self.cli.offline).await;
+    self
+      .get_task_job(task_name, generator_env)?
+      .log_file_path_opt(Some(self.cli.get_log_file_path(task_name.as_ref())))
+      .timeout_opt(self.cli.get_process_timeout_opt())
+      .run()
+      .await?;
+    Ok(())
+  }
+
+  /// Get a job running the command of a task with its args expanded, and the generator env
+  pub fn get_task_job(
+    &self,
+    task_name: TaskNames,
+    generator_env: Vec<(&'static str, String)>,
+  ) -> Result<ProcessJob, PipelineError> {
+    let task = self.get_task(task_name)?;
+    let command = task
+      .command
+      .as_ref()
+      .ok_or(PipelineError::UnsupportedTask(task_name))?;
+    let command = MakefileEnv::expand_value(command, &self.env, 0)?;
+    let program = find_in_path(&command).unwrap_or_else(|| PathBuf::from(&command));
+    let args = task
+      .args
+      .iter()
+      .flatten()
+      .map(|arg| MakefileEnv::expand_value(arg, &self.env, 0))
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut job = ProcessJob::new(program.to_string_lossy())
+      .args(args)
+      .cwd(&self.crate_dir);
+    for (key, value) in generator_env {
+      job = job.env(key, value);
+    }
+    Ok(job)
+  }
+
+  /// Check that the openapi generator cli is available
+  pub fn check_openapi_cli(&self) -> Result<PathBuf, PipelineError> {
+    let script = self.get_env_value("OPEN_API_GENERATOR_CLI_SCRIPT");
+    find_in_path(script).ok_or_else(|| PipelineError::MissingTool(script.to_string()))
+  }
+
+  /// Update the cargo manifest and readme after generation
+  async fn run_post_generation(&self) -> Result<(), PipelineError> {
+    let crate_dir = &self.crate_dir;
+    let cargo_configurator = CargoConfigurator::new(self.cli)?;
+    cargo_configurator
+      .update_cargo_manifest_post_generation_in(crate_dir)
+      .await?;
+    cargo_configurator
+      .update_cargo_manifest_post_fix_edition_in(crate_dir)
+      .await?;
+    READMEGenerator::new(self.cli)?
+      .update_readme_md_file_in(crate_dir)
+      .await?;
+    info!("updates complete");
+    Ok(())
+  }
+
+  /// Get a resolved env value
+  pub fn get_env_value(
+    &self,
+    key: &str,
+  ) -> &str {
+    self.env.get(key).map(String::as_str).unwrap_or_default()
+  }
+
+  /// Get a resolved env value as a path relative to the crate dir
+  pub fn get_env_path(
+    &self,
+    key: &str,
+  ) -> PathBuf {
+    self.crate_dir.join(self.get_env_value(key))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::make_test_cli;
+
+  #[test]
+  fn execution_plan_follows_dependencies() {
+    let mut cli = make_test_cli();
+    cli
+      .project_config
+      .hooks
+      .insert(HookStages::PreGenerate, vec!["echo hook".to_string()]);
+    let runner = NativeTaskRunner::try_new(&cli).expect("must make runner");
+    assert_eq!(
+      runner
+        .get_execution_plan(TaskNames::GenerateAll)
+        .expect("must plan"),
+      vec![
+        TaskNames::SpecPrepare,
+        TaskNames::LockCheck,
+        TaskNames::HookPreGenerate,
+        TaskNames::LibCodeGenerate,
+        TaskNames::CargoFixGenerated,
+        TaskNames::GenerateAll,
+      ]
+    );
+    assert_eq!(runner.get_env_value("SPEC_FILE_PATH"), "pets.yaml");
+  }
+
+  #[test]
+  fn spec_prepare_runs_after_post_download_hooks() {
+    let mut cli = make_test_cli();
+    cli
+      .project_config
+      .hooks
+      .insert(HookStages::PostDownload, vec!["echo hook".to_string()]);
+    cli.project_config.spec.filter.include.tags = vec!["pets".to_string()];
+    let runner = NativeTaskRunner::try_new(&cli).expect("must make runner");
+    assert_eq!(
+      runner
+        .get_execution_plan(TaskNames::LibCodeGenerate)
+        .expect("must plan"),
+      vec![
+        TaskNames::HookPostDownload,
+        TaskNames::SpecPrepare,
+        TaskNames::LockCheck,
+        TaskNames::LibCodeGenerate,
+      ]
+    );
+  }
+}
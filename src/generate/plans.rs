@@ -0,0 +1,368 @@
+//! Generation plans
+//!
+//! A plan describes what a run would do, without touching the disk: the crate dir, the files
+//! written, the tasks run and, when possible, a summary of the upstream generator's dry run. The
+//! dry run only runs a generator jar that is already downloaded.
+use crate::{
+  caches,
+  cli::{Cli, Paths, SubCommands},
+  generate::{
+    cargos::CargoWorkspace,
+    errors::*,
+    makefiles::{MakefileEnv, TaskNames},
+    pipelines::{get_generation_task_names, NativeTaskRunner},
+    specs::SpecPreparer,
+    transactions::Transaction,
+  },
+};
+use std::{
+  collections::BTreeMap,
+  fmt::{self, Display},
+  path::PathBuf,
+};
+use strum::EnumProperty;
+
+/// A file a run would write
+#[derive(Clone, Debug)]
+pub struct PlannedFile {
+  pub path: PathBuf,
+  /// Which step writes the file
+  pub step: String,
+}
+
+/// A file listed in the upstream generator's dry run
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DryRunEntry {
+  /// The single letter state, e.g. `w` for write or `s` for skipped overwrite
+  pub state: char,
+  pub path: String,
+}
+
+/// A parsed summary of the upstream generator's dry run
+#[derive(Clone, Debug, Default)]
+pub struct DryRunSummary {
+  pub entries: Vec<DryRunEntry>,
+}
+impl DryRunSummary {
+  /// States used by the upstream generator, with their descriptions
+  pub const STATES: [(char, &'static str); 6] = [
+    ('w', "write"),
+    ('n', "write if new or updated"),
+    ('i', "ignored"),
+    ('s', "skipped overwrite"),
+    ('k', "skipped by user option"),
+    ('e', "error evaluating write state"),
+  ];
+
+  /// Parse the output of `openapi-generator-cli generate --dry-run`
+  pub fn parse(output: &str) -> Self {
+    let entries = output
+      .lines()
+      .filter_map(|line| {
+        let (state, path) = line.trim().split_once(char::is_whitespace)?;
+        let state = state.parse::<char>().ok()?;
+        Self::STATES
+          .iter()
+          .any(|(s, _)| *s == state)
+          .then(|| DryRunEntry {
+            state,
+            path: path.trim().to_string(),
+          })
+      })
+      .collect();
+    Self { entries }
+  }
+
+  /// Count the entries by state description
+  pub fn get_counts(&self) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for entry in self.entries.iter() {
+      if let Some((_, description)) = Self::STATES.iter().find(|(s, _)| *s == entry.state) {
+        *counts.entry(*description).or_default() += 1;
+      }
+    }
+    counts
+  }
+}
+
+/// What a run would do
+#[derive(Clone, Debug)]
+pub struct GenerationPlan {
+  pub crate_dir: PathBuf,
+  /// Whether the crate dir would be created
+  pub is_crate_dir_new: bool,
+  pub lib_name: String,
+  pub files: Vec<PlannedFile>,
+  pub tasks: Vec<TaskNames>,
+  /// The upstream dry run, or why it was skipped
+  pub dry_run_result: Result<DryRunSummary, String>,
+  /// Problems the run would run into
+  pub notes: Vec<String>,
+}
+impl GenerationPlan {
+  /// Plan the run of a [Cli]
+  pub async fn try_new(cli: &Cli) -> Result<Self, PipelineError> {
+    let crate_dir = cli.get_output_project_dir();
+    let runner = NativeTaskRunner::try_new(cli)?;
+    let mut tasks = vec![];
+    for task_name in get_generation_task_names(cli) {
+      for planned_task_name in runner.get_execution_plan(task_name)? {
+        if !tasks.contains(&planned_task_name) {
+          tasks.push(planned_task_name);
+        }
+      }
+    }
+    let mut notes = vec![];
+    let is_test = matches!(cli.command, Some(SubCommands::TestGeneration { .. }));
+    if cli.regenerate {
+      if !cli
+        .get_output_project_subpath(&Paths::CargoTomlFile)
+        .is_file()
+      {
+        notes.push(format!("Regeneration will fail: no crate at {crate_dir:?}"));
+      }
+    } else if !is_test && crate_dir.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+      notes.push(format!(
+        "Scaffolding will fail: {crate_dir:?} exists and is not empty"
+      ));
+    }
+    if cli.staging && !tasks.is_empty() {
+      let transaction =
+        Transaction::try_for_crate_dir(&crate_dir).unwrap_or_else(|_| Transaction::new(&crate_dir));
+      notes.push(format!(
+        "Tasks run in {:?} and are swapped in once all succeed; the previous crate is kept in {:?}",
+        transaction.get_staging_dir(),
+        transaction.get_backup_dir()
+      ));
+    }
+    let mut plan = Self {
+      is_crate_dir_new: !crate_dir.exists(),
+      files: vec![],
+      lib_name: cli.get_lib_name(),
+      tasks,
+      dry_run_result: Err("not run".to_string()),
+      notes,
+      crate_dir,
+    };
+    plan.add_files(cli, &runner);
+    plan.dry_run_result = plan.run_upstream_dry_run(cli, &runner).await;
+    Ok(plan)
+  }
+
+  /// Add a file written by a step
+  fn add_file(
+    &mut self,
+    path: impl Into<PathBuf>,
+    step: impl AsRef<str>,
+  ) {
+    let path = self.crate_dir.join(path.into());
+    self.files.push(PlannedFile {
+      path,
+      step: step.as_ref().to_string(),
+    });
+  }
+
+  /// List the files scaffolding writes
+  fn add_scaffold_files(&mut self) {
+    let get_path = |path: Paths| path.get_str("path").expect("must get path");
+    self.add_file(get_path(Paths::CargoTomlFile), "scaffold (cargo init)");
+    self.add_file("src/lib.rs", "scaffold (cargo init)");
+    match CargoWorkspace::find_enclosing(&self.crate_dir)
+      .ok()
+      .flatten()
+    {
+      Some(workspace) if !workspace.is_listed_member() => self.files.push(PlannedFile {
+        path: workspace.root_dir.join(get_path(Paths::CargoTomlFile)),
+        step: "scaffold (workspace member)".to_string(),
+      }),
+      Some(_) => {}
+      None => self.add_file(get_path(Paths::GitignoreFile), "scaffold"),
+    }
+  }
+
+  /// List the files the run writes, in order
+  fn add_files(
+    &mut self,
+    cli: &Cli,
+    runner: &NativeTaskRunner,
+  ) {
+    let get_path = |path: Paths| path.get_str("path").expect("must get path");
+    if !cli.regenerate {
+      self.add_scaffold_files();
+    }
+    if cli.should_export_makefile() {
+      self.add_file(get_path(Paths::CargoMakefile), "makefile");
+    }
+    if cli.local_api_spec_filepath_opt.is_some() || cli.get_spec_command_opt().is_some() {
+      self.add_file(
+        runner.get_env_value("SPEC_FILE_PATH"),
+        "generator-configs (spec copy)",
+      );
+    }
+    self.add_file(
+      MakefileEnv::OPEN_API_GENERATOR_CONFIG_FILE,
+      "generator-configs",
+    );
+    if cli.project_config_path_opt.is_some() || cli.spec_command_opt.is_some() {
+      self.add_file(get_path(Paths::ProjectConfigFile), "project-config");
+    }
+    for task_name in self.tasks.clone() {
+      match task_name {
+        TaskNames::SpecDownloadDefault => {
+          self.add_file(runner.get_env_value("SPEC_FILE_PATH"), task_name.as_ref())
+        }
+        TaskNames::SpecPrepare => {
+          // Without a spec config, only Swagger 2.0 specs are rewritten, which the plan cannot
+          // tell before the spec is downloaded
+          if let Some(spec_preparer) = SpecPreparer::new(cli)
+            .ok()
+            .filter(|spec_preparer| !spec_preparer.config.is_empty())
+          {
+            self.add_file(
+              spec_preparer.get_original_spec_file_path(),
+              task_name.as_ref(),
+            );
+            self.add_file(spec_preparer.spec_file_path, task_name.as_ref());
+          }
+        }
+        TaskNames::LibCodeGenerate => self.add_file("src/**", task_name.as_ref()),
+        TaskNames::GenerateAll => {
+          self.add_file(get_path(Paths::CargoTomlFile), task_name.as_ref());
+          self.add_file(get_path(Paths::ReadmeMdFile), task_name.as_ref());
+        }
+        _ => {}
+      }
+    }
+    if !self.tasks.is_empty() {
+      self.add_file(get_path(Paths::LockFile), "lock");
+    }
+    self.add_file(get_path(Paths::ChangelogMdFile), "changelog");
+    self.add_file(format!("{}/*.log", get_path(Paths::LogsDir)), "logs");
+    if let Some(report_file_path) = cli.report_file_path_opt.as_ref() {
+      self.files.push(PlannedFile {
+        path: report_file_path.clone(),
+        step: "report".to_string(),
+      });
+    }
+  }
+
+  /// Run the upstream generator's dry run against an existing crate
+  async fn run_upstream_dry_run(
+    &self,
+    cli: &Cli,
+    runner: &NativeTaskRunner<'_>,
+  ) -> Result<DryRunSummary, String> {
+    let spec_file_path = match cli.local_api_spec_filepath_opt.as_ref() {
+      Some(local_api_spec_filepath) if !cli.is_spec_from_stdin() => local_api_spec_filepath.clone(),
+      _ => runner.get_env_path("SPEC_FILE_PATH"),
+    };
+    let config_path = runner.get_env_path("OPEN_API_GENERATOR_CONFIG_PATH");
+    if !spec_file_path.is_file() {
+      return Err(format!(
+        "skipped: spec {spec_file_path:?} is not available yet"
+      ));
+    }
+    if !config_path.is_file() {
+      return Err(format!(
+        "skipped: generator config {config_path:?} is not written yet"
+      ));
+    }
+    runner
+      .check_openapi_cli()
+      .map_err(|e| format!("skipped: {e}"))?;
+    // The generator script downloads the jar it runs when it is missing
+    let generator_env = caches::get_downloaded_generator_env().await.ok_or(
+      "skipped: no openapi generator jar is downloaded yet, and the dry run would download one",
+    )?;
+    let output = runner
+      .get_task_job(TaskNames::LibCodeGenerateDryRun, generator_env)
+      .map_err(|e| format!("skipped: {e}"))?
+      .timeout_opt(cli.get_process_timeout_opt())
+      .run()
+      .await
+      .map_err(|e| format!("failed: {e}"))?;
+    Ok(DryRunSummary::parse(&output.stdout))
+  }
+}
+impl Display for GenerationPlan {
+  fn fmt(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+  ) -> fmt::Result {
+    writeln!(f, "Plan for `{}`", self.lib_name)?;
+    writeln!(
+      f,
+      "\nCrate dir:\n  {:?} ({})",
+      self.crate_dir,
+      if self.is_crate_dir_new {
+        "created"
+      } else {
+        "existing"
+      }
+    )?;
+    writeln!(f, "\nFiles:")?;
+    for file in self.files.iter() {
+      writeln!(f, "  {:?} ({})", file.path, file.step)?;
+    }
+    writeln!(f, "\nTasks:")?;
+    if self.tasks.is_empty() {
+      writeln!(f, "  none")?;
+    }
+    for (i, task_name) in self.tasks.iter().enumerate() {
+      writeln!(f, "  {}. {}", i + 1, task_name.as_ref())?;
+    }
+    writeln!(f, "\nUpstream dry run:")?;
+    match self.dry_run_result.as_ref() {
+      Ok(summary) => {
+        for (description, count) in summary.get_counts() {
+          writeln!(f, "  {count} {description}")?;
+        }
+        for entry in summary.entries.iter() {
+          writeln!(f, "  {} {}", entry.state, entry.path)?;
+        }
+      }
+      Err(reason) => writeln!(f, "  {reason}")?,
+    }
+    if !self.notes.is_empty() {
+      writeln!(f, "\nNotes:")?;
+      for note in self.notes.iter() {
+        writeln!(f, "  {note}")?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dry_run_output_is_summarized() {
+    let summary = DryRunSummary::parse(
+      "
+      ::info:: Dry Run Results:
+
+      w /out/src/apis/mod.rs
+      w /out/src/models/pet.rs
+      s /out/Cargo.toml
+
+      States:
+
+        - w Write
+        - s Skipped Overwrite
+      ",
+    );
+    assert_eq!(summary.entries.len(), 3);
+    assert_eq!(
+      summary.entries[2],
+      DryRunEntry {
+        state: 's',
+        path: "/out/Cargo.toml".to_string()
+      }
+    );
+    let counts = summary.get_counts();
+    assert_eq!(counts["write"], 2);
+    assert_eq!(counts["skipped overwrite"], 1);
+  }
+}
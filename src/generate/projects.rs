@@ -0,0 +1,169 @@
+//! Per project configuration
+use crate::{
+  cli::{Cli, Paths},
+  generate::{
+    locks::LockMismatchActions,
+    makefiles::TaskNames,
+    specs::{lints::LintConfig, SpecConfig},
+    versions::VersionSources,
+  },
+  process::{ProcessError, ProcessJob},
+};
+use fs_err::tokio as fs;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Error as SerdeYAMLError;
+use std::{
+  collections::BTreeMap,
+  io::Error as IOError,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+use strum::EnumProperty;
+use thiserror::Error;
+
+/// Project configuration errors
+#[derive(Debug, Error)]
+pub enum ProjectConfigError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error("Hook for stage `{}` failed", .stage.as_ref())]
+  HookFailed {
+    stage: HookStages,
+    #[source]
+    source: ProcessError,
+  },
+}
+
+/// Project configuration
+///
+/// Read from the file given with `--config`, or from [Paths::ProjectConfigFile] in the output
+/// project dir when present.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProjectConfig {
+  /// Shell commands (or scripts) to run around generation stages, keyed by stage
+  pub hooks: BTreeMap<HookStages, Vec<String>>,
+  /// How the spec is prepared before generation
+  pub spec: SpecConfig,
+  /// Severities of the spec lint rules
+  pub lint: LintConfig,
+  /// Where the crate version comes from
+  pub version_from: VersionSources,
+  /// What to do when the generation does not match olg.lock
+  pub lock_mismatch: LockMismatchActions,
+  /// Shell command whose stdout is the spec, from `--spec-command`
+  #[serde(rename = "spec-command", skip_serializing_if = "Option::is_none")]
+  pub spec_command_opt: Option<String>,
+  /// Dir the spec command runs in, the current dir when it was given
+  #[serde(rename = "spec-command-dir", skip_serializing_if = "Option::is_none")]
+  pub spec_command_dir_opt: Option<PathBuf>,
+}
+impl ProjectConfig {
+  /// Read a project configuration from a yaml file
+  pub async fn read_from_path(path: impl AsRef<Path>) -> Result<Self, ProjectConfigError> {
+    let contents = fs::read_to_string(path.as_ref()).await?;
+    Ok(serde_yaml::from_str(&contents)?)
+  }
+
+  /// Write project configuration into the output project dir
+  pub async fn write_to_yaml_file(
+    &self,
+    cli: &Cli,
+  ) -> Result<(), ProjectConfigError> {
+    let output_file_path = cli.get_output_project_subpath(&Paths::ProjectConfigFile);
+    crate::fs::write(
+      output_file_path,
+      serde_yaml::to_string(self)?,
+      Some("Project config"),
+    )
+    .await?;
+    Ok(())
+  }
+
+  /// Get the hook commands for a stage
+  pub fn get_hooks(
+    &self,
+    stage: HookStages,
+  ) -> &[String] {
+    self
+      .hooks
+      .get(&stage)
+      .map(Vec::as_slice)
+      .unwrap_or_default()
+  }
+
+  /// Run the hook commands for a stage in order, stopping at the first failure
+  ///
+  /// Output is logged to `hook-<stage>-<n>.log` in the crate's logs dir.
+  pub async fn run_hooks(
+    &self,
+    stage: HookStages,
+    crate_dir: impl AsRef<Path>,
+    timeout_opt: Option<Duration>,
+  ) -> Result<(), ProjectConfigError> {
+    let crate_dir = crate_dir.as_ref();
+    let logs_dir = crate_dir.join(Paths::LogsDir.get_str("path").expect("must get logs dir"));
+    for (i, command) in self.get_hooks(stage).iter().enumerate() {
+      ProcessJob::new("sh")
+        .args(["-c", command])
+        .env(HookStages::ENV_KEY, stage.as_ref())
+        .cwd(crate_dir)
+        .description_opt(Some(format!("Running {} hook `{command}`", stage.as_ref())))
+        .log_file_path_opt(Some(
+          logs_dir.join(format!("{}-{i}.log", stage.get_task_name().as_ref())),
+        ))
+        .timeout_opt(timeout_opt)
+        .run()
+        .await
+        .map_err(|source| ProjectConfigError::HookFailed { stage, source })?;
+    }
+    Ok(())
+  }
+}
+
+/// Generation stages that hooks may be attached to
+#[derive(
+  Clone,
+  Copy,
+  Debug,
+  Deserialize,
+  Eq,
+  Hash,
+  Ord,
+  PartialEq,
+  PartialOrd,
+  Serialize,
+  strum::AsRefStr,
+  strum::EnumIter,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum HookStages {
+  /// Before the spec is downloaded
+  PreDownload,
+  /// After the spec is downloaded
+  PostDownload,
+  /// Before the library code is generated
+  PreGenerate,
+  /// After the library code is generated
+  PostGenerate,
+  /// After the generated code is fixed
+  PostFix,
+}
+impl HookStages {
+  /// Env var holding the current stage name while a hook runs
+  pub const ENV_KEY: &'static str = "OLG_HOOK_STAGE";
+
+  /// Get the makefile task that runs the hooks for this stage
+  pub fn get_task_name(&self) -> TaskNames {
+    match self {
+      Self::PreDownload => TaskNames::HookPreDownload,
+      Self::PostDownload => TaskNames::HookPostDownload,
+      Self::PreGenerate => TaskNames::HookPreGenerate,
+      Self::PostGenerate => TaskNames::HookPostGenerate,
+      Self::PostFix => TaskNames::HookPostFix,
+    }
+  }
+}
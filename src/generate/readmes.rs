@@ -2,11 +2,19 @@
 
 use crate::{
   cli::{Cli, InnerCli, Paths},
-  generate::utils::*,
+  generate::{
+    errors::ParameterError,
+    specs::fetches::{self, GitSpecSource},
+    utils::*,
+  },
 };
 use fs_err::tokio as fs;
 use serde::{Deserialize, Serialize};
-use std::{io::Error as IOError, string::FromUtf8Error};
+use std::{
+  io::Error as IOError,
+  path::{Path, PathBuf},
+  string::FromUtf8Error,
+};
 use strum::EnumProperty;
 use thiserror::Error;
 /// Errors that can happen with yaml generation
@@ -16,6 +24,8 @@ pub enum READMEGenerationError {
   IOError(#[from] IOError),
   #[error(transparent)]
   FromUtf8Error(#[from] FromUtf8Error),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
 }
 
 /// Readme generation
@@ -24,8 +34,13 @@ pub enum READMEGenerationError {
 pub struct READMEGenerator {
   start_readme_string: String,
   end_readme_string: String,
+  /// The spec file path, relative to the crate dir, when the spec is read from git
+  #[serde(default)]
+  git_spec_file_path_opt: Option<PathBuf>,
 }
 impl READMEGenerator {
+  /// Name of the generated section at the end of the readme
+  const ABOUT_SECTION_NAME: &'static str = "about";
   /// Get the readme string contents
   fn make_readme_strings(cli: &Cli) -> (String, String) {
     let lib_name = cli.get_lib_name();
@@ -42,18 +57,17 @@ impl READMEGenerator {
           if i == 0 {
             s.push_str("\nAdditional authors: ")
           }
-          s.push_str(&format!("{c}"));
+          s.push_str(&c);
           if i < eal - 1 {
             s.push_str(", ");
           }
           s
         });
     let InnerCli {
-      site_or_api_name,
-      api_url,
-      api_spec_url_opt,
-      ..
+      api_spec_url_opt, ..
     } = &cli.inner_cli;
+    let site_or_api_name = cli.get_site_or_api_name();
+    let api_url = cli.get_api_url();
     let mut end = format!("
       {extra_authors}
 
@@ -66,7 +80,7 @@ impl READMEGenerator {
       For these reasons, proposed changes to this repository will likely not be accepted. Try proposing changes to the generator tools instead.
 
       ");
-    if let Some(api_spec_url) = api_spec_url_opt {
+    if let Some(api_spec_url) = api_spec_url_opt.as_ref().map(fetches::redact_url) {
       end.push_str(&format!(
         "\n- Uses the corresponding OpenAPI specification found at [{api_spec_url}]."
       ));
@@ -81,23 +95,54 @@ impl READMEGenerator {
   /// Instantiate
   pub fn new(cli: &Cli) -> Result<Self, READMEGenerationError> {
     let (start_readme_string, end_readme_string) = Self::make_readme_strings(cli);
+    let is_git_spec = cli
+      .api_spec_url_opt
+      .as_ref()
+      .is_some_and(|api_spec_url| api_spec_url.scheme() == GitSpecSource::SCHEME);
+    let git_spec_file_path_opt = match is_git_spec {
+      true => Some(PathBuf::from(cli.try_get_spec_file_name()?)),
+      false => None,
+    };
     Ok(Self {
       end_readme_string,
       start_readme_string,
+      git_spec_file_path_opt,
     })
   }
   /// Write out to readme file
   pub async fn update_readme_md_file(&self) -> Result<(), READMEGenerationError> {
-    let readme_path = Paths::ReadmeMdFile
-      .get_str("path")
-      .expect("must get Cargo.toml path");
-    let mut readme_contents = fs::read(&readme_path).await.map_or_else(
-      |_| Ok(format!("{}\n\n", &self.start_readme_string)),
-      |contents| String::from_utf8(contents),
-    )?;
-    readme_contents.push_str(&self.end_readme_string);
-    fs::write(&readme_path, &readme_contents).await?;
-    println!("Wrote README.md `{readme_path:?}`");
+    self.update_readme_md_file_in(".").await
+  }
+  /// Write out to readme file in the given crate dir
+  pub async fn update_readme_md_file_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+  ) -> Result<(), READMEGenerationError> {
+    let readme_path = &crate_dir.as_ref().join(
+      Paths::ReadmeMdFile
+        .get_str("path")
+        .expect("must get README.md path"),
+    );
+    let readme_contents = fs::read(&readme_path)
+      .await
+      .map_or_else(|_| Ok(self.start_readme_string.clone()), String::from_utf8)?;
+    let mut end_readme_string = self.end_readme_string.clone();
+    if let Some(git_spec_file_path) = self.git_spec_file_path_opt.as_ref() {
+      if let Some(commit) =
+        fetches::read_spec_commit(crate_dir.as_ref().join(git_spec_file_path)).await
+      {
+        end_readme_string.push_str(&format!(
+          "\n- Was built from the spec at commit `{commit}`."
+        ));
+      }
+    }
+    let readme_contents = replace_marked_section(
+      &readme_contents,
+      Self::ABOUT_SECTION_NAME,
+      &end_readme_string,
+      false,
+    );
+    crate::fs::write(&readme_path, &readme_contents, Some("README.md")).await?;
     Ok(())
   }
 }
@@ -0,0 +1,417 @@
+//! OpenAPI spec preparation
+//!
+//! Once downloaded or copied into the crate, the spec at `SPEC_FILE_PATH` is rewritten according
+//! to the `spec` section of the [project config](crate::generate::projects::ProjectConfig)
+//! before it is handed to the generator: Swagger 2.0 specs are converted to OpenAPI 3.0 by
+//! [swaggers], then [patches] are applied, then [filters], and OpenAPI 3.1 specs are optionally
+//! downgraded to 3.0 by [downgrades]. The result is checked by [lints]. The untouched spec is kept next to it
+//! as `<name>.original.<ext>`, and the prepared spec names it under
+//! [SpecPreparer::ORIGINAL_SPEC_KEY] so that preparing again starts over from the original.
+//!
+//! Specs are handled as [serde_json::Value]s, read from and written back to yaml or json.
+pub mod bundles;
+pub mod diffs;
+pub mod downgrades;
+pub mod fetches;
+pub mod filters;
+pub mod lints;
+pub mod patches;
+pub mod swaggers;
+
+use crate::{
+  caches::CacheError, cli::Cli, generate::errors::ParameterError, process::ProcessError, reports,
+};
+use filters::SpecFilter;
+use fs_err::tokio as fs;
+use glob::PatternError;
+use json_patch::PatchError;
+use lints::{LintConfig, LintSeverities};
+use reqwest::Error as ReqwestError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Error as SerdeJSONError, Value};
+use serde_yaml::Error as SerdeYAMLError;
+use std::{
+  collections::BTreeSet,
+  io::Error as IOError,
+  path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tracing::{error, info};
+use url::Url;
+
+/// Spec errors
+#[derive(Debug, Error)]
+pub enum SpecError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeJSONError(#[from] SerdeJSONError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error(transparent)]
+  ParameterError(#[from] ParameterError),
+  #[error(transparent)]
+  ProcessError(#[from] ProcessError),
+  #[error(transparent)]
+  CacheError(#[from] CacheError),
+  #[error("{0} is not cached, so it cannot be read offline")]
+  NotCached(Url),
+  #[error("The `{key}` query parameter of the spec url is redacted, set `{env}` to fetch it")]
+  MissingSecretQuery { key: String, env: String },
+  #[error("Circular ref `{0}` cannot be inlined")]
+  CircularRef(String),
+  #[error("Invalid ref `{0}`")]
+  InvalidRef(String),
+  #[error("Could not fetch {url}")]
+  FetchFailed {
+    url: Url,
+    #[source]
+    source: ReqwestError,
+  },
+  #[error("Invalid credentials file {path:?}")]
+  InvalidCredentialsFile {
+    path: PathBuf,
+    #[source]
+    source: SerdeYAMLError,
+  },
+  #[error("Could not resolve ref to {url}")]
+  UnresolvedRef {
+    url: Url,
+    #[source]
+    source: Box<SpecError>,
+  },
+  #[error("Invalid spec filter path glob `{glob}`")]
+  InvalidPathGlob {
+    glob: String,
+    #[source]
+    source: PatternError,
+  },
+  #[error("Invalid overlay target `{target}` in {path:?}")]
+  InvalidOverlayTarget {
+    path: PathBuf,
+    target: String,
+    #[source]
+    source: serde_json_path::ParseError,
+  },
+  #[error(
+    "Overlay {path:?} no longer applies: the target `{target}` of action {index} matches nothing"
+  )]
+  OverlayTargetNotFound {
+    path: PathBuf,
+    index: usize,
+    target: String,
+  },
+  #[error("JSON patch {path:?} no longer applies")]
+  PatchFailed {
+    path: PathBuf,
+    #[source]
+    source: PatchError,
+  },
+  #[error("{0:?} is neither an OpenAPI Overlay nor a JSON Patch")]
+  UnknownPatchFormat(PathBuf),
+  #[error("Spec was prepared from {0:?}, which is missing")]
+  MissingOriginalSpec(PathBuf),
+  #[error("Spec filter leaves no operations")]
+  NoOperationsLeft,
+  #[error("Spec at {0:?} is not a YAML or JSON mapping")]
+  NotAMapping(PathBuf),
+  #[error("Spec lint found {0} errors")]
+  LintFailed(usize),
+}
+
+/// Spec file formats
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpecFormats {
+  Json,
+  Yaml,
+}
+impl SpecFormats {
+  /// Get the format of a spec file from its extension
+  pub fn from_path(path: impl AsRef<Path>) -> Self {
+    match path.as_ref().extension().and_then(|e| e.to_str()) {
+      Some("json") => Self::Json,
+      _ => Self::Yaml,
+    }
+  }
+}
+
+/// A parsed spec
+#[derive(Clone, Debug)]
+pub struct SpecDocument {
+  pub value: Value,
+  pub format: SpecFormats,
+}
+impl SpecDocument {
+  /// Parse a spec
+  pub fn parse(
+    contents: &str,
+    format: SpecFormats,
+  ) -> Result<Self, SpecError> {
+    let value = match format {
+      SpecFormats::Json => serde_json::from_str(contents)?,
+      SpecFormats::Yaml => serde_yaml::from_str(contents)?,
+    };
+    Ok(Self { value, format })
+  }
+
+  /// Read a spec file
+  pub async fn read_from_path(path: impl AsRef<Path>) -> Result<Self, SpecError> {
+    let contents = fs::read_to_string(path.as_ref()).await?;
+    let spec = Self::parse(&contents, SpecFormats::from_path(path.as_ref()))?;
+    if !spec.value.is_object() {
+      return Err(SpecError::NotAMapping(path.as_ref().to_path_buf()));
+    }
+    Ok(spec)
+  }
+
+  /// Serialize in the spec's format
+  pub fn to_string_pretty(&self) -> Result<String, SpecError> {
+    Ok(match self.format {
+      SpecFormats::Json => serde_json::to_string_pretty(&self.value)?,
+      SpecFormats::Yaml => serde_yaml::to_string(&self.value)?,
+    })
+  }
+
+  /// Write to a spec file
+  pub async fn write_to_path(
+    &self,
+    path: impl AsRef<Path>,
+    message: &str,
+  ) -> Result<(), SpecError> {
+    crate::fs::write(path, self.to_string_pretty()?, Some(message)).await?;
+    Ok(())
+  }
+}
+
+/// Spec preparation settings, from the `spec` section of the project config
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SpecConfig {
+  /// Overlay or JSON patch files applied in order, relative to the crate dir
+  pub patches: Vec<PathBuf>,
+  /// Which operations to keep
+  pub filter: SpecFilter,
+  /// Whether to downgrade OpenAPI 3.1 specs to 3.0
+  pub downgrade: bool,
+}
+impl SpecConfig {
+  /// Check whether the spec is used as is
+  pub fn is_empty(&self) -> bool {
+    self.patches.is_empty() && self.filter.is_empty() && !self.downgrade
+  }
+}
+
+/// Prepares the spec file of a crate for generation
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpecPreparer {
+  /// The spec file path, relative to the crate dir
+  pub spec_file_path: PathBuf,
+  pub config: SpecConfig,
+  #[serde(default)]
+  pub lint: LintConfig,
+}
+impl SpecPreparer {
+  /// Root key of a prepared spec naming the original spec file
+  pub const ORIGINAL_SPEC_KEY: &'static str = "x-olg-original-spec";
+
+  /// Instantiate
+  pub fn new(cli: &Cli) -> Result<Self, ParameterError> {
+    Ok(Self {
+      spec_file_path: PathBuf::from(cli.try_get_spec_file_name()?),
+      config: cli.project_config.spec.clone(),
+      lint: cli.project_config.lint.clone(),
+    })
+  }
+
+  /// Get the path of the original spec, relative to the crate dir
+  pub fn get_original_spec_file_path(&self) -> PathBuf {
+    let stem = self
+      .spec_file_path
+      .file_stem()
+      .map(|stem| stem.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let file_name = match self.spec_file_path.extension() {
+      Some(extension) => format!("{stem}.original.{}", extension.to_string_lossy()),
+      None => format!("{stem}.original"),
+    };
+    self.spec_file_path.with_file_name(file_name)
+  }
+
+  /// Prepare the spec file
+  pub async fn prepare_spec_file(&self) -> Result<(), SpecError> {
+    self.prepare_spec_file_in(".").await
+  }
+
+  /// Prepare the spec file of the given crate dir
+  pub async fn prepare_spec_file_in(
+    &self,
+    crate_dir: impl AsRef<Path>,
+  ) -> Result<(), SpecError> {
+    let spec_file_path = crate_dir.as_ref().join(&self.spec_file_path);
+    let original_spec_file_path = crate_dir.as_ref().join(self.get_original_spec_file_path());
+    let spec = SpecDocument::read_from_path(&spec_file_path).await?;
+    let is_prepared = spec.value.get(Self::ORIGINAL_SPEC_KEY).is_some();
+    let mut spec = if is_prepared {
+      if !original_spec_file_path.is_file() {
+        return Err(SpecError::MissingOriginalSpec(original_spec_file_path));
+      }
+      info!("Preparing the spec again from {original_spec_file_path:?}");
+      SpecDocument::read_from_path(&original_spec_file_path).await?
+    } else {
+      spec
+    };
+    let is_swagger = swaggers::is_swagger(&spec.value);
+    if self.config.is_empty() && !is_swagger {
+      if is_prepared {
+        let contents = fs::read(&original_spec_file_path).await?;
+        crate::fs::write(&spec_file_path, contents, Some("Restored spec")).await?;
+      }
+      return self.lint_spec(&spec.value);
+    }
+    if !is_prepared {
+      let contents = fs::read(&spec_file_path).await?;
+      crate::fs::write(&original_spec_file_path, contents, Some("Original spec")).await?;
+    }
+    if is_swagger {
+      let swaggers::SwaggerConversion {
+        spec: converted,
+        warnings,
+      } = swaggers::convert_swagger(&spec.value);
+      info!(
+        "Converted the Swagger 2.0 spec to OpenAPI {}",
+        swaggers::CONVERTED_OPENAPI_VERSION
+      );
+      for warning in warnings {
+        reports::record_warning(format!("Swagger conversion: {warning}"));
+      }
+      spec.value = converted;
+    }
+    patches::apply_patch_files(&mut spec.value, &self.config.patches, crate_dir.as_ref()).await?;
+    if !self.config.filter.is_empty() {
+      let summary = self.config.filter.try_apply(&mut spec.value)?;
+      info!(
+        "Spec filter kept {} of {} operations, pruned {} components and {} tags",
+        summary.kept_operations,
+        summary.total_operations,
+        summary.pruned_components.len(),
+        summary.pruned_tags.len()
+      );
+    }
+    if self.config.downgrade && downgrades::is_openapi_3_1(&spec.value) {
+      let unconverted = downgrades::downgrade(&mut spec.value);
+      info!(
+        "Downgraded the OpenAPI 3.1 spec to {}, {} constructs left unconverted",
+        downgrades::DOWNGRADED_OPENAPI_VERSION,
+        unconverted.len()
+      );
+      for unconverted in unconverted {
+        reports::record_warning(format!("OpenAPI 3.1 downgrade: {unconverted}"));
+      }
+    }
+    if let Some(root) = spec.value.as_object_mut() {
+      let original_file_name = original_spec_file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+      root.insert(
+        Self::ORIGINAL_SPEC_KEY.to_string(),
+        Value::String(original_file_name),
+      );
+    }
+    spec.write_to_path(&spec_file_path, "Prepared spec").await?;
+    self.lint_spec(&spec.value)
+  }
+
+  /// Lint the prepared spec, recording warnings and failing on errors
+  fn lint_spec(
+    &self,
+    spec: &Value,
+  ) -> Result<(), SpecError> {
+    let report = lints::lint(spec, &self.lint);
+    for issue in report.issues.iter() {
+      match issue.severity {
+        LintSeverities::Error => error!("Spec lint: {issue}"),
+        _ => reports::record_warning(format!("Spec lint: {issue}")),
+      }
+    }
+    report.into_result()
+  }
+}
+
+/// Roots holding reusable objects, with the depth of their entries
+const COMPONENT_ROOTS: [(&str, usize); 4] = [
+  ("components", 2),
+  ("definitions", 1),
+  ("parameters", 1),
+  ("responses", 1),
+];
+
+/// Get the JSON pointer of the component a local `$ref` points into
+pub fn get_component_pointer(reference: &str) -> Option<String> {
+  let segments = reference.strip_prefix("#/")?.split('/').collect::<Vec<_>>();
+  let (_, depth) = COMPONENT_ROOTS
+    .iter()
+    .find(|(root, _)| segments.first() == Some(root))?;
+  (segments.len() > *depth).then(|| format!("/{}", segments[..=*depth].join("/")))
+}
+
+/// Collect the `$ref`s in a value
+pub fn collect_refs(
+  value: &Value,
+  refs: &mut Vec<String>,
+) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map {
+        match value {
+          Value::String(reference) if key == "$ref" => refs.push(reference.to_string()),
+          _ => collect_refs(value, refs),
+        }
+      }
+    }
+    Value::Array(values) => values.iter().for_each(|value| collect_refs(value, refs)),
+    _ => {}
+  }
+}
+
+/// Get the pointers of the components reachable from outside the components
+pub fn get_reachable_components(spec: &Value) -> BTreeSet<String> {
+  let mut pending = vec![];
+  for (key, value) in spec.as_object().into_iter().flatten() {
+    if !COMPONENT_ROOTS.iter().any(|(root, _)| root == key) {
+      collect_refs(value, &mut pending);
+    }
+  }
+  let mut reachable = BTreeSet::new();
+  while let Some(reference) = pending.pop() {
+    if let Some(pointer) = get_component_pointer(&reference) {
+      if let Some(component) = spec.pointer(&pointer) {
+        if reachable.insert(pointer) {
+          collect_refs(component, &mut pending);
+        }
+      }
+    }
+  }
+  reachable
+}
+
+/// Escape a JSON pointer segment
+pub fn escape_pointer_segment(segment: &str) -> String {
+  segment.replace('~', "~0").replace('/', "~1")
+}
+
+/// Remove the value at a JSON pointer from its parent object
+pub fn remove_pointer(
+  spec: &mut Value,
+  pointer: &str,
+) -> bool {
+  let Some((parent, name)) = pointer.rsplit_once('/') else {
+    return false;
+  };
+  let name = name.replace("~1", "/").replace("~0", "~");
+  spec
+    .pointer_mut(parent)
+    .and_then(Value::as_object_mut)
+    .and_then(|parent| parent.shift_remove(&name))
+    .is_some()
+}
@@ -0,0 +1,495 @@
+//! Spec bundling
+//!
+//! A spec may `$ref` other files or urls, relative to its own location. Bundling resolves every
+//! such external ref into one self-contained document:
+//!
+//! - Targets that are components in their own file (`#/components/<kind>/<name>`, or
+//!   `#/definitions/<name>` and the like in Swagger 2.0), and schemas wherever they are, are
+//!   hoisted into the components of the bundled spec and referenced from there. Names that are
+//!   already taken get a numeric suffix.
+//! - Any other target is inlined.
+//!
+//! A target is hoisted only once, before its own refs are resolved, so circular refs between
+//! schemas end up as refs between components. Circular refs through inlined targets fail.
+use super::{collect_refs, fetches::SpecFetcher, SpecDocument, SpecError, SpecFormats};
+use fs_err::tokio as fs;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::info;
+use url::Url;
+
+/// Keys whose value is a schema
+const SCHEMA_KEYS: [&str; 4] = ["schema", "items", "additionalProperties", "not"];
+/// Keys whose entries are schemas
+const SCHEMA_CONTAINER_KEYS: [&str; 5] =
+  ["properties", "patternProperties", "allOf", "anyOf", "oneOf"];
+
+/// Check whether a spec has external refs
+pub fn has_external_refs(spec: &Value) -> bool {
+  let mut refs = vec![];
+  collect_refs(spec, &mut refs);
+  refs.iter().any(|reference| !reference.starts_with('#'))
+}
+
+/// Bundle spec file contents read from a url
+///
+/// Returns the bundled spec, or nothing when the spec has no external refs.
+pub async fn try_bundle_contents(
+  contents: &str,
+  base_url: &Url,
+  fetcher: &SpecFetcher,
+) -> Result<Option<String>, SpecError> {
+  let mut spec = SpecDocument::parse(contents, SpecFormats::from_path(base_url.path()))?;
+  if !has_external_refs(&spec.value) {
+    return Ok(None);
+  }
+  bundle(&mut spec.value, base_url, fetcher).await?;
+  Ok(Some(spec.to_string_pretty()?))
+}
+
+/// Resolve the external refs of a spec read from a url
+pub async fn bundle(
+  spec: &mut Value,
+  base_url: &Url,
+  fetcher: &SpecFetcher,
+) -> Result<(), SpecError> {
+  let documents = load_documents(spec, base_url, fetcher).await?;
+  let mut bundler = Bundler {
+    documents: &documents,
+    root_url: get_document_url(base_url),
+    is_swagger: spec.get("swagger").is_some(),
+    taken_pointers: get_component_pointers(spec),
+    hoisted: BTreeMap::new(),
+    hoisted_components: vec![],
+    inlining: vec![],
+  };
+  let root_url = bundler.root_url.clone();
+  bundler.rewrite(spec, &root_url, false)?;
+  let hoisted_count = bundler.hoisted_components.len();
+  for (pointer, component) in bundler.hoisted_components {
+    insert_at_pointer(spec, &pointer, component);
+  }
+  info!(
+    "Bundled {} external spec documents, hoisting {hoisted_count} components",
+    documents.len() - 1
+  );
+  Ok(())
+}
+
+/// Get the url of a document, without the fragment
+fn get_document_url(url: &Url) -> Url {
+  let mut url = url.clone();
+  url.set_fragment(None);
+  url
+}
+
+/// Load the spec and every document reachable through its external refs
+async fn load_documents(
+  spec: &Value,
+  base_url: &Url,
+  fetcher: &SpecFetcher,
+) -> Result<HashMap<Url, Value>, SpecError> {
+  let root_url = get_document_url(base_url);
+  let mut documents = HashMap::from([(root_url.clone(), spec.clone())]);
+  let mut pending = vec![root_url];
+  while let Some(document_url) = pending.pop() {
+    let mut refs = vec![];
+    collect_refs(&documents[&document_url], &mut refs);
+    for reference in refs.iter().filter(|r| !r.starts_with('#')) {
+      let url = document_url
+        .join(reference)
+        .map_err(|_| SpecError::InvalidRef(reference.to_string()))?;
+      let url = get_document_url(&url);
+      if !documents.contains_key(&url) {
+        let document =
+          load_document(&url, fetcher)
+            .await
+            .map_err(|source| SpecError::UnresolvedRef {
+              url: url.clone(),
+              source: Box::new(source),
+            })?;
+        documents.insert(url.clone(), document);
+        pending.push(url);
+      }
+    }
+  }
+  Ok(documents)
+}
+
+/// Load a document from a file or http url
+async fn load_document(
+  url: &Url,
+  fetcher: &SpecFetcher,
+) -> Result<Value, SpecError> {
+  let contents = match url.scheme() {
+    "file" => {
+      let path = url
+        .to_file_path()
+        .map_err(|_| SpecError::InvalidRef(url.to_string()))?;
+      fs::read_to_string(path).await?
+    }
+    _ => fetcher.fetch_text(url).await?,
+  };
+  Ok(SpecDocument::parse(&contents, SpecFormats::from_path(url.path()))?.value)
+}
+
+/// Get the pointers of the components of a spec
+fn get_component_pointers(spec: &Value) -> BTreeSet<String> {
+  let mut pointers = BTreeSet::new();
+  for prefix in ["/definitions", "/parameters", "/responses"] {
+    for name in spec
+      .pointer(prefix)
+      .and_then(Value::as_object)
+      .into_iter()
+      .flat_map(|map| map.keys())
+    {
+      pointers.insert(format!("{prefix}/{name}"));
+    }
+  }
+  for (kind, components) in spec
+    .pointer("/components")
+    .and_then(Value::as_object)
+    .into_iter()
+    .flatten()
+  {
+    for name in components
+      .as_object()
+      .into_iter()
+      .flat_map(|map| map.keys())
+    {
+      pointers.insert(format!("/components/{kind}/{name}"));
+    }
+  }
+  pointers
+}
+
+/// Rewrites the refs of a spec against the loaded documents
+struct Bundler<'a> {
+  documents: &'a HashMap<Url, Value>,
+  root_url: Url,
+  is_swagger: bool,
+  /// Pointers taken by components
+  taken_pointers: BTreeSet<String>,
+  /// Pointers of hoisted components, by target url
+  hoisted: BTreeMap<String, String>,
+  hoisted_components: Vec<(String, Value)>,
+  /// Target urls being inlined, to catch cycles
+  inlining: Vec<String>,
+}
+impl Bundler<'_> {
+  /// Get the pointer prefix of a component kind, if components of that kind can be hoisted
+  fn get_component_prefix(
+    &self,
+    kind: &str,
+  ) -> Option<String> {
+    match (self.is_swagger, kind) {
+      (true, "schemas") => Some("/definitions".to_string()),
+      (true, "parameters" | "responses") => Some(format!("/{kind}")),
+      (true, _) => None,
+      (false, _) => Some(format!("/components/{kind}")),
+    }
+  }
+
+  /// Get the kind and name a target is hoisted under, or nothing to inline it
+  fn get_hoisting(
+    &self,
+    url: &Url,
+    fragment: &str,
+    is_schema: bool,
+  ) -> Option<(String, String)> {
+    let segments = fragment
+      .strip_prefix('/')
+      .map(|pointer| pointer.split('/').collect::<Vec<_>>())
+      .unwrap_or_default();
+    let unescape = |segment: &str| segment.replace("~1", "/").replace("~0", "~");
+    let (kind, name) = match segments.as_slice() {
+      ["components", kind, name] => (kind.to_string(), unescape(name)),
+      ["definitions", name] => ("schemas".to_string(), unescape(name)),
+      [kind @ ("parameters" | "responses"), name] => (kind.to_string(), unescape(name)),
+      _ if is_schema => {
+        let name = match segments.last() {
+          Some(last) if !last.is_empty() => unescape(last),
+          _ => url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|file_name| file_name.split('.').next())
+            .unwrap_or("Schema")
+            .to_string(),
+        };
+        ("schemas".to_string(), name)
+      }
+      _ => return None,
+    };
+    Some((self.get_component_prefix(&kind)?, name))
+  }
+
+  /// Get a pointer for a new component that is not taken yet
+  fn get_free_pointer(
+    &mut self,
+    prefix: &str,
+    name: &str,
+  ) -> String {
+    let name = name
+      .chars()
+      .map(|c| {
+        if c.is_ascii_alphanumeric() || "._-".contains(c) {
+          c
+        } else {
+          '_'
+        }
+      })
+      .collect::<String>();
+    let mut pointer = format!("{prefix}/{name}");
+    let mut suffix = 1;
+    while self.taken_pointers.contains(&pointer) {
+      suffix += 1;
+      pointer = format!("{prefix}/{name}{suffix}");
+    }
+    self.taken_pointers.insert(pointer.clone());
+    pointer
+  }
+
+  /// Rewrite the refs in a value from the document at a url
+  fn rewrite(
+    &mut self,
+    value: &mut Value,
+    document_url: &Url,
+    is_schema: bool,
+  ) -> Result<(), SpecError> {
+    match value {
+      Value::Object(map) => {
+        if let Some(Value::String(reference)) = map.get("$ref") {
+          let reference = reference.to_string();
+          return self.rewrite_ref(map, &reference, document_url, is_schema);
+        }
+        for (key, child) in map.iter_mut() {
+          self.rewrite_child(child, key, document_url)?;
+        }
+        Ok(())
+      }
+      Value::Array(values) => {
+        for child in values.iter_mut() {
+          self.rewrite(child, document_url, is_schema)?;
+        }
+        Ok(())
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Rewrite the value of an object key, telling schemas apart by the key
+  fn rewrite_child(
+    &mut self,
+    child: &mut Value,
+    key: &str,
+    document_url: &Url,
+  ) -> Result<(), SpecError> {
+    if !SCHEMA_CONTAINER_KEYS.contains(&key) {
+      return self.rewrite(child, document_url, SCHEMA_KEYS.contains(&key));
+    }
+    match child {
+      Value::Object(entries) => {
+        for entry in entries.values_mut() {
+          self.rewrite(entry, document_url, true)?;
+        }
+      }
+      Value::Array(entries) => {
+        for entry in entries.iter_mut() {
+          self.rewrite(entry, document_url, true)?;
+        }
+      }
+      _ => {}
+    }
+    Ok(())
+  }
+
+  /// Rewrite a ref object
+  fn rewrite_ref(
+    &mut self,
+    map: &mut Map<String, Value>,
+    reference: &str,
+    document_url: &Url,
+    is_schema: bool,
+  ) -> Result<(), SpecError> {
+    if reference.starts_with('#') && *document_url == self.root_url {
+      return Ok(());
+    }
+    let target_url = document_url
+      .join(reference)
+      .map_err(|_| SpecError::InvalidRef(reference.to_string()))?;
+    let fragment = target_url.fragment().unwrap_or_default().to_string();
+    let url = get_document_url(&target_url);
+    if url == self.root_url {
+      map.insert("$ref".to_string(), Value::String(format!("#{fragment}")));
+      return Ok(());
+    }
+    let target_key = target_url.to_string();
+    if let Some(pointer) = self.hoisted.get(&target_key) {
+      map.insert("$ref".to_string(), Value::String(format!("#{pointer}")));
+      return Ok(());
+    }
+    let mut target = self
+      .documents
+      .get(&url)
+      .and_then(|document| document.pointer(&fragment))
+      .cloned()
+      .ok_or_else(|| SpecError::InvalidRef(target_key.clone()))?;
+    match self.get_hoisting(&url, &fragment, is_schema) {
+      Some((prefix, name)) => {
+        let pointer = self.get_free_pointer(&prefix, &name);
+        self.hoisted.insert(target_key, pointer.clone());
+        let is_target_schema = is_schema || prefix.ends_with("schemas") || prefix == "/definitions";
+        self.rewrite(&mut target, &url, is_target_schema)?;
+        self.hoisted_components.push((pointer.clone(), target));
+        map.insert("$ref".to_string(), Value::String(format!("#{pointer}")));
+      }
+      None => {
+        if self.inlining.contains(&target_key) {
+          return Err(SpecError::CircularRef(target_key));
+        }
+        self.inlining.push(target_key);
+        self.rewrite(&mut target, &url, is_schema)?;
+        let target_key = self.inlining.pop().unwrap_or_default();
+        let Value::Object(target) = target else {
+          return Err(SpecError::InvalidRef(target_key));
+        };
+        map.shift_remove("$ref");
+        for (key, value) in target {
+          map.entry(key).or_insert(value);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Insert a value at a pointer, creating missing parent objects
+fn insert_at_pointer(
+  spec: &mut Value,
+  pointer: &str,
+  value: Value,
+) {
+  let mut segments = pointer.split('/').skip(1).collect::<Vec<_>>();
+  let Some(name) = segments.pop() else {
+    return;
+  };
+  let mut node = spec;
+  for segment in segments {
+    let Some(map) = node.as_object_mut() else {
+      return;
+    };
+    node = map
+      .entry(segment.to_string())
+      .or_insert_with(|| Value::Object(Map::new()));
+  }
+  if let Some(map) = node.as_object_mut() {
+    map.insert(name.to_string(), value);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[tokio::test]
+  async fn external_refs_are_hoisted_or_inlined() {
+    let dir = std::env::temp_dir().join(format!("olg_bundles_{}", std::process::id()));
+    fs::create_dir_all(dir.join("schemas"))
+      .await
+      .expect("must create dirs");
+    let files = [
+      (
+        "schemas/pet.yaml",
+        "properties:\n  owner:\n    $ref: ./owner.yaml\n",
+      ),
+      (
+        "schemas/owner.yaml",
+        "properties:\n  pets:\n    items:\n      $ref: pet.yaml\n",
+      ),
+      (
+        "common.yaml",
+        "components:\n  parameters:\n    Limit:\n      name: limit\n      in: query\nNotFound:\n  description: Not found\n",
+      ),
+    ];
+    for (path, contents) in files {
+      fs::write(dir.join(path), contents)
+        .await
+        .expect("must write");
+    }
+    let mut spec = json!({
+      "openapi": "3.0.3",
+      "paths": {
+        "/pets": {
+          "get": {
+            "parameters": [{ "$ref": "common.yaml#/components/parameters/Limit" }],
+            "responses": {
+              "200": {
+                "description": "Pets",
+                "content": { "application/json": { "schema": { "$ref": "./schemas/pet.yaml" } } }
+              },
+              "404": { "$ref": "common.yaml#/NotFound" }
+            }
+          }
+        }
+      },
+      "components": { "schemas": { "pet": { "type": "string" } } }
+    });
+    let base_url = Url::from_file_path(dir.join("openapi.yaml")).expect("must get url");
+    bundle(&mut spec, &base_url, &SpecFetcher::default())
+      .await
+      .expect("must bundle");
+    assert!(!has_external_refs(&spec));
+    let get = &spec["paths"]["/pets"]["get"];
+    assert_eq!(
+      get["parameters"][0],
+      json!({ "$ref": "#/components/parameters/Limit" })
+    );
+    assert_eq!(
+      get["responses"]["404"],
+      json!({ "description": "Not found" })
+    );
+    assert_eq!(
+      get["responses"]["200"]["content"]["application/json"]["schema"],
+      json!({ "$ref": "#/components/schemas/pet2" })
+    );
+    let schemas = &spec["components"]["schemas"];
+    assert_eq!(schemas["pet"], json!({ "type": "string" }));
+    assert_eq!(
+      schemas["pet2"]["properties"]["owner"],
+      json!({ "$ref": "#/components/schemas/owner" })
+    );
+    assert_eq!(
+      schemas["owner"]["properties"]["pets"]["items"],
+      json!({ "$ref": "#/components/schemas/pet2" })
+    );
+    fs::remove_dir_all(dir).await.expect("must clean up");
+  }
+
+  #[tokio::test]
+  async fn circular_inlined_refs_fail() {
+    let dir = std::env::temp_dir().join(format!("olg_bundles_circular_{}", std::process::id()));
+    fs::create_dir_all(&dir).await.expect("must create dir");
+    fs::write(
+      dir.join("responses.yaml"),
+      "NotFound:\n  description: Not found\n  links:\n    $ref: '#/Gone'\nGone:\n  next:\n    $ref: '#/NotFound'\n",
+    )
+    .await
+    .expect("must write");
+    let mut spec = json!({
+      "openapi": "3.0.3",
+      "paths": {
+        "/pets": {
+          "get": { "responses": { "404": { "$ref": "responses.yaml#/NotFound" } } }
+        }
+      }
+    });
+    let base_url = Url::from_file_path(dir.join("openapi.yaml")).expect("must get url");
+    let result = bundle(&mut spec, &base_url, &SpecFetcher::default()).await;
+    assert!(
+      matches!(&result, Err(SpecError::CircularRef(key)) if key.ends_with("responses.yaml#/NotFound")),
+      "{result:?}"
+    );
+    fs::remove_dir_all(dir).await.expect("must clean up");
+  }
+}
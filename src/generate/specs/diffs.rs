@@ -0,0 +1,837 @@
+//! Spec diffs
+//!
+//! Compares two specs and classifies every change as breaking or not for the client crate:
+//! removed operations, parameters, responses, schemas and properties, new required parameters
+//! and properties, type, format and nullability changes, and removed enum values are breaking.
+//! Additions are not.
+//!
+//! Swagger 2.0 specs are converted to OpenAPI 3.0 before being compared.
+use super::{swaggers, SpecDocument, SpecError, SpecPreparer};
+use crate::{cli::Cli, reports};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+  collections::BTreeSet,
+  fmt::{self, Display, Formatter},
+  path::Path,
+};
+use tracing::{debug, info};
+
+/// How deep schemas are compared through refs and nesting
+const MAX_SCHEMA_DEPTH: usize = 16;
+
+/// Keys of a path item holding operations
+const HTTP_METHODS: [&str; 8] = [
+  "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Spec diff output formats
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecDiffFormats {
+  /// Human-readable lines
+  #[default]
+  Text,
+  /// A JSON object
+  Json,
+}
+
+/// What a change does to the operation or schema at its location
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecChangeKinds {
+  /// The whole operation or schema was added
+  Added,
+  /// The whole operation or schema was removed
+  Removed,
+  /// Something in the operation or schema changed
+  #[default]
+  Changed,
+}
+
+/// A change between two specs
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SpecChange {
+  /// Whether the change breaks the client crate
+  pub breaking: bool,
+  #[serde(default)]
+  pub kind: SpecChangeKinds,
+  /// Where the change is, like `GET /pets parameter query.limit` or `schemas.Pet.name`
+  pub location: String,
+  pub description: String,
+}
+impl Display for SpecChange {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "{}: {}", self.location, self.description)
+  }
+}
+
+/// The changes between two specs
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SpecDiff {
+  pub breaking_changes: usize,
+  pub non_breaking_changes: usize,
+  /// Whether the specs differ at all, including in ways that do not affect the client like
+  /// descriptions
+  pub changed: bool,
+  pub changes: Vec<SpecChange>,
+}
+impl SpecDiff {
+  /// Compare an old spec with a new one
+  pub fn new(
+    old_spec: &Value,
+    new_spec: &Value,
+  ) -> Self {
+    let mut differ = SpecDiffer {
+      old_spec,
+      new_spec,
+      changes: vec![],
+    };
+    differ.diff_operations();
+    differ.diff_component_schemas();
+    let changes = differ.changes;
+    let breaking_changes = changes.iter().filter(|change| change.breaking).count();
+    let without_marker = |spec: &Value| {
+      let mut spec = spec.clone();
+      if let Some(root) = spec.as_object_mut() {
+        root.shift_remove(SpecPreparer::ORIGINAL_SPEC_KEY);
+      }
+      spec
+    };
+    Self {
+      breaking_changes,
+      non_breaking_changes: changes.len() - breaking_changes,
+      changed: without_marker(old_spec) != without_marker(new_spec),
+      changes,
+    }
+  }
+
+  /// Read and compare two spec files
+  pub async fn try_from_paths(
+    old_spec_path: impl AsRef<Path>,
+    new_spec_path: impl AsRef<Path>,
+  ) -> Result<Self, SpecError> {
+    let old_spec = read_comparable_spec(old_spec_path).await?;
+    let new_spec = read_comparable_spec(new_spec_path).await?;
+    Ok(Self::new(&old_spec, &new_spec))
+  }
+
+  /// Check whether any change breaks the client crate
+  pub fn is_breaking(&self) -> bool {
+    self.breaking_changes > 0
+  }
+
+  /// Format in the given format
+  pub fn to_formatted_string(
+    &self,
+    format: SpecDiffFormats,
+  ) -> Result<String, SpecError> {
+    Ok(match format {
+      SpecDiffFormats::Text => self.to_string(),
+      SpecDiffFormats::Json => serde_json::to_string_pretty(self)?,
+    })
+  }
+}
+impl Display for SpecDiff {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    if self.changes.is_empty() && self.changed {
+      return write!(f, "No spec changes affecting the client crate");
+    }
+    if self.changes.is_empty() {
+      return write!(f, "No spec changes");
+    }
+    write!(
+      f,
+      "{} breaking and {} non-breaking spec changes",
+      self.breaking_changes, self.non_breaking_changes
+    )?;
+    for (breaking, title) in [(true, "Breaking"), (false, "Non-breaking")] {
+      let mut changes = self
+        .changes
+        .iter()
+        .filter(|change| change.breaking == breaking)
+        .peekable();
+      if changes.peek().is_some() {
+        write!(f, "\n{title}:")?;
+        for change in changes {
+          write!(f, "\n  - {change}")?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Read a spec to compare, converting Swagger 2.0 specs
+pub async fn read_comparable_spec(path: impl AsRef<Path>) -> Result<Value, SpecError> {
+  let SpecDocument { value, .. } = SpecDocument::read_from_path(path).await?;
+  Ok(if swaggers::is_swagger(&value) {
+    swaggers::convert_swagger(&value).spec
+  } else {
+    value
+  })
+}
+
+/// Read the spec of the crate being regenerated, to compare it with the regenerated one
+pub async fn read_crate_spec(cli: &Cli) -> Option<Value> {
+  let spec_file_path = cli
+    .get_output_project_dir()
+    .join(cli.try_get_spec_file_name().ok()?);
+  read_comparable_spec(&spec_file_path)
+    .await
+    .map_err(|e| debug!("No previous spec to compare at {spec_file_path:?}: {e}"))
+    .ok()
+}
+
+/// Compare the regenerated spec of the crate with the previous one, logging and recording the diff
+pub async fn report_crate_spec_changes(
+  cli: &Cli,
+  previous_spec: &Value,
+) -> Result<SpecDiff, SpecError> {
+  let Some(spec) = read_crate_spec(cli).await else {
+    return Ok(SpecDiff::default());
+  };
+  let spec_diff = SpecDiff::new(previous_spec, &spec);
+  info!("{spec_diff}");
+  if spec_diff.is_breaking() {
+    reports::record_warning(format!(
+      "The spec has {} breaking changes for the client crate",
+      spec_diff.breaking_changes
+    ));
+  }
+  reports::record_spec_diff(&spec_diff);
+  Ok(spec_diff)
+}
+
+/// Compares two specs, collecting changes
+struct SpecDiffer<'a> {
+  old_spec: &'a Value,
+  new_spec: &'a Value,
+  changes: Vec<SpecChange>,
+}
+impl<'a> SpecDiffer<'a> {
+  /// Record a change within an operation or schema
+  fn push(
+    &mut self,
+    breaking: bool,
+    location: &str,
+    description: impl Into<String>,
+  ) {
+    self.push_kind(SpecChangeKinds::Changed, breaking, location, description)
+  }
+
+  /// Record a change of a given kind
+  fn push_kind(
+    &mut self,
+    kind: SpecChangeKinds,
+    breaking: bool,
+    location: &str,
+    description: impl Into<String>,
+  ) {
+    self.changes.push(SpecChange {
+      breaking,
+      kind,
+      location: location.to_string(),
+      description: description.into(),
+    });
+  }
+
+  /// Compare the operations of both specs
+  fn diff_operations(&mut self) {
+    let old_operations = get_operations(self.old_spec);
+    let new_operations = get_operations(self.new_spec);
+    for (location, old_operation) in old_operations.iter() {
+      let Some(new_operation) = find_operation(&new_operations, location) else {
+        self.push_kind(
+          SpecChangeKinds::Removed,
+          true,
+          location,
+          "operation removed",
+        );
+        continue;
+      };
+      self.diff_operation(location, old_operation, new_operation);
+    }
+    for (location, _) in new_operations.iter() {
+      if find_operation(&old_operations, location).is_none() {
+        self.push_kind(SpecChangeKinds::Added, false, location, "operation added");
+      }
+    }
+  }
+
+  /// Compare an operation
+  fn diff_operation(
+    &mut self,
+    location: &str,
+    old_operation: &Operation<'a>,
+    new_operation: &Operation<'a>,
+  ) {
+    let get_operation_id = |operation: &Operation| {
+      operation
+        .value
+        .get("operationId")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+    };
+    let old_operation_id_opt = get_operation_id(old_operation);
+    let new_operation_id_opt = get_operation_id(new_operation);
+    if let (Some(old_operation_id), Some(new_operation_id)) =
+      (old_operation_id_opt, new_operation_id_opt)
+    {
+      if old_operation_id != new_operation_id {
+        self.push(
+          true,
+          location,
+          format!("operationId renamed from `{old_operation_id}` to `{new_operation_id}`"),
+        );
+      }
+    }
+    self.diff_parameters(location, old_operation, new_operation);
+    self.diff_request_bodies(location, old_operation.value, new_operation.value);
+    self.diff_responses(location, old_operation.value, new_operation.value);
+  }
+
+  /// Compare the parameters of an operation
+  fn diff_parameters(
+    &mut self,
+    location: &str,
+    old_operation: &Operation<'a>,
+    new_operation: &Operation<'a>,
+  ) {
+    let old_parameters = old_operation.get_parameters(self.old_spec);
+    let new_parameters = new_operation.get_parameters(self.new_spec);
+    let is_required = |parameter: &Value| parameter.get("required") == Some(&Value::Bool(true));
+    for (key, old_parameter) in old_parameters.iter() {
+      let parameter_location = format!("{location} parameter {key}");
+      let Some(new_parameter) = new_parameters.get(key) else {
+        self.push(true, &parameter_location, "parameter removed");
+        continue;
+      };
+      match (is_required(old_parameter), is_required(new_parameter)) {
+        (false, true) => self.push(true, &parameter_location, "parameter became required"),
+        (true, false) => self.push(false, &parameter_location, "parameter became optional"),
+        _ => {}
+      }
+      self.diff_schemas(
+        &parameter_location,
+        old_parameter.get("schema"),
+        new_parameter.get("schema"),
+        0,
+      );
+    }
+    for (key, new_parameter) in new_parameters.iter() {
+      if !old_parameters.contains_key(key) {
+        let parameter_location = format!("{location} parameter {key}");
+        if is_required(new_parameter) {
+          self.push(true, &parameter_location, "required parameter added");
+        } else {
+          self.push(false, &parameter_location, "optional parameter added");
+        }
+      }
+    }
+  }
+
+  /// Compare the request bodies of an operation
+  fn diff_request_bodies(
+    &mut self,
+    location: &str,
+    old_operation: &Value,
+    new_operation: &Value,
+  ) {
+    let location = format!("{location} request body");
+    let old_request_body_opt = old_operation
+      .get("requestBody")
+      .map(|request_body| resolve(self.old_spec, request_body));
+    let new_request_body_opt = new_operation
+      .get("requestBody")
+      .map(|request_body| resolve(self.new_spec, request_body));
+    let is_required =
+      |request_body: &Value| request_body.get("required") == Some(&Value::Bool(true));
+    match (old_request_body_opt, new_request_body_opt) {
+      (None, None) => {}
+      (None, Some(new_request_body)) => self.push(
+        is_required(new_request_body),
+        &location,
+        "request body added",
+      ),
+      (Some(_), None) => self.push(true, &location, "request body removed"),
+      (Some(old_request_body), Some(new_request_body)) => {
+        if !is_required(old_request_body) && is_required(new_request_body) {
+          self.push(true, &location, "request body became required");
+        }
+        self.diff_contents(&location, old_request_body, new_request_body);
+      }
+    }
+  }
+
+  /// Compare the responses of an operation
+  fn diff_responses(
+    &mut self,
+    location: &str,
+    old_operation: &Value,
+    new_operation: &Value,
+  ) {
+    let get_responses = |operation: &Value| {
+      operation
+        .get("responses")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default()
+    };
+    let old_responses = get_responses(old_operation);
+    let new_responses = get_responses(new_operation);
+    for (status, old_response) in old_responses.iter() {
+      let response_location = format!("{location} response {status}");
+      let Some(new_response) = new_responses.get(status) else {
+        self.push(true, &response_location, "response removed");
+        continue;
+      };
+      let old_response = resolve(self.old_spec, old_response).clone();
+      let new_response = resolve(self.new_spec, new_response).clone();
+      self.diff_contents(&response_location, &old_response, &new_response);
+    }
+    for status in new_responses.keys() {
+      if !old_responses.contains_key(status) {
+        let response_location = format!("{location} response {status}");
+        self.push(false, &response_location, "response added");
+      }
+    }
+  }
+
+  /// Compare the schemas of the media types of a request body or response
+  fn diff_contents(
+    &mut self,
+    location: &str,
+    old: &Value,
+    new: &Value,
+  ) {
+    let empty = Map::new();
+    let old_content = old
+      .get("content")
+      .and_then(Value::as_object)
+      .unwrap_or(&empty);
+    let new_content = new
+      .get("content")
+      .and_then(Value::as_object)
+      .unwrap_or(&empty);
+    let common_media_type_opt = old_content
+      .keys()
+      .find(|media_type| new_content.contains_key(*media_type));
+    match common_media_type_opt {
+      Some(media_type) => self.diff_schemas(
+        location,
+        old_content[media_type].get("schema"),
+        new_content[media_type].get("schema"),
+        0,
+      ),
+      None if old_content.is_empty() && !new_content.is_empty() => {
+        self.push(false, location, "content added")
+      }
+      None if old_content.is_empty() => {}
+      None => self.push(
+        true,
+        location,
+        format!(
+          "media types changed from {} to {}",
+          join_keys(old_content),
+          join_keys(new_content)
+        ),
+      ),
+    }
+  }
+
+  /// Compare the schemas of both specs' components
+  fn diff_component_schemas(&mut self) {
+    let empty = Map::new();
+    let get_schemas = |spec: &'a Value| {
+      spec
+        .pointer("/components/schemas")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty)
+        .clone()
+    };
+    let old_schemas = get_schemas(self.old_spec);
+    let new_schemas = get_schemas(self.new_spec);
+    for (name, old_schema) in old_schemas.iter() {
+      let location = format!("schemas.{name}");
+      match new_schemas.get(name) {
+        Some(new_schema) => self.diff_schemas(&location, Some(old_schema), Some(new_schema), 0),
+        None => self.push_kind(SpecChangeKinds::Removed, true, &location, "schema removed"),
+      }
+    }
+    for name in new_schemas.keys() {
+      if !old_schemas.contains_key(name) {
+        self.push_kind(
+          SpecChangeKinds::Added,
+          false,
+          &format!("schemas.{name}"),
+          "schema added",
+        );
+      }
+    }
+  }
+
+  /// Compare two schemas
+  ///
+  /// Refs to the same component are not followed, the component itself is compared once.
+  fn diff_schemas(
+    &mut self,
+    location: &str,
+    old_schema_opt: Option<&Value>,
+    new_schema_opt: Option<&Value>,
+    depth: usize,
+  ) {
+    let (Some(old_schema), Some(new_schema)) = (old_schema_opt, new_schema_opt) else {
+      if old_schema_opt.is_some() != new_schema_opt.is_some() {
+        self.push(true, location, "schema changed");
+      }
+      return;
+    };
+    if depth > MAX_SCHEMA_DEPTH {
+      return;
+    }
+    let get_ref = |schema: &Value| {
+      schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+    };
+    match (get_ref(old_schema), get_ref(new_schema)) {
+      (Some(old_ref), Some(new_ref)) if old_ref == new_ref => return,
+      (Some(old_ref), Some(new_ref)) => {
+        self.push(
+          true,
+          location,
+          format!(
+            "type changed from `{}` to `{}`",
+            get_ref_name(&old_ref),
+            get_ref_name(&new_ref)
+          ),
+        );
+        return;
+      }
+      (None, None) => {}
+      _ => {
+        let old_schema = resolve(self.old_spec, old_schema);
+        let new_schema = resolve(self.new_spec, new_schema);
+        self.diff_schemas(location, Some(old_schema), Some(new_schema), depth + 1);
+        return;
+      }
+    }
+    for keyword in ["type", "format"] {
+      let old_value_opt = old_schema.get(keyword);
+      let new_value_opt = new_schema.get(keyword);
+      if old_value_opt != new_value_opt {
+        self.push(
+          true,
+          location,
+          format!(
+            "{keyword} changed from {} to {}",
+            format_value_opt(old_value_opt),
+            format_value_opt(new_value_opt)
+          ),
+        );
+        return;
+      }
+    }
+    let is_nullable = |schema: &Value| schema.get("nullable") == Some(&Value::Bool(true));
+    if is_nullable(old_schema) != is_nullable(new_schema) {
+      let description = if is_nullable(new_schema) {
+        "became nullable"
+      } else {
+        "is no longer nullable"
+      };
+      self.push(true, location, description);
+    }
+    self.diff_enums(location, old_schema, new_schema);
+    for keyword in ["allOf", "oneOf", "anyOf"] {
+      if old_schema.get(keyword) != new_schema.get(keyword) {
+        self.push(true, location, format!("`{keyword}` changed"));
+      }
+    }
+    self.diff_properties(location, old_schema, new_schema, depth);
+    self.diff_schemas(
+      &format!("{location}[]"),
+      old_schema.get("items"),
+      new_schema.get("items"),
+      depth + 1,
+    );
+  }
+
+  /// Compare the enum values of two schemas
+  fn diff_enums(
+    &mut self,
+    location: &str,
+    old_schema: &Value,
+    new_schema: &Value,
+  ) {
+    let get_values = |schema: &Value| {
+      schema
+        .get("enum")
+        .and_then(Value::as_array)
+        .map(|values| values.iter().map(Value::to_string).collect::<BTreeSet<_>>())
+    };
+    match (get_values(old_schema), get_values(new_schema)) {
+      (Some(old_values), Some(new_values)) => {
+        for value in old_values.difference(&new_values) {
+          self.push(true, location, format!("enum value {value} removed"));
+        }
+        for value in new_values.difference(&old_values) {
+          self.push(false, location, format!("enum value {value} added"));
+        }
+      }
+      (None, Some(_)) => self.push(true, location, "values restricted to an enum"),
+      (Some(_), None) => self.push(false, location, "values no longer restricted to an enum"),
+      (None, None) => {}
+    }
+  }
+
+  /// Compare the properties of two object schemas
+  fn diff_properties(
+    &mut self,
+    location: &str,
+    old_schema: &Value,
+    new_schema: &Value,
+    depth: usize,
+  ) {
+    let empty = Map::new();
+    let old_properties = old_schema
+      .get("properties")
+      .and_then(Value::as_object)
+      .unwrap_or(&empty);
+    let new_properties = new_schema
+      .get("properties")
+      .and_then(Value::as_object)
+      .unwrap_or(&empty);
+    let is_required = |schema: &Value, name: &str| {
+      schema
+        .get("required")
+        .and_then(Value::as_array)
+        .is_some_and(|required| required.iter().any(|r| r == name))
+    };
+    for (name, old_property) in old_properties {
+      let property_location = format!("{location}.{name}");
+      let Some(new_property) = new_properties.get(name) else {
+        self.push(true, &property_location, "property removed");
+        continue;
+      };
+      match (is_required(old_schema, name), is_required(new_schema, name)) {
+        (false, true) => self.push(true, &property_location, "property became required"),
+        (true, false) => self.push(true, &property_location, "property became optional"),
+        _ => {}
+      }
+      self.diff_schemas(
+        &property_location,
+        Some(old_property),
+        Some(new_property),
+        depth + 1,
+      );
+    }
+    for name in new_properties.keys() {
+      if !old_properties.contains_key(name) {
+        let property_location = format!("{location}.{name}");
+        if is_required(new_schema, name) {
+          self.push(true, &property_location, "required property added");
+        } else {
+          self.push(false, &property_location, "optional property added");
+        }
+      }
+    }
+  }
+}
+
+/// An operation with the parameters of its path item
+struct Operation<'a> {
+  value: &'a Value,
+  path_item: &'a Value,
+}
+impl<'a> Operation<'a> {
+  /// Get the resolved parameters by `<in>.<name>`, the operation's overriding the path item's
+  fn get_parameters(
+    &self,
+    spec: &'a Value,
+  ) -> Map<String, Value> {
+    [self.path_item, self.value]
+      .into_iter()
+      .filter_map(|value| value.get("parameters").and_then(Value::as_array))
+      .flatten()
+      .map(|parameter| resolve(spec, parameter))
+      .filter_map(|parameter| {
+        let name = parameter.get("name").and_then(Value::as_str)?;
+        let r#in = parameter.get("in").and_then(Value::as_str)?;
+        Some((format!("{in}.{name}"), parameter.clone()))
+      })
+      .collect()
+  }
+}
+
+/// Get the operations of a spec with their `<METHOD> <path>`
+fn get_operations(spec: &Value) -> Vec<(String, Operation<'_>)> {
+  spec
+    .get("paths")
+    .and_then(Value::as_object)
+    .into_iter()
+    .flatten()
+    .flat_map(|(path, path_item)| {
+      HTTP_METHODS.iter().filter_map(move |method| {
+        let value = path_item.get(*method)?;
+        Some((
+          format!("{} {path}", method.to_uppercase()),
+          Operation { value, path_item },
+        ))
+      })
+    })
+    .collect()
+}
+
+/// Find an operation by its `<METHOD> <path>`
+fn find_operation<'o, 'a>(
+  operations: &'o [(String, Operation<'a>)],
+  location: &str,
+) -> Option<&'o Operation<'a>> {
+  operations
+    .iter()
+    .find(|(other_location, _)| other_location == location)
+    .map(|(_, operation)| operation)
+}
+
+/// Follow local refs to non-schema objects
+fn resolve<'a>(
+  spec: &'a Value,
+  mut value: &'a Value,
+) -> &'a Value {
+  for _ in 0..MAX_SCHEMA_DEPTH {
+    let Some(target) = value
+      .get("$ref")
+      .and_then(Value::as_str)
+      .and_then(|reference| reference.strip_prefix('#'))
+      .and_then(|pointer| spec.pointer(pointer))
+    else {
+      break;
+    };
+    value = target;
+  }
+  value
+}
+
+/// Get the component name a ref points to
+fn get_ref_name(reference: &str) -> &str {
+  reference.rsplit('/').next().unwrap_or(reference)
+}
+
+/// Format an optional value for a change description
+fn format_value_opt(value_opt: Option<&Value>) -> String {
+  match value_opt {
+    Some(value) => value.to_string(),
+    None => "none".to_string(),
+  }
+}
+
+/// Join the keys of a map for a change description
+fn join_keys(map: &Map<String, Value>) -> String {
+  map.keys().cloned().collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn changes_are_classified_as_breaking_or_not() {
+    let old_spec: Value = serde_yaml::from_str(
+      r##"
+      openapi: 3.0.3
+      paths:
+        /pets:
+          get:
+            operationId: listPets
+            parameters:
+              - { name: limit, in: query, schema: { type: integer } }
+            responses:
+              "200":
+                description: Pets
+                content:
+                  application/json: { schema: { $ref: "#/components/schemas/Pet" } }
+        /pets/{id}:
+          delete:
+            operationId: deletePet
+            responses: { "204": { description: Deleted } }
+      components:
+        schemas:
+          Pet:
+            type: object
+            required: [id]
+            properties:
+              id: { type: integer }
+              status: { type: string, enum: [available, sold] }
+      "##,
+    )
+    .expect("must parse");
+    let new_spec: Value = serde_yaml::from_str(
+      r##"
+      openapi: 3.0.3
+      paths:
+        /pets:
+          get:
+            operationId: listPets
+            parameters:
+              - { name: limit, in: query, schema: { type: integer } }
+              - { name: owner, in: query, required: true, schema: { type: string } }
+            responses:
+              "200":
+                description: Pets
+                content:
+                  application/json: { schema: { $ref: "#/components/schemas/Pet" } }
+      components:
+        schemas:
+          Pet:
+            type: object
+            required: [id]
+            properties:
+              id: { type: string }
+              status: { type: string, enum: [available, pending] }
+              name: { type: string }
+      "##,
+    )
+    .expect("must parse");
+    let spec_diff = SpecDiff::new(&old_spec, &new_spec);
+    assert_eq!(
+      spec_diff
+        .changes
+        .iter()
+        .map(|change| (change.breaking, change.to_string()))
+        .collect::<Vec<_>>(),
+      vec![
+        (
+          true,
+          "GET /pets parameter query.owner: required parameter added".to_string()
+        ),
+        (true, "DELETE /pets/{id}: operation removed".to_string()),
+        (
+          true,
+          "schemas.Pet.id: type changed from \"integer\" to \"string\"".to_string()
+        ),
+        (
+          true,
+          "schemas.Pet.status: enum value \"sold\" removed".to_string()
+        ),
+        (
+          false,
+          "schemas.Pet.status: enum value \"pending\" added".to_string()
+        ),
+        (
+          false,
+          "schemas.Pet.name: optional property added".to_string()
+        ),
+      ]
+    );
+    assert_eq!(spec_diff.breaking_changes, 4);
+    assert!(spec_diff.is_breaking());
+  }
+}
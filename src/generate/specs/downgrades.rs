@@ -0,0 +1,337 @@
+//! OpenAPI 3.1 to 3.0 downgrade
+//!
+//! The upstream Rust generator handles OpenAPI 3.1 poorly, so 3.1 specs can be rewritten into
+//! their 3.0 equivalent: `type: [string, "null"]` becomes `type: string` with `nullable: true`,
+//! `const` a single-value `enum`, schema `examples` an `example`, numeric `exclusiveMinimum` and
+//! `exclusiveMaximum` their boolean form, and `$ref`s with siblings an `allOf`. Constructs with no
+//! 3.0 equivalent, like `prefixItems` or `webhooks`, are listed as [Unconverted].
+use super::escape_pointer_segment as escape;
+use serde_json::{json, Map, Value};
+use std::fmt::{self, Display, Formatter};
+
+/// The OpenAPI version specs are downgraded to
+pub const DOWNGRADED_OPENAPI_VERSION: &str = "3.0.3";
+
+/// Schema keywords holding a subschema
+const SUBSCHEMA_KEYWORDS: [&str; 3] = ["items", "not", "additionalProperties"];
+
+/// Schema keywords holding a map of subschemas
+const SUBSCHEMA_MAP_KEYWORDS: [&str; 1] = ["properties"];
+
+/// Schema keywords holding a list of subschemas
+const SUBSCHEMA_LIST_KEYWORDS: [&str; 3] = ["allOf", "anyOf", "oneOf"];
+
+/// JSON Schema keywords OpenAPI 3.0 has no equivalent for
+const UNSUPPORTED_SCHEMA_KEYWORDS: [&str; 17] = [
+  "$anchor",
+  "$defs",
+  "$dynamicAnchor",
+  "$dynamicRef",
+  "$id",
+  "contains",
+  "dependentRequired",
+  "dependentSchemas",
+  "else",
+  "if",
+  "maxContains",
+  "minContains",
+  "patternProperties",
+  "prefixItems",
+  "propertyNames",
+  "then",
+  "unevaluatedProperties",
+];
+
+/// A 3.1 construct left as is or dropped by the downgrade
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unconverted {
+  /// JSON pointer of the construct
+  pub pointer: String,
+  pub reason: String,
+}
+impl Display for Unconverted {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    write!(f, "{} at `{}`", self.reason, self.pointer)
+  }
+}
+
+/// Check whether a spec is an OpenAPI 3.1 document
+pub fn is_openapi_3_1(spec: &Value) -> bool {
+  spec
+    .get("openapi")
+    .and_then(Value::as_str)
+    .is_some_and(|version| version.starts_with("3.1"))
+}
+
+/// Downgrade an OpenAPI 3.1 spec to 3.0, listing what could not be converted
+pub fn downgrade(spec: &mut Value) -> Vec<Unconverted> {
+  let mut unconverted = vec![];
+  let Some(root) = spec.as_object_mut() else {
+    return unconverted;
+  };
+  root.insert("openapi".to_string(), json!(DOWNGRADED_OPENAPI_VERSION));
+  root.shift_remove("jsonSchemaDialect");
+  for (pointer, reason) in [
+    ("/webhooks", "Dropped webhooks"),
+    ("/components/pathItems", "Dropped path item components"),
+    ("/info/summary", "Dropped the info summary"),
+    (
+      "/info/license/identifier",
+      "Dropped the SPDX license identifier",
+    ),
+  ] {
+    if super::remove_pointer(spec, pointer) {
+      unconverted.push(Unconverted {
+        pointer: pointer.to_string(),
+        reason: reason.to_string(),
+      });
+    }
+  }
+  if let Some(root) = spec.as_object_mut() {
+    root.entry("paths".to_string()).or_insert_with(|| json!({}));
+  }
+  downgrade_node(spec, "", &mut unconverted);
+  unconverted
+}
+
+/// Walk a non-schema node, downgrading the schemas found in it
+fn downgrade_node(
+  node: &mut Value,
+  pointer: &str,
+  unconverted: &mut Vec<Unconverted>,
+) {
+  match node {
+    Value::Object(map) => {
+      for (key, value) in map.iter_mut() {
+        let child_pointer = format!("{pointer}/{}", escape(key));
+        if key == "schema" || pointer == "/components/schemas" {
+          downgrade_schema(value, &child_pointer, unconverted);
+        } else if !key.starts_with("x-") {
+          downgrade_node(value, &child_pointer, unconverted);
+        }
+      }
+    }
+    Value::Array(values) => {
+      for (i, value) in values.iter_mut().enumerate() {
+        downgrade_node(value, &format!("{pointer}/{i}"), unconverted);
+      }
+    }
+    _ => {}
+  }
+}
+
+/// Downgrade a schema and its subschemas
+fn downgrade_schema(
+  schema: &mut Value,
+  pointer: &str,
+  unconverted: &mut Vec<Unconverted>,
+) {
+  let Some(map) = schema.as_object_mut() else {
+    return;
+  };
+  let mut unconvertible = |reason: String| {
+    unconverted.push(Unconverted {
+      pointer: pointer.to_string(),
+      reason,
+    })
+  };
+  downgrade_type(map, &mut unconvertible);
+  downgrade_null_variants(map);
+  if let Some(value) = map.shift_remove("const") {
+    if value.is_null() {
+      map.insert("nullable".to_string(), json!(true));
+    }
+    if map.contains_key("enum") {
+      unconvertible("Dropped `const` next to `enum`".to_string());
+    } else {
+      map.insert("enum".to_string(), json!([value]));
+    }
+  }
+  if let Some(examples) = map.shift_remove("examples") {
+    match examples {
+      Value::Array(mut examples) if !examples.is_empty() => {
+        if examples.len() > 1 {
+          unconvertible(format!(
+            "Kept only the first of {} schema examples",
+            examples.len()
+          ));
+        }
+        map.insert("example".to_string(), examples.swap_remove(0));
+      }
+      Value::Array(_) => {}
+      examples => {
+        map.insert("examples".to_string(), examples);
+      }
+    }
+  }
+  for (exclusive_keyword, keyword) in [
+    ("exclusiveMinimum", "minimum"),
+    ("exclusiveMaximum", "maximum"),
+  ] {
+    if let Some(limit) = map
+      .get(exclusive_keyword)
+      .filter(|v| v.is_number())
+      .cloned()
+    {
+      map.insert(keyword.to_string(), limit);
+      map.insert(exclusive_keyword.to_string(), json!(true));
+    }
+  }
+  if let Some(encoding) = map.shift_remove("contentEncoding") {
+    match encoding.as_str() {
+      Some("base64") => {
+        map.insert("format".to_string(), json!("byte"));
+      }
+      _ => unconvertible(format!("Dropped `contentEncoding: {encoding}`")),
+    }
+  }
+  if map.shift_remove("contentMediaType").is_some() && !map.contains_key("format") {
+    map.insert("format".to_string(), json!("binary"));
+  }
+  if map.contains_key("$ref") && map.len() > 1 {
+    let reference = map.shift_remove("$ref").unwrap_or_default();
+    let all_of = map.entry("allOf".to_string()).or_insert_with(|| json!([]));
+    if let Some(all_of) = all_of.as_array_mut() {
+      all_of.insert(0, json!({ "$ref": reference }));
+    }
+  }
+  for keyword in UNSUPPORTED_SCHEMA_KEYWORDS {
+    if map.contains_key(keyword) {
+      unconvertible(format!("Kept unsupported `{keyword}`"));
+    }
+  }
+  for (key, value) in map.iter_mut() {
+    let child_pointer = format!("{pointer}/{}", escape(key));
+    if SUBSCHEMA_KEYWORDS.contains(&key.as_str()) {
+      downgrade_schema(value, &child_pointer, unconverted);
+    } else if SUBSCHEMA_MAP_KEYWORDS.contains(&key.as_str()) {
+      for (name, subschema) in value.as_object_mut().into_iter().flatten() {
+        let subschema_pointer = format!("{child_pointer}/{}", escape(name));
+        downgrade_schema(subschema, &subschema_pointer, unconverted);
+      }
+    } else if SUBSCHEMA_LIST_KEYWORDS.contains(&key.as_str()) {
+      for (i, subschema) in value.as_array_mut().into_iter().flatten().enumerate() {
+        downgrade_schema(subschema, &format!("{child_pointer}/{i}"), unconverted);
+      }
+    }
+  }
+}
+
+/// Turn a `type` array into a single type, marking it nullable or splitting it into an `anyOf`
+fn downgrade_type(
+  map: &mut Map<String, Value>,
+  unconvertible: &mut impl FnMut(String),
+) {
+  let types = match map.get("type") {
+    Some(Value::Array(types)) => types.clone(),
+    Some(Value::String(r#type)) if r#type == "null" => {
+      unconvertible("Kept the `null` type".to_string());
+      return;
+    }
+    _ => return,
+  };
+  let mut non_null_types = types
+    .into_iter()
+    .filter(|r#type| r#type != "null")
+    .collect::<Vec<_>>();
+  let is_nullable = map
+    .get("type")
+    .and_then(Value::as_array)
+    .is_some_and(|types| types.len() > non_null_types.len());
+  if is_nullable {
+    map.insert("nullable".to_string(), json!(true));
+  }
+  match non_null_types.len() {
+    0 => {
+      map.shift_remove("type");
+      unconvertible("Dropped the `null` only type".to_string());
+    }
+    1 => {
+      map.insert("type".to_string(), non_null_types.remove(0));
+    }
+    _ if map.contains_key("anyOf") => {
+      unconvertible("Kept a `type` array next to `anyOf`".to_string());
+    }
+    _ => {
+      map.shift_remove("type");
+      let any_of = non_null_types
+        .into_iter()
+        .map(|r#type| json!({ "type": r#type }))
+        .collect();
+      map.insert("anyOf".to_string(), Value::Array(any_of));
+    }
+  }
+}
+
+/// Turn `{ type: "null" }` variants of `anyOf` and `oneOf` into `nullable`
+fn downgrade_null_variants(map: &mut Map<String, Value>) {
+  for keyword in ["anyOf", "oneOf"] {
+    let Some(variants) = map.get_mut(keyword).and_then(Value::as_array_mut) else {
+      continue;
+    };
+    let count = variants.len();
+    variants.retain(|variant| variant != &json!({ "type": "null" }));
+    if variants.len() < count {
+      map.insert("nullable".to_string(), json!(true));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn openapi_3_1_schemas_are_downgraded() {
+    let mut spec: Value = serde_yaml::from_str(
+      r##"
+      openapi: 3.1.0
+      info: { title: Pets, version: 1.0.0, license: { name: MIT, identifier: MIT } }
+      webhooks: {}
+      components:
+        schemas:
+          Pet:
+            type: object
+            properties:
+              name: { type: [string, "null"], examples: [Rex] }
+              kind: { const: dog }
+              age: { type: integer, exclusiveMinimum: 0 }
+              owner: { $ref: "#/components/schemas/Owner", description: The owner }
+              tags: { type: array, prefixItems: [{ type: string }] }
+      "##,
+    )
+    .expect("must parse");
+    assert!(is_openapi_3_1(&spec));
+    let unconverted = downgrade(&mut spec);
+    assert_eq!(spec["openapi"], "3.0.3");
+    assert_eq!(spec["paths"], json!({}));
+    let properties = &spec["components"]["schemas"]["Pet"]["properties"];
+    assert_eq!(
+      properties["name"],
+      json!({ "type": "string", "nullable": true, "example": "Rex" })
+    );
+    assert_eq!(properties["kind"], json!({ "enum": ["dog"] }));
+    assert_eq!(
+      properties["age"],
+      json!({ "type": "integer", "exclusiveMinimum": true, "minimum": 0 })
+    );
+    assert_eq!(
+      properties["owner"],
+      json!({ "description": "The owner", "allOf": [{ "$ref": "#/components/schemas/Owner" }] })
+    );
+    assert_eq!(
+      unconverted
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>(),
+      vec![
+        "Dropped webhooks at `/webhooks`",
+        "Dropped the SPDX license identifier at `/info/license/identifier`",
+        "Kept unsupported `prefixItems` at `/components/schemas/Pet/properties/tags`",
+      ]
+    );
+  }
+}
@@ -0,0 +1,294 @@
+//! Spec filtering
+//!
+//! An operation is kept when it matches one of the `include` rules (or there are none) and none
+//! of the `exclude` rules. Components and tags that only dropped operations used are pruned too.
+//!
+//! ```yaml
+//! spec:
+//!   filter:
+//!     include:
+//!       tags: [pets]
+//!       paths: ["/stores/*"]
+//!     exclude:
+//!       operation-ids: [deletePet]
+//! ```
+use super::{get_reachable_components, remove_pointer, SpecError};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Rules matching operations by tag, path glob or operationId
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SpecFilterRules {
+  pub tags: Vec<String>,
+  /// Globs matched against the path template, e.g. `/pets/*`
+  pub paths: Vec<String>,
+  pub operation_ids: Vec<String>,
+}
+impl SpecFilterRules {
+  /// Check whether there are no rules
+  pub fn is_empty(&self) -> bool {
+    self.tags.is_empty() && self.paths.is_empty() && self.operation_ids.is_empty()
+  }
+
+  /// Compile the path globs
+  fn try_get_path_patterns(&self) -> Result<Vec<Pattern>, SpecError> {
+    self
+      .paths
+      .iter()
+      .map(|glob| {
+        Pattern::new(glob).map_err(|source| SpecError::InvalidPathGlob {
+          glob: glob.to_string(),
+          source,
+        })
+      })
+      .collect()
+  }
+}
+
+/// Compiled [SpecFilterRules]
+struct CompiledRules<'a> {
+  rules: &'a SpecFilterRules,
+  path_patterns: Vec<Pattern>,
+}
+impl<'a> CompiledRules<'a> {
+  fn try_new(rules: &'a SpecFilterRules) -> Result<Self, SpecError> {
+    Ok(Self {
+      path_patterns: rules.try_get_path_patterns()?,
+      rules,
+    })
+  }
+
+  /// Check whether an operation matches any rule
+  fn matches(
+    &self,
+    path: &str,
+    operation: &Value,
+  ) -> bool {
+    let has_tag = operation
+      .get("tags")
+      .and_then(Value::as_array)
+      .map(|tags| {
+        tags
+          .iter()
+          .filter_map(Value::as_str)
+          .any(|tag| self.rules.tags.iter().any(|t| t == tag))
+      })
+      .unwrap_or(false);
+    let has_operation_id = operation
+      .get("operationId")
+      .and_then(Value::as_str)
+      .map(|id| self.rules.operation_ids.iter().any(|i| i == id))
+      .unwrap_or(false);
+    has_tag || has_operation_id || self.path_patterns.iter().any(|p| p.matches(path))
+  }
+}
+
+/// What a [SpecFilter] removed
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SpecFilterSummary {
+  pub total_operations: usize,
+  pub kept_operations: usize,
+  /// JSON pointers of the pruned components
+  pub pruned_components: Vec<String>,
+  pub pruned_tags: Vec<String>,
+}
+
+/// Include and exclude rules for the operations of a spec
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SpecFilter {
+  pub include: SpecFilterRules,
+  pub exclude: SpecFilterRules,
+}
+impl SpecFilter {
+  /// Keys of the operations in a path item
+  pub const HTTP_METHODS: [&'static str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+  ];
+
+  /// Check whether the filter keeps everything
+  pub fn is_empty(&self) -> bool {
+    self.include.is_empty() && self.exclude.is_empty()
+  }
+
+  /// Remove the operations the filter drops, then what only they used
+  pub fn try_apply(
+    &self,
+    spec: &mut Value,
+  ) -> Result<SpecFilterSummary, SpecError> {
+    let include = CompiledRules::try_new(&self.include)?;
+    let exclude = CompiledRules::try_new(&self.exclude)?;
+    let components_before = get_reachable_components(spec);
+    let tags_before = get_used_tags(spec);
+    let mut summary = SpecFilterSummary::default();
+    if let Some(paths) = spec.get_mut("paths").and_then(Value::as_object_mut) {
+      paths.retain(|path, path_item| {
+        let Some(path_item) = path_item.as_object_mut() else {
+          return true;
+        };
+        let operation_count = Self::HTTP_METHODS
+          .iter()
+          .filter(|method| path_item.contains_key(**method))
+          .count();
+        path_item.retain(|key, operation| {
+          if !Self::HTTP_METHODS.contains(&key.as_str()) {
+            return true;
+          }
+          (self.include.is_empty() || include.matches(path, operation))
+            && !exclude.matches(path, operation)
+        });
+        let kept_count = Self::HTTP_METHODS
+          .iter()
+          .filter(|method| path_item.contains_key(**method))
+          .count();
+        summary.total_operations += operation_count;
+        summary.kept_operations += kept_count;
+        operation_count == 0 || kept_count > 0
+      });
+    }
+    if summary.total_operations > 0 && summary.kept_operations == 0 {
+      return Err(SpecError::NoOperationsLeft);
+    }
+    let components_after = get_reachable_components(spec);
+    for pointer in components_before.difference(&components_after) {
+      if remove_pointer(spec, pointer) {
+        summary.pruned_components.push(pointer.to_string());
+      }
+    }
+    let tags_after = get_used_tags(spec);
+    summary.pruned_tags = tags_before
+      .difference(&tags_after)
+      .map(String::to_string)
+      .collect();
+    if let Some(tags) = spec.get_mut("tags").and_then(Value::as_array_mut) {
+      tags.retain(|tag| {
+        tag
+          .get("name")
+          .and_then(Value::as_str)
+          .map(|name| !summary.pruned_tags.iter().any(|t| t == name))
+          .unwrap_or(true)
+      });
+    }
+    Ok(summary)
+  }
+}
+
+/// Get the tags used by operations
+fn get_used_tags(spec: &Value) -> BTreeSet<String> {
+  spec
+    .get("paths")
+    .and_then(Value::as_object)
+    .into_iter()
+    .flat_map(|paths| paths.values())
+    .filter_map(Value::as_object)
+    .flat_map(|path_item| {
+      SpecFilter::HTTP_METHODS
+        .iter()
+        .filter_map(|method| path_item.get(*method))
+    })
+    .filter_map(|operation| operation.get("tags").and_then(Value::as_array))
+    .flatten()
+    .filter_map(Value::as_str)
+    .map(str::to_string)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn filter_prunes_operations_and_unreachable_components() {
+    let mut spec = json!({
+      "openapi": "3.0.3",
+      "tags": [{ "name": "pets" }, { "name": "store" }],
+      "paths": {
+        "/pets": {
+          "get": {
+            "tags": ["pets"],
+            "operationId": "listPets",
+            "responses": { "200": { "$ref": "#/components/responses/Pets" } }
+          },
+          "delete": { "tags": ["pets"], "operationId": "deletePets" }
+        },
+        "/store/order": {
+          "post": {
+            "tags": ["store"],
+            "operationId": "placeOrder",
+            "requestBody": { "$ref": "#/components/requestBodies/Order" }
+          }
+        }
+      },
+      "components": {
+        "schemas": {
+          "Pet": { "properties": { "owner": { "$ref": "#/components/schemas/Owner" } } },
+          "Owner": { "type": "object" },
+          "Order": { "type": "object" },
+          "Unused": { "type": "object" }
+        },
+        "responses": {
+          "Pets": {
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Pet" } } }
+          }
+        },
+        "requestBodies": {
+          "Order": {
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Order" } } }
+          }
+        }
+      }
+    });
+    let filter = SpecFilter {
+      include: SpecFilterRules {
+        tags: vec!["pets".to_string()],
+        ..Default::default()
+      },
+      exclude: SpecFilterRules {
+        operation_ids: vec!["deletePets".to_string()],
+        ..Default::default()
+      },
+    };
+    let summary = filter.try_apply(&mut spec).expect("must filter");
+    assert_eq!(summary.total_operations, 3);
+    assert_eq!(summary.kept_operations, 1);
+    assert_eq!(
+      summary.pruned_components,
+      vec![
+        "/components/requestBodies/Order",
+        "/components/schemas/Order"
+      ]
+    );
+    assert_eq!(summary.pruned_tags, vec!["store"]);
+    assert!(spec["paths"]["/store/order"].is_null());
+    assert!(spec["paths"]["/pets"]["delete"].is_null());
+    assert!(spec["components"]["schemas"]["Owner"].is_object());
+    assert!(spec["components"]["schemas"]["Unused"].is_object());
+    assert_eq!(spec["tags"], json!([{ "name": "pets" }]));
+  }
+
+  #[test]
+  fn filter_leaving_no_operations_fails() {
+    let mut spec = json!({
+      "openapi": "3.0.3",
+      "paths": {
+        "/pets": { "get": { "operationId": "listPets" } },
+        "/pets/{id}": { "delete": { "operationId": "deletePet" } }
+      }
+    });
+    let filter = SpecFilter {
+      exclude: SpecFilterRules {
+        paths: vec!["/pets*".to_string()],
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    assert!(matches!(
+      filter.try_apply(&mut spec),
+      Err(SpecError::NoOperationsLeft)
+    ));
+  }
+}
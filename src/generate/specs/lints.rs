@@ -0,0 +1,508 @@
+//! Spec linting
+//!
+//! A valid spec can still make the Rust generator produce poor code. The [LintRules] catch the
+//! usual causes: missing or duplicate `operationId`s, inline anonymous schemas, enum values whose
+//! variant names clash once converted to Rust case, and parameters named after Rust keywords.
+//! Each rule has a [severity](LintSeverities) that can be set in the `lint` section of the project
+//! config; errors fail the spec preparation, ahead of generation.
+use super::{diffs, escape_pointer_segment, filters::SpecFilter, get_component_pointer, SpecError};
+use heck::{ToLowerCamelCase, ToSnakeCase, ToUpperCamelCase};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt::{self, Display, Formatter},
+  path::Path,
+};
+
+/// Rust keywords, strict and reserved, that generated identifiers must avoid
+const RUST_KEYWORDS: [&str; 51] = [
+  "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do",
+  "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let",
+  "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+  "self", "Self", "static", "struct", "super", "trait", "true", "try", "type", "typeof", "unsafe",
+  "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Lint output formats
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintFormats {
+  /// Human-readable lines
+  #[default]
+  Text,
+  /// A JSON object
+  Json,
+}
+
+/// Lint rules
+#[derive(
+  Clone,
+  Copy,
+  Debug,
+  Deserialize,
+  Eq,
+  Hash,
+  Ord,
+  PartialEq,
+  PartialOrd,
+  Serialize,
+  strum::AsRefStr,
+  strum::EnumIter,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum LintRules {
+  /// Operations without an `operationId` get a name made up by the generator
+  MissingOperationId,
+  /// `operationId`s used twice, or equal once converted to snake case, get suffixed
+  DuplicateOperationId,
+  /// Inline object schemas become models with made up names
+  InlineSchema,
+  /// Enum values converting to the same variant name do not compile
+  EnumCaseClash,
+  /// Parameters named after Rust keywords get escaped or renamed arguments
+  RustKeywordParameter,
+}
+impl LintRules {
+  /// Get the severity of the rule when the project config does not set it
+  pub fn get_default_severity(self) -> LintSeverities {
+    match self {
+      Self::EnumCaseClash => LintSeverities::Error,
+      _ => LintSeverities::Warning,
+    }
+  }
+}
+
+/// Lint issue severities, from the lowest
+#[derive(
+  Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, strum::AsRefStr,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum LintSeverities {
+  /// The rule is not checked
+  Off,
+  /// Issues are reported
+  Warning,
+  /// Issues are reported and fail the generation
+  Error,
+}
+
+/// Lint settings, from the `lint` section of the project config
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct LintConfig {
+  /// Severities overriding the defaults of the rules
+  pub rules: BTreeMap<LintRules, LintSeverities>,
+}
+impl LintConfig {
+  /// Get the severity of a rule
+  pub fn get_severity(
+    &self,
+    rule: LintRules,
+  ) -> LintSeverities {
+    self
+      .rules
+      .get(&rule)
+      .copied()
+      .unwrap_or_else(|| rule.get_default_severity())
+  }
+}
+
+/// An issue found in a spec
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LintIssue {
+  pub rule: LintRules,
+  pub severity: LintSeverities,
+  /// JSON pointer of the offending node
+  pub pointer: String,
+  pub message: String,
+  pub suggestion: String,
+}
+impl Display for LintIssue {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    write!(
+      f,
+      "{}[{}] `{}`: {}. {}",
+      self.severity.as_ref(),
+      self.rule.as_ref(),
+      self.pointer,
+      self.message,
+      self.suggestion
+    )
+  }
+}
+
+/// The issues found in a spec
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LintReport {
+  pub errors: usize,
+  pub warnings: usize,
+  pub issues: Vec<LintIssue>,
+}
+impl LintReport {
+  /// Instantiate from issues, most severe first
+  pub fn new(mut issues: Vec<LintIssue>) -> Self {
+    issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+    let errors = issues
+      .iter()
+      .filter(|issue| issue.severity == LintSeverities::Error)
+      .count();
+    Self {
+      errors,
+      warnings: issues.len() - errors,
+      issues,
+    }
+  }
+
+  /// Format in the given format
+  pub fn to_formatted_string(
+    &self,
+    format: LintFormats,
+  ) -> Result<String, SpecError> {
+    Ok(match format {
+      LintFormats::Text => self.to_string(),
+      LintFormats::Json => serde_json::to_string_pretty(self)?,
+    })
+  }
+
+  /// Fail when there are errors
+  pub fn into_result(self) -> Result<(), SpecError> {
+    match self.errors {
+      0 => Ok(()),
+      errors => Err(SpecError::LintFailed(errors)),
+    }
+  }
+}
+impl Display for LintReport {
+  fn fmt(
+    &self,
+    f: &mut Formatter<'_>,
+  ) -> fmt::Result {
+    for issue in self.issues.iter() {
+      writeln!(f, "{issue}")?;
+    }
+    match self.issues.len() {
+      0 => write!(f, "No spec lint issues"),
+      _ => write!(f, "{} errors and {} warnings", self.errors, self.warnings),
+    }
+  }
+}
+
+/// Lint a spec
+pub fn lint(
+  spec: &Value,
+  config: &LintConfig,
+) -> LintReport {
+  let mut linter = SpecLinter {
+    spec,
+    config,
+    issues: vec![],
+  };
+  linter.lint_operations();
+  linter.lint_node(spec, "");
+  LintReport::new(linter.issues)
+}
+
+/// Read and lint a spec file, converting Swagger 2.0 specs
+pub async fn lint_file(
+  path: impl AsRef<Path>,
+  config: &LintConfig,
+) -> Result<LintReport, SpecError> {
+  let spec = diffs::read_comparable_spec(path).await?;
+  Ok(lint(&spec, config))
+}
+
+/// Checks a spec, collecting issues
+struct SpecLinter<'a> {
+  spec: &'a Value,
+  config: &'a LintConfig,
+  issues: Vec<LintIssue>,
+}
+impl SpecLinter<'_> {
+  /// Record an issue unless its rule is off
+  fn push(
+    &mut self,
+    rule: LintRules,
+    pointer: &str,
+    message: String,
+    suggestion: String,
+  ) {
+    let severity = self.config.get_severity(rule);
+    if severity != LintSeverities::Off {
+      self.issues.push(LintIssue {
+        rule,
+        severity,
+        pointer: pointer.to_string(),
+        message,
+        suggestion,
+      });
+    }
+  }
+
+  /// Check the operation ids and parameters
+  fn lint_operations(&mut self) {
+    let mut operation_ids = HashMap::<String, (String, String)>::new();
+    let paths = self.spec.get("paths").and_then(Value::as_object);
+    for (path, path_item) in paths.into_iter().flatten() {
+      let path_item_pointer = format!("/paths/{}", escape_pointer_segment(path));
+      self.lint_parameters(path_item, &path_item_pointer);
+      for method in SpecFilter::HTTP_METHODS {
+        let Some(operation) = path_item.get(method) else {
+          continue;
+        };
+        let pointer = format!("{path_item_pointer}/{method}");
+        self.lint_parameters(operation, &pointer);
+        let Some(operation_id) = operation.get("operationId").and_then(Value::as_str) else {
+          self.push(
+            LintRules::MissingOperationId,
+            &pointer,
+            format!("`{} {path}` has no operationId", method.to_uppercase()),
+            format!(
+              "Add one, like `operationId: {}`",
+              make_operation_id(method, path)
+            ),
+          );
+          continue;
+        };
+        let key = operation_id.to_snake_case();
+        match operation_ids.get(&key) {
+          Some((other_operation_id, other_pointer)) => {
+            let message = match other_operation_id == operation_id {
+              true => format!("operationId `{operation_id}` is already used at `{other_pointer}`"),
+              false => format!(
+                "operationId `{operation_id}` becomes `{key}` like `{other_operation_id}` at `{other_pointer}`"
+              ),
+            };
+            self.push(
+              LintRules::DuplicateOperationId,
+              &format!("{pointer}/operationId"),
+              message,
+              "Give each operation a unique operationId".to_string(),
+            );
+          }
+          None => {
+            operation_ids.insert(key, (operation_id.to_string(), pointer));
+          }
+        }
+      }
+    }
+  }
+
+  /// Check the parameters of an operation or path item
+  fn lint_parameters(
+    &mut self,
+    node: &Value,
+    pointer: &str,
+  ) {
+    let parameters = node.get("parameters").and_then(Value::as_array);
+    for (i, parameter) in parameters.into_iter().flatten().enumerate() {
+      let parameter = match parameter.get("$ref").and_then(Value::as_str) {
+        Some(reference) => match get_component_pointer(reference)
+          .and_then(|component_pointer| self.spec.pointer(&component_pointer))
+        {
+          Some(parameter) => parameter,
+          None => continue,
+        },
+        None => parameter,
+      };
+      let Some(name) = parameter.get("name").and_then(Value::as_str) else {
+        continue;
+      };
+      let rust_name = name.to_snake_case();
+      if RUST_KEYWORDS.contains(&rust_name.as_str()) {
+        self.push(
+          LintRules::RustKeywordParameter,
+          &format!("{pointer}/parameters/{i}"),
+          format!("Parameter `{name}` becomes the Rust keyword `{rust_name}`"),
+          format!(
+            "Map it to another name with the generator's `parameterNameMappings`, like `{name}={rust_name}_param`"
+          ),
+        );
+      }
+    }
+  }
+
+  /// Walk a non-schema node, checking the schemas found in it
+  fn lint_node(
+    &mut self,
+    node: &Value,
+    pointer: &str,
+  ) {
+    match node {
+      Value::Object(map) => {
+        for (key, value) in map {
+          let child_pointer = format!("{pointer}/{}", escape_pointer_segment(key));
+          if pointer == "/components/schemas" {
+            self.lint_schema(value, &child_pointer, true);
+          } else if key == "schema" {
+            self.lint_schema(value, &child_pointer, false);
+          } else if !key.starts_with("x-") {
+            self.lint_node(value, &child_pointer);
+          }
+        }
+      }
+      Value::Array(values) => {
+        for (i, value) in values.iter().enumerate() {
+          self.lint_node(value, &format!("{pointer}/{i}"));
+        }
+      }
+      _ => {}
+    }
+  }
+
+  /// Check a schema and its subschemas
+  ///
+  /// Named schemas are the component schemas and the `allOf` parts the generator merges into
+  /// their parent.
+  fn lint_schema(
+    &mut self,
+    schema: &Value,
+    pointer: &str,
+    is_named: bool,
+  ) {
+    let Some(map) = schema.as_object() else {
+      return;
+    };
+    if !is_named && map.contains_key("properties") {
+      self.push(
+        LintRules::InlineSchema,
+        pointer,
+        "Inline object schema".to_string(),
+        "Move it to `components/schemas` and `$ref` it to name its model".to_string(),
+      );
+    }
+    if let Some(values) = map.get("enum").and_then(Value::as_array) {
+      self.lint_enum(values, &format!("{pointer}/enum"));
+    }
+    for (key, value) in map {
+      let child_pointer = format!("{pointer}/{}", escape_pointer_segment(key));
+      match key.as_str() {
+        "items" | "not" | "additionalProperties" => self.lint_schema(value, &child_pointer, false),
+        "properties" => {
+          for (name, subschema) in value.as_object().into_iter().flatten() {
+            let subschema_pointer = format!("{child_pointer}/{}", escape_pointer_segment(name));
+            self.lint_schema(subschema, &subschema_pointer, false);
+          }
+        }
+        "allOf" | "anyOf" | "oneOf" => {
+          for (i, subschema) in value.as_array().into_iter().flatten().enumerate() {
+            self.lint_schema(subschema, &format!("{child_pointer}/{i}"), key == "allOf");
+          }
+        }
+        _ => {}
+      }
+    }
+  }
+
+  /// Check that the values of an enum convert to distinct variant names
+  fn lint_enum(
+    &mut self,
+    values: &[Value],
+    pointer: &str,
+  ) {
+    let mut variants = HashMap::<String, &str>::new();
+    for (i, value) in values.iter().enumerate() {
+      let Some(value) = value.as_str() else {
+        continue;
+      };
+      let variant = value.to_upper_camel_case();
+      match variants.get(&variant) {
+        Some(other_value) => {
+          let message = format!(
+            "Enum values `{other_value}` and `{value}` both become the variant `{variant}`"
+          );
+          self.push(
+            LintRules::EnumCaseClash,
+            &format!("{pointer}/{i}"),
+            message,
+            "Drop one of the values, or name the variants with `x-enum-varnames`".to_string(),
+          );
+        }
+        None => {
+          variants.insert(variant, value);
+        }
+      }
+    }
+  }
+}
+
+/// Make an operation id from a method and a path, like `getPetsByPetId` for `GET /pets/{petId}`
+fn make_operation_id(
+  method: &str,
+  path: &str,
+) -> String {
+  let words = path
+    .split('/')
+    .filter(|segment| !segment.is_empty())
+    .map(|segment| match segment.strip_prefix('{') {
+      Some(parameter) => format!("by {}", parameter.trim_end_matches('}')),
+      None => segment.to_string(),
+    })
+    .collect::<Vec<_>>()
+    .join(" ");
+  format!("{method} {words}").to_lower_camel_case()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lint_issues_are_reported_with_their_severity() {
+    let spec: Value = serde_yaml::from_str(
+      r##"
+      openapi: 3.0.3
+      paths:
+        /pets/{petId}:
+          get:
+            operationId: getPet
+            parameters:
+              - { name: type, in: query, schema: { type: string, enum: [dog, Dog, cat] } }
+            responses:
+              "200":
+                content:
+                  application/json:
+                    schema: { type: object, properties: { name: { type: string } } }
+          delete:
+            operationId: get_pet
+        /pets:
+          post: {}
+      components:
+        schemas:
+          Pet: { type: object, properties: { name: { type: string } } }
+      "##,
+    )
+    .expect("must parse");
+    let config = LintConfig {
+      rules: BTreeMap::from([(LintRules::InlineSchema, LintSeverities::Off)]),
+    };
+    let report = lint(&spec, &config);
+    assert_eq!(
+      report
+        .issues
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>(),
+      vec![
+        "error[enum-case-clash] `/paths/~1pets~1{petId}/get/parameters/0/schema/enum/1`: Enum values `dog` and `Dog` both become the variant `Dog`. Drop one of the values, or name the variants with `x-enum-varnames`",
+        "warning[rust-keyword-parameter] `/paths/~1pets~1{petId}/get/parameters/0`: Parameter `type` becomes the Rust keyword `type`. Map it to another name with the generator's `parameterNameMappings`, like `type=type_param`",
+        "warning[duplicate-operation-id] `/paths/~1pets~1{petId}/delete/operationId`: operationId `get_pet` becomes `get_pet` like `getPet` at `/paths/~1pets~1{petId}/get`. Give each operation a unique operationId",
+        "warning[missing-operation-id] `/paths/~1pets/post`: `POST /pets` has no operationId. Add one, like `operationId: postPets`",
+      ]
+    );
+    assert_eq!((report.errors, report.warnings), (1, 3));
+    assert_eq!(make_operation_id("get", "/pets/{petId}"), "getPetsByPetId");
+    let report = lint(&spec, &LintConfig::default());
+    assert!(report
+      .issues
+      .iter()
+      .any(|issue| issue.rule == LintRules::InlineSchema
+        && issue.pointer
+          == "/paths/~1pets~1{petId}/get/responses/200/content/application~1json/schema"));
+  }
+}
@@ -0,0 +1,257 @@
+//! Spec patches
+//!
+//! Patch files fix upstream specs before validation and generation. Each file is either an
+//! [OpenAPI Overlay](https://spec.openapis.org/overlay/v1.0.0.html) or an
+//! [RFC 6902 JSON Patch](https://www.rfc-editor.org/rfc/rfc6902), in yaml or json, and they are
+//! applied in the order they are listed.
+//!
+//! A patch that no longer applies fails the run: a JSON Patch operation whose path is gone or
+//! whose `test` fails, or an overlay action whose target matches nothing. This usually means
+//! the upstream spec was fixed and the patch can be dropped.
+use super::{SpecDocument, SpecError, SpecFormats};
+use fs_err::tokio as fs;
+use json_patch::Patch;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::path::{Path, PathBuf};
+
+/// An overlay action
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlayAction {
+  /// JSONPath of the nodes to update or remove
+  pub target: String,
+  #[serde(default, rename = "description")]
+  pub description_opt: Option<String>,
+  /// Merged into objects, appended to arrays
+  #[serde(default, rename = "update")]
+  pub update_opt: Option<Value>,
+  #[serde(default)]
+  pub remove: bool,
+}
+
+/// An OpenAPI Overlay document
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Overlay {
+  /// The overlay spec version
+  pub overlay: String,
+  pub actions: Vec<OverlayAction>,
+}
+
+/// A patch read from a file
+#[derive(Clone, Debug)]
+pub enum SpecPatch {
+  Overlay(Overlay),
+  JSONPatch(Patch),
+}
+impl SpecPatch {
+  /// Read a patch file, telling overlays and JSON patches apart
+  pub async fn read_from_path(path: impl AsRef<Path>) -> Result<Self, SpecError> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path).await?;
+    let SpecDocument { value, .. } = SpecDocument::parse(&contents, SpecFormats::from_path(path))?;
+    match value {
+      Value::Array(_) => Ok(Self::JSONPatch(serde_json::from_value(value)?)),
+      Value::Object(ref map) if map.contains_key("overlay") => {
+        Ok(Self::Overlay(serde_json::from_value(value)?))
+      }
+      _ => Err(SpecError::UnknownPatchFormat(path.to_path_buf())),
+    }
+  }
+
+  /// Apply to a spec
+  ///
+  /// The path of the patch file is only used in errors.
+  pub fn try_apply(
+    &self,
+    spec: &mut Value,
+    path: &Path,
+  ) -> Result<(), SpecError> {
+    match self {
+      Self::JSONPatch(patch) => {
+        json_patch::patch(spec, patch).map_err(|source| SpecError::PatchFailed {
+          path: path.to_path_buf(),
+          source,
+        })
+      }
+      Self::Overlay(overlay) => {
+        for (index, action) in overlay.actions.iter().enumerate() {
+          apply_overlay_action(spec, action).map_err(|e| match e {
+            OverlayActionError::InvalidTarget(source) => SpecError::InvalidOverlayTarget {
+              path: path.to_path_buf(),
+              target: action.target.to_string(),
+              source,
+            },
+            OverlayActionError::NoMatch => SpecError::OverlayTargetNotFound {
+              path: path.to_path_buf(),
+              index,
+              target: action.target.to_string(),
+            },
+          })?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+/// Why an overlay action could not be applied
+enum OverlayActionError {
+  InvalidTarget(serde_json_path::ParseError),
+  NoMatch,
+}
+
+/// Apply an overlay action to every node its target matches
+fn apply_overlay_action(
+  spec: &mut Value,
+  action: &OverlayAction,
+) -> Result<(), OverlayActionError> {
+  let target = JsonPath::parse(&action.target).map_err(OverlayActionError::InvalidTarget)?;
+  let mut pointers = target
+    .query_located(spec)
+    .locations()
+    .map(|location| location.to_json_pointer())
+    .collect::<Vec<_>>();
+  if pointers.is_empty() {
+    return Err(OverlayActionError::NoMatch);
+  }
+  if action.remove {
+    // Remove later array entries first so earlier indices stay valid
+    pointers.reverse();
+    for pointer in pointers {
+      remove_node(spec, &pointer);
+    }
+  } else if let Some(update) = action.update_opt.as_ref() {
+    for pointer in pointers {
+      if let Some(node) = spec.pointer_mut(&pointer) {
+        merge_update(node, update);
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Remove the node at a JSON pointer from its parent object or array
+fn remove_node(
+  spec: &mut Value,
+  pointer: &str,
+) {
+  let Some((parent, name)) = pointer.rsplit_once('/') else {
+    return;
+  };
+  let name = name.replace("~1", "/").replace("~0", "~");
+  match spec.pointer_mut(parent) {
+    Some(Value::Object(map)) => {
+      map.shift_remove(&name);
+    }
+    Some(Value::Array(values)) => {
+      if let Some(i) = name.parse::<usize>().ok().filter(|i| *i < values.len()) {
+        values.remove(i);
+      }
+    }
+    _ => {}
+  }
+}
+
+/// Merge an overlay update into a node
+///
+/// Objects are merged recursively, arrays get the update appended, anything else is replaced.
+fn merge_update(
+  node: &mut Value,
+  update: &Value,
+) {
+  match (node, update) {
+    (Value::Object(node), Value::Object(update)) => {
+      for (key, value) in update {
+        match node.get_mut(key) {
+          Some(existing) if existing.is_object() && value.is_object() => {
+            merge_update(existing, value)
+          }
+          _ => {
+            node.insert(key.to_string(), value.clone());
+          }
+        }
+      }
+    }
+    (Value::Array(node), Value::Array(update)) => node.extend(update.iter().cloned()),
+    (Value::Array(node), update) => node.push(update.clone()),
+    (node, update) => *node = update.clone(),
+  }
+}
+
+/// Read and apply patch files in order, resolving their paths from a dir
+pub async fn apply_patch_files(
+  spec: &mut Value,
+  patch_file_paths: &[PathBuf],
+  dir: impl AsRef<Path>,
+) -> Result<(), SpecError> {
+  for patch_file_path in patch_file_paths {
+    let path = dir.as_ref().join(patch_file_path);
+    SpecPatch::read_from_path(&path)
+      .await?
+      .try_apply(spec, &path)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn overlays_update_and_remove_and_fail_when_stale() {
+    let mut spec = json!({
+      "components": {
+        "schemas": {
+          "Pet": {
+            "properties": { "id": { "type": "string" }, "legacy": { "type": "string" } }
+          }
+        }
+      }
+    });
+    let overlay: Overlay = serde_yaml::from_str(
+      r#"
+      overlay: 1.0.0
+      actions:
+        - target: $.components.schemas.Pet
+          update:
+            required: [id]
+            properties:
+              id: { type: integer }
+        - target: $.components.schemas.Pet.properties.legacy
+          remove: true
+      "#,
+    )
+    .expect("must parse overlay");
+    let patch = SpecPatch::Overlay(overlay);
+    let path = Path::new("fix.yaml");
+    patch.try_apply(&mut spec, path).expect("must apply");
+    assert_eq!(
+      spec["components"]["schemas"]["Pet"],
+      json!({ "properties": { "id": { "type": "integer" } }, "required": ["id"] })
+    );
+    assert!(matches!(
+      patch.try_apply(&mut spec, path),
+      Err(SpecError::OverlayTargetNotFound { index: 1, .. })
+    ));
+  }
+
+  #[test]
+  fn json_patches_fail_when_a_test_fails() {
+    let mut spec = json!({ "info": { "title": "Petstore", "version": "1.0.0" } });
+    let patch: Patch = serde_json::from_value(json!([
+      { "op": "replace", "path": "/info/title", "value": "Pets" },
+      { "op": "test", "path": "/info/version", "value": "0.9.0" }
+    ]))
+    .expect("must parse patch");
+    let result = SpecPatch::JSONPatch(patch).try_apply(&mut spec, Path::new("fix.json"));
+    assert!(matches!(
+      result,
+      Err(SpecError::PatchFailed { source, .. })
+        if source.operation == 1 && matches!(source.kind, json_patch::PatchErrorKind::TestFailed)
+    ));
+    assert_eq!(spec["info"]["title"], "Petstore");
+  }
+}
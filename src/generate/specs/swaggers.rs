@@ -0,0 +1,725 @@
+//! Swagger 2.0 to OpenAPI 3.0 conversion
+//!
+//! Conversion is lossy in places, for example `collectionFormat: tsv` has no OpenAPI 3.0
+//! equivalent, and such spots are reported as warnings along with the ones that change the shape
+//! of the API (formData and body parameters becoming request bodies).
+use serde_json::{json, Map, Value};
+
+/// The OpenAPI version specs are converted to
+pub const CONVERTED_OPENAPI_VERSION: &str = "3.0.3";
+
+/// Keys of a Swagger 2.0 non-body parameter that move into its schema
+const PARAMETER_SCHEMA_KEYS: [&str; 16] = [
+  "type",
+  "format",
+  "items",
+  "default",
+  "maximum",
+  "exclusiveMaximum",
+  "minimum",
+  "exclusiveMinimum",
+  "maxLength",
+  "minLength",
+  "pattern",
+  "maxItems",
+  "minItems",
+  "uniqueItems",
+  "enum",
+  "multipleOf",
+];
+
+/// Keys of a path item holding operations
+const HTTP_METHODS: [&str; 7] = ["get", "put", "post", "delete", "options", "head", "patch"];
+
+/// Check whether a spec is a Swagger 2.0 document
+pub fn is_swagger(spec: &Value) -> bool {
+  spec
+    .get("swagger")
+    .and_then(Value::as_str)
+    .is_some_and(|version| version.starts_with("2."))
+}
+
+/// A converted spec with the conversion warnings
+#[derive(Clone, Debug)]
+pub struct SwaggerConversion {
+  pub spec: Value,
+  pub warnings: Vec<String>,
+}
+
+/// Convert a Swagger 2.0 spec to OpenAPI 3.0
+pub fn convert_swagger(swagger: &Value) -> SwaggerConversion {
+  let mut converter = SwaggerConverter {
+    swagger,
+    warnings: vec![],
+  };
+  let mut spec = converter.convert();
+  rewrite_refs(&mut spec, &converter.get_body_parameter_names());
+  SwaggerConversion {
+    spec,
+    warnings: converter.warnings,
+  }
+}
+
+/// Converts a Swagger 2.0 spec, collecting warnings
+struct SwaggerConverter<'a> {
+  swagger: &'a Value,
+  warnings: Vec<String>,
+}
+impl<'a> SwaggerConverter<'a> {
+  /// Get a root value as an object
+  fn get_root_object(
+    &self,
+    key: &str,
+  ) -> Option<&'a Map<String, Value>> {
+    self.swagger.get(key).and_then(Value::as_object)
+  }
+
+  /// Get the names of the root parameters that are bodies or form data
+  fn get_body_parameter_names(&self) -> Vec<String> {
+    self
+      .get_root_object("parameters")
+      .into_iter()
+      .flatten()
+      .filter(|(_, parameter)| is_body_or_form_data(parameter))
+      .map(|(name, _)| name.to_string())
+      .collect()
+  }
+
+  /// Get the media types from a `consumes` or `produces` key, falling back to the root's
+  fn get_media_types(
+    &self,
+    operation: &Value,
+    key: &str,
+  ) -> Vec<String> {
+    operation
+      .get(key)
+      .or_else(|| self.swagger.get(key))
+      .and_then(Value::as_array)
+      .map(|types| {
+        types
+          .iter()
+          .filter_map(Value::as_str)
+          .map(str::to_string)
+          .collect::<Vec<_>>()
+      })
+      .filter(|types| !types.is_empty())
+      .unwrap_or_else(|| vec!["application/json".to_string()])
+  }
+
+  /// Convert the whole spec
+  fn convert(&mut self) -> Value {
+    let mut spec = Map::new();
+    spec.insert("openapi".to_string(), json!(CONVERTED_OPENAPI_VERSION));
+    for (key, value) in self.swagger.as_object().into_iter().flatten() {
+      match key.as_str() {
+        "swagger"
+        | "host"
+        | "basePath"
+        | "schemes"
+        | "consumes"
+        | "produces"
+        | "paths"
+        | "definitions"
+        | "parameters"
+        | "responses"
+        | "securityDefinitions" => {}
+        _ => {
+          spec.insert(key.to_string(), value.clone());
+        }
+      }
+    }
+    spec.insert("servers".to_string(), self.convert_servers());
+    let mut paths = Map::new();
+    for (path, path_item) in self.get_root_object("paths").into_iter().flatten() {
+      paths.insert(path.to_string(), self.convert_path_item(path, path_item));
+    }
+    spec.insert("paths".to_string(), Value::Object(paths));
+    let components = self.convert_components();
+    if !components.is_empty() {
+      spec.insert("components".to_string(), Value::Object(components));
+    }
+    Value::Object(spec)
+  }
+
+  /// Convert `host`, `basePath` and `schemes` to servers
+  fn convert_servers(&self) -> Value {
+    let base_path = self
+      .swagger
+      .get("basePath")
+      .and_then(Value::as_str)
+      .unwrap_or_default();
+    let urls = match self.swagger.get("host").and_then(Value::as_str) {
+      Some(host) => {
+        let schemes = self
+          .swagger
+          .get("schemes")
+          .and_then(Value::as_array)
+          .map(|schemes| schemes.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+          .filter(|schemes| !schemes.is_empty())
+          .unwrap_or_else(|| vec!["https"]);
+        schemes
+          .iter()
+          .map(|scheme| format!("{scheme}://{host}{base_path}"))
+          .collect()
+      }
+      None if base_path.is_empty() => vec!["/".to_string()],
+      None => vec![base_path.to_string()],
+    };
+    Value::Array(urls.into_iter().map(|url| json!({ "url": url })).collect())
+  }
+
+  /// Convert a path item and its operations
+  ///
+  /// Body and formData parameters of the path item are moved into its operations.
+  fn convert_path_item(
+    &mut self,
+    path: &str,
+    path_item: &Value,
+  ) -> Value {
+    let (body_parameters, parameters): (Vec<_>, Vec<_>) = path_item
+      .get("parameters")
+      .and_then(Value::as_array)
+      .into_iter()
+      .flatten()
+      .partition(|parameter| is_body_or_form_data(&self.resolve_parameter(parameter)));
+    let mut converted = Map::new();
+    for (key, value) in path_item.as_object().into_iter().flatten() {
+      if HTTP_METHODS.contains(&key.as_str()) {
+        let location = format!("{} {path}", key.to_uppercase());
+        let operation = self.convert_operation(&location, value, &body_parameters);
+        converted.insert(key.to_string(), operation);
+      } else if key == "parameters" {
+        let location = format!("the path item {path}");
+        let parameters = parameters
+          .iter()
+          .map(|parameter| self.convert_parameter(&location, parameter))
+          .collect();
+        converted.insert(key.to_string(), Value::Array(parameters));
+      } else {
+        converted.insert(key.to_string(), value.clone());
+      }
+    }
+    Value::Object(converted)
+  }
+
+  /// Resolve a ref to a root parameter
+  fn resolve_parameter(
+    &self,
+    parameter: &Value,
+  ) -> Value {
+    parameter
+      .get("$ref")
+      .and_then(Value::as_str)
+      .and_then(|reference| reference.strip_prefix("#/parameters/"))
+      .and_then(|name| self.get_root_object("parameters")?.get(name))
+      .unwrap_or(parameter)
+      .clone()
+  }
+
+  /// Convert an operation, turning its body or formData parameters into a request body
+  fn convert_operation(
+    &mut self,
+    location: &str,
+    operation: &Value,
+    path_body_parameters: &[&Value],
+  ) -> Value {
+    let consumes = self.get_media_types(operation, "consumes");
+    let produces = self.get_media_types(operation, "produces");
+    let operation_parameters = operation
+      .get("parameters")
+      .and_then(Value::as_array)
+      .map(Vec::as_slice)
+      .unwrap_or_default();
+    let has_body = operation_parameters
+      .iter()
+      .any(|parameter| is_body_or_form_data(&self.resolve_parameter(parameter)));
+    let inherited_body_parameters = if has_body { &[] } else { path_body_parameters };
+    let mut parameters = vec![];
+    let mut request_body_opt = None;
+    let mut form_data_parameters = vec![];
+    for parameter in operation_parameters
+      .iter()
+      .chain(inherited_body_parameters.iter().copied())
+    {
+      let resolved = self.resolve_parameter(parameter);
+      match resolved.get("in").and_then(Value::as_str) {
+        Some("body") => {
+          request_body_opt = Some(match parameter.get("$ref") {
+            Some(reference) => json!({ "$ref": reference }),
+            None => convert_body_parameter(&resolved, &consumes),
+          });
+        }
+        Some("formData") => form_data_parameters.push(resolved),
+        _ => parameters.push(self.convert_parameter(location, parameter)),
+      }
+    }
+    if !form_data_parameters.is_empty() {
+      self.warnings.push(format!(
+        "Converted the formData parameters of {location} to a request body"
+      ));
+      request_body_opt = Some(convert_form_data_parameters(
+        &form_data_parameters,
+        &consumes,
+      ));
+    }
+    let mut converted = Map::new();
+    for (key, value) in operation.as_object().into_iter().flatten() {
+      match key.as_str() {
+        "consumes" | "produces" => {}
+        "schemes" => self.warnings.push(format!(
+          "Dropped the `schemes` of {location}: OpenAPI 3.0 operations have no schemes"
+        )),
+        "parameters" => {
+          if !parameters.is_empty() {
+            converted.insert(key.to_string(), Value::Array(parameters.clone()));
+          }
+        }
+        "responses" => {
+          let responses = value
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(status, response)| (status.to_string(), convert_response(response, &produces)))
+            .collect();
+          if let Some(request_body) = request_body_opt.take() {
+            converted.insert("requestBody".to_string(), request_body);
+          }
+          converted.insert(key.to_string(), Value::Object(responses));
+        }
+        _ => {
+          converted.insert(key.to_string(), value.clone());
+        }
+      }
+    }
+    if let Some(request_body) = request_body_opt {
+      converted.insert("requestBody".to_string(), request_body);
+    }
+    Value::Object(converted)
+  }
+
+  /// Convert a non-body parameter, moving its type into a schema
+  fn convert_parameter(
+    &mut self,
+    location: &str,
+    parameter: &Value,
+  ) -> Value {
+    let Some(parameter) = parameter.as_object() else {
+      return parameter.clone();
+    };
+    if parameter.contains_key("$ref") {
+      return Value::Object(parameter.clone());
+    }
+    let mut converted = Map::new();
+    let mut schema = Map::new();
+    for (key, value) in parameter {
+      if PARAMETER_SCHEMA_KEYS.contains(&key.as_str()) {
+        schema.insert(key.to_string(), value.clone());
+      } else if key != "collectionFormat" {
+        converted.insert(key.to_string(), value.clone());
+      }
+    }
+    if let Some(collection_format) = parameter.get("collectionFormat").and_then(Value::as_str) {
+      let is_query = parameter.get("in").and_then(Value::as_str) == Some("query");
+      let style_opt = match collection_format {
+        "csv" if is_query => Some(("form", false)),
+        "csv" => Some(("simple", false)),
+        "multi" => Some(("form", true)),
+        "ssv" => Some(("spaceDelimited", false)),
+        "pipes" => Some(("pipeDelimited", false)),
+        _ => None,
+      };
+      match style_opt {
+        Some((style, explode)) => {
+          converted.insert("style".to_string(), json!(style));
+          converted.insert("explode".to_string(), json!(explode));
+        }
+        None => self.warnings.push(format!(
+          "Dropped `collectionFormat: {collection_format}` of parameter `{}` of {location}: OpenAPI 3.0 has no equivalent",
+          parameter.get("name").and_then(Value::as_str).unwrap_or_default()
+        )),
+      }
+    }
+    if !schema.is_empty() {
+      converted.insert("schema".to_string(), Value::Object(schema));
+    }
+    Value::Object(converted)
+  }
+
+  /// Convert definitions, root parameters, responses and security definitions to components
+  fn convert_components(&mut self) -> Map<String, Value> {
+    let mut components = Map::new();
+    if let Some(definitions) = self.swagger.get("definitions") {
+      components.insert("schemas".to_string(), definitions.clone());
+    }
+    let mut parameters = Map::new();
+    let mut request_bodies = Map::new();
+    let consumes = self.get_media_types(&Value::Null, "consumes");
+    let produces = self.get_media_types(&Value::Null, "produces");
+    for (name, parameter) in self.get_root_object("parameters").into_iter().flatten() {
+      match parameter.get("in").and_then(Value::as_str) {
+        Some("body") => {
+          request_bodies.insert(
+            name.to_string(),
+            convert_body_parameter(parameter, &consumes),
+          );
+        }
+        Some("formData") => {
+          request_bodies.insert(
+            name.to_string(),
+            convert_form_data_parameters(std::slice::from_ref(parameter), &consumes),
+          );
+        }
+        _ => {
+          let location = format!("root parameter `{name}`");
+          parameters.insert(
+            name.to_string(),
+            self.convert_parameter(&location, parameter),
+          );
+        }
+      }
+    }
+    if !request_bodies.is_empty() {
+      self
+        .warnings
+        .push("Converted the root body and formData parameters to request bodies".to_string());
+    }
+    let responses = self
+      .get_root_object("responses")
+      .into_iter()
+      .flatten()
+      .map(|(name, response)| (name.to_string(), convert_response(response, &produces)))
+      .collect::<Map<_, _>>();
+    let security_schemes = self
+      .get_root_object("securityDefinitions")
+      .into_iter()
+      .flatten()
+      .map(|(name, scheme)| (name.to_string(), self.convert_security_scheme(name, scheme)))
+      .collect::<Vec<_>>();
+    for (key, map) in [
+      ("parameters", parameters),
+      ("requestBodies", request_bodies),
+      ("responses", responses),
+      ("securitySchemes", Map::from_iter(security_schemes)),
+    ] {
+      if !map.is_empty() {
+        components.insert(key.to_string(), Value::Object(map));
+      }
+    }
+    components
+  }
+
+  /// Convert a security definition to a security scheme
+  fn convert_security_scheme(
+    &mut self,
+    name: &str,
+    scheme: &Value,
+  ) -> Value {
+    let get = |key: &str| scheme.get(key).cloned().unwrap_or(Value::Null);
+    let mut converted = match scheme.get("type").and_then(Value::as_str) {
+      Some("basic") => json!({ "type": "http", "scheme": "basic" }),
+      Some("oauth2") => {
+        let scopes = scheme.get("scopes").cloned().unwrap_or_else(|| json!({}));
+        let flow = match scheme.get("flow").and_then(Value::as_str) {
+          Some("implicit") => json!({ "implicit": {
+            "authorizationUrl": get("authorizationUrl"), "scopes": scopes
+          }}),
+          Some("password") => json!({ "password": {
+            "tokenUrl": get("tokenUrl"), "scopes": scopes
+          }}),
+          Some("application") => json!({ "clientCredentials": {
+            "tokenUrl": get("tokenUrl"), "scopes": scopes
+          }}),
+          _ => json!({ "authorizationCode": {
+            "authorizationUrl": get("authorizationUrl"),
+            "tokenUrl": get("tokenUrl"),
+            "scopes": scopes
+          }}),
+        };
+        json!({ "type": "oauth2", "flows": flow })
+      }
+      Some("apiKey") => json!({ "type": "apiKey", "name": get("name"), "in": get("in") }),
+      _ => {
+        self.warnings.push(format!(
+          "Kept the unknown security definition `{name}` as is"
+        ));
+        return scheme.clone();
+      }
+    };
+    if let (Some(converted), Some(description)) =
+      (converted.as_object_mut(), scheme.get("description"))
+    {
+      converted.insert("description".to_string(), description.clone());
+    }
+    converted
+  }
+}
+
+/// Check whether a parameter is a body or form data
+fn is_body_or_form_data(parameter: &Value) -> bool {
+  matches!(
+    parameter.get("in").and_then(Value::as_str),
+    Some("body" | "formData")
+  )
+}
+
+/// Convert a body parameter to a request body
+fn convert_body_parameter(
+  parameter: &Value,
+  consumes: &[String],
+) -> Value {
+  let schema = parameter
+    .get("schema")
+    .cloned()
+    .unwrap_or_else(|| json!({}));
+  let content = consumes
+    .iter()
+    .map(|media_type| (media_type.to_string(), json!({ "schema": schema })))
+    .collect::<Map<_, _>>();
+  let mut request_body = Map::from_iter([("content".to_string(), Value::Object(content))]);
+  for key in ["description", "required"] {
+    if let Some(value) = parameter.get(key) {
+      request_body.insert(key.to_string(), value.clone());
+    }
+  }
+  Value::Object(request_body)
+}
+
+/// Convert form data parameters to a request body
+fn convert_form_data_parameters(
+  parameters: &[Value],
+  consumes: &[String],
+) -> Value {
+  let has_file = parameters
+    .iter()
+    .any(|p| p.get("type").and_then(Value::as_str) == Some("file"));
+  let media_type = if has_file || consumes.iter().any(|c| c == "multipart/form-data") {
+    "multipart/form-data"
+  } else {
+    "application/x-www-form-urlencoded"
+  };
+  let mut properties = Map::new();
+  let mut required = vec![];
+  for parameter in parameters {
+    let name = parameter
+      .get("name")
+      .and_then(Value::as_str)
+      .unwrap_or_default()
+      .to_string();
+    let mut schema = PARAMETER_SCHEMA_KEYS
+      .iter()
+      .filter_map(|key| Some((key.to_string(), parameter.get(*key)?.clone())))
+      .collect::<Map<_, _>>();
+    if let Some(description) = parameter.get("description") {
+      schema.insert("description".to_string(), description.clone());
+    }
+    if parameter.get("required") == Some(&Value::Bool(true)) {
+      required.push(json!(name));
+    }
+    properties.insert(name, Value::Object(schema));
+  }
+  let mut schema = json!({ "type": "object", "properties": properties });
+  if !required.is_empty() {
+    schema["required"] = Value::Array(required);
+  }
+  json!({ "content": { media_type: { "schema": schema } } })
+}
+
+/// Convert a response, moving its schema and examples into content
+fn convert_response(
+  response: &Value,
+  produces: &[String],
+) -> Value {
+  let Some(response) = response.as_object() else {
+    return response.clone();
+  };
+  if response.contains_key("$ref") {
+    return Value::Object(response.clone());
+  }
+  let mut converted = Map::new();
+  let mut content = Map::new();
+  if let Some(schema) = response.get("schema") {
+    for media_type in produces {
+      content.insert(media_type.to_string(), json!({ "schema": schema }));
+    }
+  }
+  for (media_type, example) in response
+    .get("examples")
+    .and_then(Value::as_object)
+    .into_iter()
+    .flatten()
+  {
+    let media_type_object = content
+      .entry(media_type.to_string())
+      .or_insert_with(|| json!({}));
+    media_type_object["example"] = example.clone();
+  }
+  for (key, value) in response {
+    match key.as_str() {
+      "schema" | "examples" => {}
+      "headers" => {
+        let headers = value
+          .as_object()
+          .into_iter()
+          .flatten()
+          .map(|(name, header)| {
+            let mut converted_header = Map::new();
+            let mut schema = Map::new();
+            for (key, value) in header.as_object().into_iter().flatten() {
+              if PARAMETER_SCHEMA_KEYS.contains(&key.as_str()) {
+                schema.insert(key.to_string(), value.clone());
+              } else if key != "collectionFormat" {
+                converted_header.insert(key.to_string(), value.clone());
+              }
+            }
+            converted_header.insert("schema".to_string(), Value::Object(schema));
+            (name.to_string(), Value::Object(converted_header))
+          })
+          .collect::<Map<_, _>>();
+        converted.insert(key.to_string(), Value::Object(headers));
+      }
+      _ => {
+        converted.insert(key.to_string(), value.clone());
+      }
+    }
+  }
+  if !content.is_empty() {
+    converted.insert("content".to_string(), Value::Object(content));
+  }
+  Value::Object(converted)
+}
+
+/// Point refs at components and convert Swagger-only schema keywords
+fn rewrite_refs(
+  value: &mut Value,
+  body_parameter_names: &[String],
+) {
+  match value {
+    Value::Object(map) => {
+      if let Some(Value::String(reference)) = map.get_mut("$ref") {
+        let rewritten = if let Some(name) = reference.strip_prefix("#/definitions/") {
+          format!("#/components/schemas/{name}")
+        } else if let Some(name) = reference.strip_prefix("#/parameters/") {
+          if body_parameter_names.iter().any(|n| n == name) {
+            format!("#/components/requestBodies/{name}")
+          } else {
+            format!("#/components/parameters/{name}")
+          }
+        } else if let Some(name) = reference.strip_prefix("#/responses/") {
+          format!("#/components/responses/{name}")
+        } else {
+          reference.to_string()
+        };
+        *reference = rewritten;
+      }
+      if let Some(is_nullable) = map.shift_remove("x-nullable") {
+        map.insert("nullable".to_string(), is_nullable);
+      }
+      if map.get("type").and_then(Value::as_str) == Some("file") {
+        map.insert("type".to_string(), json!("string"));
+        map.insert("format".to_string(), json!("binary"));
+      }
+      if let Some(Value::String(property_name)) = map.get("discriminator").cloned() {
+        map.insert(
+          "discriminator".to_string(),
+          json!({ "propertyName": property_name }),
+        );
+      }
+      map
+        .values_mut()
+        .for_each(|value| rewrite_refs(value, body_parameter_names));
+    }
+    Value::Array(values) => values
+      .iter_mut()
+      .for_each(|value| rewrite_refs(value, body_parameter_names)),
+    _ => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn swagger_specs_are_converted_with_warnings() {
+    let swagger: Value = serde_yaml::from_str(
+      r##"
+      swagger: "2.0"
+      info: { title: Pets, version: 1.0.0 }
+      host: pets.example
+      basePath: /v1
+      schemes: [https]
+      produces: [application/json]
+      paths:
+        /pets:
+          get:
+            operationId: listPets
+            parameters:
+              - { name: tags, in: query, type: array, items: { type: string }, collectionFormat: tsv }
+              - { name: limit, in: query, type: integer, collectionFormat: csv }
+            responses:
+              "200":
+                description: Pets
+                schema: { type: array, items: { $ref: "#/definitions/Pet" } }
+          post:
+            operationId: addPet
+            consumes: [multipart/form-data]
+            parameters:
+              - { name: name, in: formData, type: string, required: true }
+              - { name: photo, in: formData, type: file }
+            responses:
+              "201": { $ref: "#/responses/Created" }
+      definitions:
+        Pet:
+          type: object
+          properties:
+            nickname: { type: string, x-nullable: true }
+      responses:
+        Created: { description: Created }
+      securityDefinitions:
+        token: { type: oauth2, flow: application, tokenUrl: https://pets.example/token, scopes: {} }
+      "##,
+    )
+    .expect("must parse");
+    assert!(is_swagger(&swagger));
+    let SwaggerConversion { spec, warnings } = convert_swagger(&swagger);
+    assert_eq!(spec["openapi"], "3.0.3");
+    assert_eq!(
+      spec["servers"],
+      json!([{ "url": "https://pets.example/v1" }])
+    );
+    let get = &spec["paths"]["/pets"]["get"];
+    assert_eq!(
+      get["parameters"][1],
+      json!({
+        "name": "limit", "in": "query", "schema": { "type": "integer" },
+        "style": "form", "explode": false
+      })
+    );
+    assert_eq!(
+      get["responses"]["200"]["content"]["application/json"]["schema"]["items"],
+      json!({ "$ref": "#/components/schemas/Pet" })
+    );
+    let post = &spec["paths"]["/pets"]["post"];
+    let form_schema = &post["requestBody"]["content"]["multipart/form-data"]["schema"];
+    assert_eq!(form_schema["required"], json!(["name"]));
+    assert_eq!(
+      form_schema["properties"]["photo"],
+      json!({ "type": "string", "format": "binary" })
+    );
+    assert_eq!(
+      post["responses"]["201"],
+      json!({ "$ref": "#/components/responses/Created" })
+    );
+    assert_eq!(
+      spec["components"]["schemas"]["Pet"]["properties"]["nickname"],
+      json!({ "type": "string", "nullable": true })
+    );
+    assert_eq!(
+      spec["components"]["securitySchemes"]["token"]["flows"]["clientCredentials"]["tokenUrl"],
+      "https://pets.example/token"
+    );
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings[0].contains("collectionFormat: tsv"));
+    assert!(warnings[1].contains("formData parameters of POST /pets"));
+  }
+}
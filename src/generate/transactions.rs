@@ -0,0 +1,284 @@
+//! Staged generation
+//!
+//! Tasks run on a copy of the crate in a staging dir next to it. Only once every task succeeded
+//! is the copy swapped in, and the previous crate is kept in a backup dir next to it, which
+//! [Transaction::rollback] swaps back. Contents are swapped rather than dirs, so the crate dir
+//! itself stays, with a shell that may be in it.
+//!
+//! Workspace members cannot be built from a copy outside the workspace members, so their tasks
+//! run in place on the crate, and the staging dir holds a snapshot that is restored on failure.
+//! Their staging and backup dirs live in `.olg/<member>` under the workspace root, out of reach
+//! of member globs.
+use crate::generate::cargos::{CargoConfigError, CargoWorkspace};
+use fs_err::tokio as fs;
+use std::{
+  ffi::OsStr,
+  io::Error as IOError,
+  path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Transaction errors
+#[derive(Debug, Error)]
+pub enum TransactionError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  CargoConfigError(#[from] CargoConfigError),
+  #[error("No backup to roll back to at {0:?}")]
+  MissingBackup(PathBuf),
+  #[error("Could not find crate dir at {0:?}")]
+  MissingCrateDir(PathBuf),
+}
+
+/// A staged generation of a crate
+#[derive(Clone, Debug)]
+pub struct Transaction {
+  crate_dir: PathBuf,
+  staging_dir: PathBuf,
+  backup_dir: PathBuf,
+  is_in_place: bool,
+}
+impl Transaction {
+  /// Entries that are not copied into the staging dir, but moved over on commit
+  pub const SKIPPED_NAMES: [&'static str; 1] = ["target"];
+
+  /// Instantiate for a crate dir
+  pub fn new(crate_dir: impl AsRef<Path>) -> Self {
+    let crate_dir = crate_dir.as_ref().to_path_buf();
+    let get_sibling = |suffix: &str| {
+      let crate_dir_name = crate_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+      crate_dir.with_file_name(format!(".{crate_dir_name}.olg-{suffix}"))
+    };
+    Self {
+      staging_dir: get_sibling("staging"),
+      backup_dir: get_sibling("backup"),
+      crate_dir,
+      is_in_place: false,
+    }
+  }
+
+  /// Instantiate for a crate dir whose tasks run in place, keeping state in another dir
+  pub fn new_in_place(
+    crate_dir: impl AsRef<Path>,
+    state_dir: impl AsRef<Path>,
+  ) -> Self {
+    Self {
+      crate_dir: crate_dir.as_ref().to_path_buf(),
+      staging_dir: state_dir.as_ref().join("staging"),
+      backup_dir: state_dir.as_ref().join("backup"),
+      is_in_place: true,
+    }
+  }
+
+  /// Instantiate for a crate dir, running in place if it is a workspace member
+  pub fn try_for_crate_dir(crate_dir: impl AsRef<Path>) -> Result<Self, TransactionError> {
+    Ok(match CargoWorkspace::find_enclosing(crate_dir.as_ref())? {
+      Some(workspace) => Self::new_in_place(
+        crate_dir,
+        workspace
+          .root_dir
+          .join(".olg")
+          .join(workspace.member_path.replace('/', "__")),
+      ),
+      None => Self::new(crate_dir),
+    })
+  }
+
+  /// Get the dir the tasks run in
+  pub fn get_work_dir(&self) -> &Path {
+    if self.is_in_place {
+      &self.crate_dir
+    } else {
+      &self.staging_dir
+    }
+  }
+
+  /// Get the dir the crate is copied into
+  pub fn get_staging_dir(&self) -> &Path {
+    &self.staging_dir
+  }
+
+  /// Get the dir the previous crate is kept in
+  pub fn get_backup_dir(&self) -> &Path {
+    &self.backup_dir
+  }
+
+  /// Copy the crate into a fresh staging dir
+  pub async fn begin(&self) -> Result<(), TransactionError> {
+    if !self.crate_dir.is_dir() {
+      return Err(TransactionError::MissingCrateDir(self.crate_dir.clone()));
+    }
+    remove_dir_if_exists(&self.staging_dir).await?;
+    crate::fs::copy_dir_all(&self.crate_dir, &self.staging_dir, &Self::SKIPPED_NAMES).await?;
+    info!("Staging generation in {:?}", self.staging_dir);
+    Ok(())
+  }
+
+  /// Swap the staging dir in, keeping the crate as the backup
+  pub async fn commit(&self) -> Result<(), TransactionError> {
+    remove_dir_if_exists(&self.backup_dir).await?;
+    if self.is_in_place {
+      fs::rename(&self.staging_dir, &self.backup_dir).await?;
+      info!("Kept the previous crate in {:?}", self.backup_dir);
+      return Ok(());
+    }
+    move_entries(&self.crate_dir, &self.backup_dir).await?;
+    if let Err(e) = move_entries(&self.staging_dir, &self.crate_dir).await {
+      move_entries(&self.crate_dir, &self.staging_dir).await?;
+      move_entries(&self.backup_dir, &self.crate_dir).await?;
+      return Err(e.into());
+    }
+    remove_dir_if_exists(&self.staging_dir).await?;
+    info!(
+      "Swapped in the generated crate, keeping the previous one in {:?}",
+      self.backup_dir
+    );
+    Ok(())
+  }
+
+  /// Drop the staging dir, leaving the crate as it was before [Self::begin]
+  pub async fn abort(&self) -> Result<(), TransactionError> {
+    if self.is_in_place {
+      remove_entries(&self.crate_dir).await?;
+      move_entries(&self.staging_dir, &self.crate_dir).await?;
+    }
+    warn!(
+      "Generation failed, the crate at {:?} was left untouched",
+      self.crate_dir
+    );
+    remove_dir_if_exists(&self.staging_dir).await?;
+    Ok(())
+  }
+
+  /// Swap the backup and the crate
+  ///
+  /// The rolled back crate becomes the backup, so rolling back twice redoes the generation.
+  pub async fn rollback(&self) -> Result<(), TransactionError> {
+    if !self.backup_dir.is_dir() {
+      return Err(TransactionError::MissingBackup(self.backup_dir.clone()));
+    }
+    remove_dir_if_exists(&self.staging_dir).await?;
+    move_entries(&self.crate_dir, &self.staging_dir).await?;
+    move_entries(&self.backup_dir, &self.crate_dir).await?;
+    move_entries(&self.staging_dir, &self.backup_dir).await?;
+    remove_dir_if_exists(&self.staging_dir).await?;
+    info!("Rolled back the crate at {:?}", self.crate_dir);
+    Ok(())
+  }
+}
+
+/// Move the entries of a dir into another, but the [Transaction::SKIPPED_NAMES]
+async fn move_entries(
+  from_dir: &Path,
+  to_dir: &Path,
+) -> Result<(), IOError> {
+  fs::create_dir_all(to_dir).await?;
+  let mut entries = fs::read_dir(from_dir).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    if !is_skipped(&entry.file_name()) {
+      fs::rename(entry.path(), to_dir.join(entry.file_name())).await?;
+    }
+  }
+  Ok(())
+}
+
+/// Remove the entries of a dir, but the [Transaction::SKIPPED_NAMES]
+async fn remove_entries(dir: &Path) -> Result<(), IOError> {
+  let mut entries = fs::read_dir(dir).await?;
+  while let Some(entry) = entries.next_entry().await? {
+    if is_skipped(&entry.file_name()) {
+      continue;
+    }
+    if entry.file_type().await?.is_dir() {
+      fs::remove_dir_all(entry.path()).await?;
+    } else {
+      fs::remove_file(entry.path()).await?;
+    }
+  }
+  Ok(())
+}
+
+/// Whether an entry is one of the [Transaction::SKIPPED_NAMES]
+fn is_skipped(name: &OsStr) -> bool {
+  Transaction::SKIPPED_NAMES
+    .iter()
+    .any(|skipped| name == *skipped)
+}
+
+/// Remove a dir and its contents if it exists
+async fn remove_dir_if_exists(path: &Path) -> Result<(), IOError> {
+  if path.exists() {
+    fs::remove_dir_all(path).await?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::os::unix::fs::MetadataExt;
+
+  #[tokio::test]
+  async fn commit_keeps_backup_and_rollback_swaps_it_back() {
+    let root = std::env::temp_dir().join(format!("olg_transaction_{}", std::process::id()));
+    let crate_dir = root.join("pets");
+    fs::create_dir_all(crate_dir.join("target"))
+      .await
+      .expect("must create dirs");
+    fs::write(crate_dir.join("lib.rs"), "old")
+      .await
+      .expect("must write");
+    let get_inode = |path: &Path| std::fs::metadata(path).expect("must stat").ino();
+    let crate_dir_inode = get_inode(&crate_dir);
+    let transaction = Transaction::new(&crate_dir);
+    transaction.begin().await.expect("must begin");
+    assert!(!transaction.get_staging_dir().join("target").exists());
+    fs::write(transaction.get_staging_dir().join("lib.rs"), "new")
+      .await
+      .expect("must write");
+    transaction.commit().await.expect("must commit");
+    let read = |path: PathBuf| std::fs::read_to_string(path).expect("must read");
+    assert_eq!(read(crate_dir.join("lib.rs")), "new");
+    assert!(crate_dir.join("target").is_dir());
+    assert_eq!(get_inode(&crate_dir), crate_dir_inode);
+    assert!(!transaction.get_staging_dir().exists());
+    assert_eq!(read(transaction.get_backup_dir().join("lib.rs")), "old");
+    transaction.rollback().await.expect("must roll back");
+    assert_eq!(read(crate_dir.join("lib.rs")), "old");
+    assert_eq!(read(transaction.get_backup_dir().join("lib.rs")), "new");
+    assert_eq!(get_inode(&crate_dir), crate_dir_inode);
+    fs::remove_dir_all(root).await.expect("must clean up");
+  }
+
+  #[tokio::test]
+  async fn aborting_in_place_restores_the_crate() {
+    let root =
+      std::env::temp_dir().join(format!("olg_transaction_in_place_{}", std::process::id()));
+    let crate_dir = root.join("pets");
+    fs::create_dir_all(crate_dir.join("target"))
+      .await
+      .expect("must create dirs");
+    fs::write(crate_dir.join("lib.rs"), "old")
+      .await
+      .expect("must write");
+    let transaction = Transaction::new_in_place(&crate_dir, root.join(".olg"));
+    transaction.begin().await.expect("must begin");
+    assert_eq!(transaction.get_work_dir(), crate_dir);
+    fs::write(crate_dir.join("lib.rs"), "broken")
+      .await
+      .expect("must write");
+    transaction.abort().await.expect("must abort");
+    assert_eq!(
+      std::fs::read_to_string(crate_dir.join("lib.rs")).expect("must read"),
+      "old"
+    );
+    assert!(crate_dir.join("target").is_dir());
+    assert!(!transaction.get_staging_dir().exists());
+    fs::remove_dir_all(root).await.expect("must clean up");
+  }
+}
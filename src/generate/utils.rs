@@ -1,22 +1,18 @@
 //! Codegen utilities
-use crate::{cli::Cli, testing};
+use crate::{
+  caches::{self, Cache},
+  cli::Cli,
+  generate::{locks::Locker, makefiles::MakefileEnv, specs::fetches},
+  process::{ProcessJob, ProcessOutput},
+  testing,
+};
 use std::{
   env,
-  io::Error as IOError,
   path::{Path, PathBuf},
-  process::Output,
 };
-use thiserror::Error;
-use tokio::process::{Child, Command};
+use tracing::debug;
 
-/// Testing errors
-#[derive(Debug, Error)]
-pub enum ProcessError {
-  #[error(transparent)]
-  IOError(#[from] IOError),
-  #[error("Process failed \n {0}")]
-  Failure(String),
-}
+pub use crate::process::ProcessError;
 
 #[macro_export]
 /// Just makes a vec of specified items from arguments
@@ -31,39 +27,81 @@ pub async fn run_cargo_job<T: AsRef<str>, P: AsRef<Path>>(
   args: &[T],
   cwd_opt: Option<P>,
   description_opt: Option<T>,
-) -> Result<Output, ProcessError> {
-  let mut command = Command::new("cargo");
-  let cwd_string = if let Some(cwd) = cwd_opt.as_ref() {
-    command.current_dir(&cwd.as_ref());
-    format!("in {}", cwd.as_ref().to_string_lossy())
-  } else {
-    String::default()
-  };
-  let args_vec = &Vec::from_iter(args.iter().map(AsRef::as_ref));
-  let arg_string = args_vec
-    .iter()
-    .map(|a| format!("\"{a}\" "))
-    .collect::<String>();
-  let description = description_opt
-    .map(|s| s.as_ref().to_string())
-    .unwrap_or_else(|| format!("Running `cargo` {arg_string} {cwd_string}.",));
-  println!("{description}");
-  let child: Child = command.args(args_vec).spawn()?;
-  child.wait_with_output().await.map_err(ProcessError::from)
+) -> Result<ProcessOutput, ProcessError> {
+  let mut job = ProcessJob::new("cargo")
+    .args(args)
+    .description_opt(description_opt);
+  if let Some(cwd) = cwd_opt {
+    job = job.cwd(cwd.as_ref());
+  }
+  job.run().await
 }
 
 /// Attempt to run a cargo make task
 pub async fn run_cargo_make_task<T: AsRef<str>>(
   cli: &Cli,
   task_name: T,
-) -> Result<Output, ProcessError> {
-  let output_project_dir = &cli.get_output_project_dir();
-  run_cargo_job(
-    &["make", task_name.as_ref()],
-    Some(output_project_dir),
-    None,
-  )
-  .await
+) -> Result<ProcessOutput, ProcessError> {
+  let task_name = task_name.as_ref();
+  let mut job = ProcessJob::new("cargo")
+    .args(["make", task_name])
+    .cwd(cli.get_output_project_dir())
+    .env(Locker::UPDATE_LOCK_ENV, cli.update_lock.to_string())
+    .env(Cache::OFFLINE_ENV, cli.offline.to_string());
+  for (key, value) in caches::get_generator_env(cli.offline).await {
+    job = job.env(key, value);
+  }
+  // The makefile holds the spec url redacted, its secret query values go through the env
+  if let Some(api_spec_url) = &cli.inner_cli.api_spec_url_opt {
+    for (key, value) in fetches::get_secret_query_env_vars(api_spec_url) {
+      job = job.env(key, value);
+    }
+  }
+  job
+    .log_file_path_opt(Some(cli.get_log_file_path(task_name)))
+    .timeout_opt(cli.get_process_timeout_opt())
+    .run()
+    .await
+}
+
+/// Find an executable in the `PATH`
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+  env::var_os("PATH").and_then(|paths| {
+    env::split_paths(&paths)
+      .map(|dir| dir.join(program))
+      .find(|path| path.is_file())
+  })
+}
+
+/// Get the version of the openapi generator cli, or `unknown` when it cannot run
+pub async fn get_openapi_generator_version(
+  crate_dir: impl AsRef<Path>,
+  offline: bool,
+) -> String {
+  let Some(script) = find_in_path(MakefileEnv::OPEN_API_GENERATOR_CLI_SCRIPT) else {
+    return "unknown".to_string();
+  };
+  let mut job = ProcessJob::new(script.to_string_lossy())
+    .args(["version"])
+    .cwd(crate_dir.as_ref());
+  for (key, value) in caches::get_generator_env(offline).await {
+    job = job.env(key, value);
+  }
+  job
+    .description_opt(Some("Getting the openapi generator version"))
+    .run()
+    .await
+    .map_err(|e| debug!("Could not get the openapi generator version: {e}"))
+    .ok()
+    .and_then(|output| {
+      output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .map(str::to_string)
+    })
+    .unwrap_or_else(|| "unknown".to_string())
 }
 
 /// trim leading whitespace from multiline code resulting in a single string
@@ -83,7 +121,7 @@ pub fn trim_lines_vec(s: &str) -> Vec<String> {
           target = " ".repeat(leading_whitespace);
         }
         if !target.is_empty() {
-          result.push(format!("{}", line.strip_prefix(&target).unwrap_or(line)));
+          result.push(line.strip_prefix(&target).unwrap_or(line).to_string());
         }
         (target, result)
       },
@@ -91,6 +129,57 @@ pub fn trim_lines_vec(s: &str) -> Vec<String> {
     .1
 }
 
+/// Get the start and end markers of a generated section
+pub fn get_section_markers(name: &str) -> (String, String) {
+  (
+    format!("<!-- olg:{name}:start -->"),
+    format!("<!-- olg:{name}:end -->"),
+  )
+}
+/// Replace the generated section between its markers, or add it when missing
+///
+/// The section is appended, or prepended when `prepend` is set. Running again with the same
+/// section leaves the contents unchanged.
+pub fn replace_marked_section(
+  contents: &str,
+  name: &str,
+  section: &str,
+  prepend: bool,
+) -> String {
+  let (start_marker, end_marker) = get_section_markers(name);
+  let marked_section = format!(
+    "{start_marker}
+{}
+{end_marker}",
+    section.trim()
+  );
+  let span_opt = contents.find(&start_marker).and_then(|start| {
+    contents[start..]
+      .find(&end_marker)
+      .map(|end| (start, start + end + end_marker.len()))
+  });
+  match span_opt {
+    Some((start, end)) => format!("{}{marked_section}{}", &contents[..start], &contents[end..]),
+    None if contents.trim().is_empty() => format!(
+      "{marked_section}
+"
+    ),
+    None if prepend => format!(
+      "{marked_section}
+
+{}",
+      contents.trim_start()
+    ),
+    None => format!(
+      "{}
+
+{marked_section}
+",
+      contents.trim_end()
+    ),
+  }
+}
+
 /// Get the name of this crate
 pub fn get_this_crate_name() -> &'static str {
   env!("CARGO_CRATE_NAME")
@@ -109,7 +198,7 @@ pub fn get_temp_root_dir() -> PathBuf {
 }
 /// Get temp project subdir
 pub fn get_temp_subdir() -> PathBuf {
-  get_temp_root_dir().join(&format!(
+  get_temp_root_dir().join(format!(
     "{}_{}",
     get_this_crate_name(),
     testing::TEST_SUBDIR_NAME
@@ -127,4 +216,20 @@ mod test_mod_name {
     "#;
     assert_eq!(trim_lines_vec(test), vec!["line zero", "  line one"])
   }
+  #[test]
+  fn marked_sections_are_replaced() {
+    let contents = replace_marked_section("# lib\n", "about", "v1", false);
+    assert_eq!(
+      contents,
+      "# lib\n\n<!-- olg:about:start -->\nv1\n<!-- olg:about:end -->\n"
+    );
+    let contents = replace_marked_section(&contents, "about", "v2", false);
+    assert_eq!(
+      replace_marked_section(&contents, "about", "v2", false),
+      contents
+    );
+    assert!(contents.contains("\nv2\n") && !contents.contains("v1"));
+    let contents = replace_marked_section(&contents, "top", "first", true);
+    assert!(contents.starts_with("<!-- olg:top:start -->\nfirst"));
+  }
 }
@@ -0,0 +1,295 @@
+//! Crate versioning
+//!
+//! On regenerate, the next crate version is computed from the [spec diff](SpecDiff): major for
+//! breaking changes, minor for additions and patch for changes that do not affect the client,
+//! like descriptions. Following cargo's convention, a `0.x` crate bumps its minor version for
+//! breaking changes and its patch version otherwise. With [VersionSources::InfoVersion], the
+//! version is taken from the spec's `info.version` instead, on every run.
+//!
+//! The version is written to the generator config's `packageVersion` and the cargo manifest.
+use crate::{
+  cli::{Cli, Paths},
+  generate::{
+    makefiles::MakefileEnv,
+    specs::diffs::{self, SpecDiff},
+    yamls::OpenAPIRustGeneratorConfigs,
+  },
+  reports,
+};
+use fs_err::tokio as fs;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_yaml::Error as SerdeYAMLError;
+use std::{io::Error as IOError, path::Path};
+use strum::EnumProperty;
+use thiserror::Error;
+use toml_edit::{Document, TomlError as TomlEditError};
+use tracing::{debug, info};
+
+/// Versioning errors
+#[derive(Debug, Error)]
+pub enum VersionError {
+  #[error(transparent)]
+  IOError(#[from] IOError),
+  #[error(transparent)]
+  SerdeYAMLError(#[from] SerdeYAMLError),
+  #[error(transparent)]
+  TomlEditError(#[from] TomlEditError),
+}
+
+/// Where the crate version comes from
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VersionSources {
+  /// Bump the previous version according to the spec diff, on regenerate
+  #[default]
+  SpecDiff,
+  /// Use the spec's `info.version`
+  InfoVersion,
+}
+
+/// Version bumps, from the smallest
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum VersionBumps {
+  None,
+  Patch,
+  Minor,
+  Major,
+}
+impl From<&SpecDiff> for VersionBumps {
+  fn from(spec_diff: &SpecDiff) -> Self {
+    if spec_diff.is_breaking() {
+      Self::Major
+    } else if spec_diff.non_breaking_changes > 0 {
+      Self::Minor
+    } else if spec_diff.changed {
+      Self::Patch
+    } else {
+      Self::None
+    }
+  }
+}
+impl VersionBumps {
+  /// Bump a version, dropping its pre-release and build metadata
+  pub fn apply(
+    self,
+    version: &Version,
+  ) -> Version {
+    let Version {
+      major,
+      minor,
+      patch,
+      ..
+    } = *version;
+    let bump = match (self, major) {
+      (Self::Major, 0) => Self::Minor,
+      (Self::Minor, 0) => Self::Patch,
+      (bump, _) => bump,
+    };
+    match bump {
+      Self::None => version.clone(),
+      Self::Patch => Version::new(major, minor, patch + 1),
+      Self::Minor => Version::new(major, minor + 1, 0),
+      Self::Major => Version::new(major + 1, 0, 0),
+    }
+  }
+}
+
+/// Parse the spec's `info.version` leniently, like `v2`, `2.1` or `2.1.0-beta`
+pub fn get_info_version(spec: &Value) -> Option<Version> {
+  let info_version = spec.pointer("/info/version")?.as_str()?.trim();
+  let info_version = info_version.strip_prefix('v').unwrap_or(info_version);
+  Version::parse(info_version).ok().or_else(|| {
+    let mut parts = info_version.split('.').map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    parts
+      .next()
+      .is_none()
+      .then(|| Version::new(major, minor, 0))
+  })
+}
+
+/// Whether the manifest's `package.version` is inherited with `version.workspace = true`
+fn is_version_inherited(document: &Document) -> bool {
+  document
+    .get("package")
+    .and_then(|package| package.get("version"))
+    .and_then(|version| version.get("workspace"))
+    .and_then(|workspace| workspace.as_bool())
+    .unwrap_or_default()
+}
+
+/// Read the version of the crate in the output dir
+///
+/// A version inherited from the workspace is not read, so it is never bumped.
+pub async fn read_crate_version(cli: &Cli) -> Option<Version> {
+  let cargo_toml_path = cli.get_output_project_subpath(&Paths::CargoTomlFile);
+  let contents = fs::read_to_string(&cargo_toml_path).await.ok()?;
+  let document = contents.parse::<Document>().ok()?;
+  if is_version_inherited(&document) {
+    debug!("Crate version is inherited from the workspace");
+    return None;
+  }
+  let version = document.get("package")?.get("version")?.as_str()?;
+  Version::parse(version)
+    .map_err(|e| debug!("Crate version `{version}` is not semver: {e}"))
+    .ok()
+}
+
+/// Set the crate version after generation
+///
+/// Bumps the previous version according to the spec diff, or follows the spec's `info.version`,
+/// depending on the project config. Does nothing when there is no version to set.
+pub async fn update_crate_version(
+  cli: &Cli,
+  previous_version_opt: Option<&Version>,
+  spec_diff_opt: Option<&SpecDiff>,
+) -> Result<(), VersionError> {
+  let version_opt = match cli.project_config.version_from {
+    VersionSources::InfoVersion => {
+      let info_version_opt = diffs::read_crate_spec(cli)
+        .await
+        .and_then(|spec| get_info_version(&spec));
+      if info_version_opt.is_none() {
+        reports::record_warning(
+          "The spec's `info.version` is not a version, keeping the crate version",
+        );
+      }
+      info_version_opt
+    }
+    VersionSources::SpecDiff => match (previous_version_opt, spec_diff_opt) {
+      (Some(previous_version), Some(spec_diff)) => {
+        Some(VersionBumps::from(spec_diff).apply(previous_version))
+      }
+      _ => None,
+    },
+  };
+  let Some(version) = version_opt else {
+    return Ok(());
+  };
+  match previous_version_opt {
+    Some(previous_version) if previous_version != &version => {
+      info!("Crate version changed from {previous_version} to {version}")
+    }
+    _ => info!("Crate version is {version}"),
+  }
+  write_crate_version(cli.get_output_project_dir(), &version).await
+}
+
+/// Write the version to the generator config and the cargo manifest of a crate
+///
+/// A manifest version inherited from the workspace, or a manifest without a `[package]` table, is
+/// left unchanged with a warning.
+pub async fn write_crate_version(
+  crate_dir: impl AsRef<Path>,
+  version: &Version,
+) -> Result<(), VersionError> {
+  let generator_config_path = crate_dir
+    .as_ref()
+    .join(MakefileEnv::OPEN_API_GENERATOR_CONFIG_FILE);
+  if generator_config_path.is_file() {
+    let contents = fs::read_to_string(&generator_config_path).await?;
+    let mut generator_configs: OpenAPIRustGeneratorConfigs = serde_yaml::from_str(&contents)?;
+    generator_configs.packageVersion = version.to_string();
+    crate::fs::write(
+      generator_config_path,
+      serde_yaml::to_string(&generator_configs)?,
+      Some("Crate version in generator configs"),
+    )
+    .await?;
+  }
+  let cargo_toml_path = crate_dir.as_ref().join(
+    Paths::CargoTomlFile
+      .get_str("path")
+      .expect("must get Cargo.toml path"),
+  );
+  if cargo_toml_path.is_file() {
+    let mut document = fs::read_to_string(&cargo_toml_path)
+      .await?
+      .parse::<Document>()?;
+    if is_version_inherited(&document) {
+      reports::record_warning(format!(
+        "The crate version is inherited from the workspace, not setting it to {version}"
+      ));
+      return Ok(());
+    }
+    let Some(package) = document
+      .get_mut("package")
+      .and_then(|package| package.as_table_like_mut())
+    else {
+      reports::record_warning(format!(
+        "The cargo manifest has no `[package]` table, not setting the crate version to {version}"
+      ));
+      return Ok(());
+    };
+    package.insert("version", toml_edit::value(version.to_string()));
+    crate::fs::write(
+      cargo_toml_path,
+      document.to_string(),
+      Some("Crate version in cargo manifest"),
+    )
+    .await?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn versions_are_bumped_and_parsed() {
+    let version = Version::new(1, 4, 2);
+    assert_eq!(VersionBumps::Major.apply(&version), Version::new(2, 0, 0));
+    assert_eq!(VersionBumps::Minor.apply(&version), Version::new(1, 5, 0));
+    assert_eq!(VersionBumps::Patch.apply(&version), Version::new(1, 4, 3));
+    assert_eq!(VersionBumps::None.apply(&version), version);
+    let version = Version::new(0, 3, 1);
+    assert_eq!(VersionBumps::Major.apply(&version), Version::new(0, 4, 0));
+    assert_eq!(VersionBumps::Minor.apply(&version), Version::new(0, 3, 2));
+    assert_eq!(VersionBumps::Patch.apply(&version), Version::new(0, 3, 2));
+    assert_eq!(VersionBumps::None.apply(&version), version);
+    let version = Version::parse("0.0.7-alpha.1").expect("must parse version");
+    assert_eq!(VersionBumps::Major.apply(&version), Version::new(0, 1, 0));
+    assert_eq!(VersionBumps::Minor.apply(&version), Version::new(0, 0, 8));
+    for (info_version, version_opt) in [
+      ("2.1.0-beta.1", Version::parse("2.1.0-beta.1").ok()),
+      ("v3", Some(Version::new(3, 0, 0))),
+      ("1.2", Some(Version::new(1, 2, 0))),
+      ("2023-01-01", None),
+    ] {
+      let spec = json!({ "info": { "version": info_version } });
+      assert_eq!(get_info_version(&spec), version_opt, "{info_version}");
+    }
+  }
+
+  #[tokio::test]
+  async fn inherited_or_missing_versions_are_kept() {
+    let crate_dir = std::env::temp_dir().join(format!("olg_versions_{}", std::process::id()));
+    std::fs::create_dir_all(&crate_dir).expect("must create crate dir");
+    let cargo_toml_path = crate_dir.join("Cargo.toml");
+    for manifest in [
+      "[package]\nname = \"petstore\"\nversion.workspace = true\n",
+      "[package]\nname = \"petstore\"\nversion = { workspace = true }\n",
+      "package = \"petstore\"\n",
+    ] {
+      std::fs::write(&cargo_toml_path, manifest).expect("must write manifest");
+      write_crate_version(&crate_dir, &Version::new(1, 2, 0))
+        .await
+        .expect("must keep version");
+      let contents = std::fs::read_to_string(&cargo_toml_path).expect("must read manifest");
+      assert_eq!(contents, manifest);
+    }
+    let manifest = "[package]\nname = \"petstore\" # the name\nversion = \"0.1.0\"\n";
+    std::fs::write(&cargo_toml_path, manifest).expect("must write manifest");
+    write_crate_version(&crate_dir, &Version::new(0, 2, 0))
+      .await
+      .expect("must write version");
+    let contents = std::fs::read_to_string(&cargo_toml_path).expect("must read manifest");
+    assert_eq!(contents, manifest.replace("0.1.0", "0.2.0"));
+    std::fs::remove_dir_all(&crate_dir).expect("must remove crate dir");
+  }
+}
@@ -3,14 +3,21 @@
 use crate::{
   cli::Cli,
   fs::write,
-  generate::{errors::ParameterError, makefiles::MakefileEnv},
+  generate::{
+    errors::{ParameterError, SpecError},
+    makefiles::MakefileEnv,
+    specs::{bundles, fetches::SpecFetcher},
+  },
+  process::{ProcessError, ProcessJob},
   testing,
 };
 use fs_err::tokio as fs;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Error as SerdeYAMLError;
-use std::io::Error as IOError;
+use std::{env, io::Error as IOError, path::Path};
 use thiserror::Error;
+use tokio::io::{self, AsyncReadExt};
+use url::Url;
 /// Errors that can happen with yaml generation
 #[derive(Debug, Error)]
 pub enum YAMLGenerationError {
@@ -20,6 +27,12 @@ pub enum YAMLGenerationError {
   SerdeYAMLError(#[from] SerdeYAMLError),
   #[error(transparent)]
   ParameterError(#[from] ParameterError),
+  #[error(transparent)]
+  SpecError(#[from] SpecError),
+  #[error("Spec command failed")]
+  SpecCommandFailed(#[source] ProcessError),
+  #[error("No spec was read from {0}")]
+  EmptySpec(String),
 }
 
 /// Rust OpenAPI Generator Configs  
@@ -80,18 +93,60 @@ impl OpenAPIRustGeneratorConfigs {
     }
   }
   /// Copy spec file if applicable
+  ///
+  /// The spec is read from the `--spec-file` path, stdin, or the stdout of the spec command,
+  /// which runs in the dir it was given in. A spec with external `$ref`s is [bundled](bundles)
+  /// into a single file, resolving refs from the spec file, or from the dir it was read in
+  /// otherwise.
   pub async fn copy_spec_file(
     &self,
     cli: &Cli,
   ) -> Result<(), YAMLGenerationError> {
-    if let Some(local_api_spec_filepath) = cli.inner_cli.local_api_spec_filepath_opt.as_ref() {
-      let spec_file_name = cli.try_get_spec_file_name()?;
-      let contents = fs::read(local_api_spec_filepath).await?;
-      write(spec_file_name, contents, Some("Copy spec file")).await?;
-      Ok(())
+    let spec_file_name = cli.try_get_spec_file_name()?;
+    let get_spec_url_in =
+      |dir: &Path| Url::from_file_path(dir.join(&spec_file_name)).expect("must get spec file url");
+    let cwd_spec_url = || get_spec_url_in(&env::current_dir().unwrap_or_default());
+    let (contents, base_url, source) = if cli.is_spec_from_stdin() {
+      let mut contents = String::new();
+      io::stdin().read_to_string(&mut contents).await?;
+      (contents, cwd_spec_url(), "stdin".to_string())
+    } else if let Some(local_api_spec_filepath) = cli.local_api_spec_filepath_opt.as_ref() {
+      let contents = fs::read_to_string(local_api_spec_filepath).await?;
+      let base_url = Url::from_file_path(fs::canonicalize(local_api_spec_filepath).await?)
+        .expect("must get spec file url");
+      (contents, base_url, format!("{local_api_spec_filepath:?}"))
+    } else if let Some(spec_command) = cli.get_spec_command_opt() {
+      let spec_command_dir = match cli.project_config.spec_command_dir_opt.as_ref() {
+        Some(spec_command_dir) => spec_command_dir.clone(),
+        None => env::current_dir()?,
+      };
+      let output = ProcessJob::new("sh")
+        .args(["-c", spec_command])
+        .cwd(&spec_command_dir)
+        .description_opt(Some(format!("Running spec command `{spec_command}`")))
+        .log_file_path_opt(Some(cli.get_log_file_path("spec-command")))
+        .timeout_opt(cli.get_process_timeout_opt())
+        .run()
+        .await
+        .map_err(YAMLGenerationError::SpecCommandFailed)?;
+      (
+        output.stdout,
+        get_spec_url_in(&spec_command_dir),
+        format!("the output of `{spec_command}`"),
+      )
     } else {
-      Ok(())
+      return Ok(());
+    };
+    if contents.trim().is_empty() {
+      return Err(YAMLGenerationError::EmptySpec(source));
+    }
+    let spec_file_path = cli.get_output_project_dir().join(spec_file_name);
+    let fetcher = SpecFetcher::from_env(None, cli.offline).await?;
+    match bundles::try_bundle_contents(&contents, &base_url, &fetcher).await? {
+      Some(bundled) => write(spec_file_path, bundled, Some("Bundle spec file")).await?,
+      None => write(spec_file_path, contents, Some("Copy spec file")).await?,
     }
+    Ok(())
   }
   /// Write configs to yaml file
   pub async fn write_to_yaml_file(
@@ -116,13 +171,9 @@ impl OpenAPIRustGeneratorConfigs {
 /// Returns the name of the spec created
 pub async fn create_testing_spec_file(cli: &Cli) -> Result<(), YAMLGenerationError> {
   let petstore_yaml: &'static str = testing::PETSTORE_YAML;
-  let output_file_path = cli
-    .inner_cli
-    .local_api_spec_filepath_opt
-    .clone()
-    .ok_or_else(|| {
-      YAMLGenerationError::ParameterError(ParameterError::TestingYAMLSpecPathMissing)
-    })?;
+  let output_file_path = cli.inner_cli.local_api_spec_filepath_opt.clone().ok_or(
+    YAMLGenerationError::ParameterError(ParameterError::TestingYAMLSpecPathMissing),
+  )?;
   write(
     &output_file_path,
     petstore_yaml,
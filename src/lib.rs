@@ -1,6 +1,12 @@
 #![doc = include_str!("../README.md")]
 
+pub mod caches;
 pub mod cli;
+pub mod diagnostics;
+pub mod doctor;
 pub mod fs;
 pub mod generate;
+pub mod logging;
+pub mod process;
+pub mod reports;
 pub mod testing;
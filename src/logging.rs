@@ -0,0 +1,56 @@
+//! Structured logging
+//!
+//! Diagnostics are emitted with [tracing] to stderr, so that stdout stays free for reports.
+//! The level defaults to `info` and is raised with `-v` or lowered with `-q`; `RUST_LOG`
+//! overrides both. Stages and child processes run in named spans.
+use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+/// Log output formats
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormats {
+  /// Human-readable lines
+  #[default]
+  Text,
+  /// One JSON object per line
+  Json,
+}
+
+/// Get the max log level for the given number of `-v` and `-q` flags
+pub fn get_level_filter(
+  verbose: u8,
+  quiet: u8,
+) -> LevelFilter {
+  match i16::from(verbose) - i16::from(quiet) {
+    i16::MIN..=-2 => LevelFilter::ERROR,
+    -1 => LevelFilter::WARN,
+    0 => LevelFilter::INFO,
+    1 => LevelFilter::DEBUG,
+    _ => LevelFilter::TRACE,
+  }
+}
+
+/// Install the global logger
+///
+/// Does nothing if a logger is already installed.
+pub fn init_logging(
+  verbose: u8,
+  quiet: u8,
+  log_format: LogFormats,
+) {
+  let env_filter = EnvFilter::builder()
+    .with_default_directive(get_level_filter(verbose, quiet).into())
+    .from_env_lossy();
+  let builder = tracing_subscriber::fmt()
+    .with_env_filter(env_filter)
+    .with_writer(std::io::stderr)
+    .with_ansi(std::io::stderr().is_terminal())
+    .with_target(false);
+  let _ = match log_format {
+    LogFormats::Text => builder.try_init(),
+    LogFormats::Json => builder.json().with_current_span(true).try_init(),
+  };
+}
@@ -1,84 +1,245 @@
 //! Open api lib generator CLI
 
+use semver::Version;
+use serde_json::Value;
+use std::process::ExitCode;
+use tracing::error;
+
 use openapi_lib_generator::{
+  caches::run_cache_command,
   cli::*,
+  diagnostics::{Diagnostic, ExitCodes},
+  doctor::run_doctor,
+  fs,
   generate::{
-    crate_scaffolds,
-    makefiles::{MakefileSpec, TaskNames},
-    utils::{run_cargo_make_task, ProcessError},
+    batches::run_batch,
+    changelogs, crate_scaffolds,
+    errors::ParameterError,
+    locks,
+    makefiles::MakefileSpec,
+    pipelines::{get_generation_task_names, run_task},
+    plans::GenerationPlan,
+    specs::{
+      diffs::{self, SpecDiff},
+      lints,
+    },
+    transactions::Transaction,
+    versions,
     yamls::OpenAPIRustGeneratorConfigs,
-    CrateScaffoldingError,
   },
-  testing::TestingError,
+  logging::{init_logging, LogFormats},
+  process::CANCELLATION_TOKEN,
+  reports::{record_stage, record_staged_dir, ReportFormats, RunReport},
 };
 
-/// Run a subcommand
-async fn run_subcommands(cli: &Cli) -> Result<(), CLIError> {
-  let Cli {
-    inner_cli: InnerCli {
-      api_spec_url_opt,
-      autogenerate,
-      ..
-    },
-    ..
-  } = cli;
-  match cli.command.as_ref() {
-    Some(SubCommands::TestGeneration { .. }) => {
-      let task_name = TaskNames::GenerateAll;
-      let output = run_cargo_make_task(cli, task_name)
-        .await
-        .map_err(TestingError::from)
-        .map_err(CLIError::from)?;
-      if !output.status.success() {
-        Err(CLIError::from(TestingError::ProcessError(
-          ProcessError::Failure(format!("{output:#?}")),
-        )))
-      } else {
-        Ok(())
-      }
-    }
-    None => {
-      if *autogenerate && api_spec_url_opt.is_some() {
-        let task_name = TaskNames::SpecDownloadDefault;
-        let output = run_cargo_make_task(cli, task_name)
-          .await
-          .map_err(CrateScaffoldingError::from)
-          .map_err(CLIError::from)?;
-        if !output.status.success() {
-          Err(CLIError::from(ProcessError::Failure(format!(
-            "{output:#?}"
-          ))))
-        } else {
-          let task_name = TaskNames::GenerateAll;
-          let output = run_cargo_make_task(cli, task_name)
-            .await
-            .map_err(CrateScaffoldingError::from)
-            .map_err(CLIError::from)?;
-          if !output.status.success() {
-            Err(CLIError::from(ProcessError::Failure(format!(
-              "{output:#?}"
-            ))))
-          } else {
-            Ok(())
-          }
-        }
-      } else {
-        Ok(())
-      }
+/// Write the crate files and run the generation tasks in a crate dir
+///
+/// The makefile is made from the crate's own cli, as its env records the crate dir.
+async fn generate(
+  cli: &Cli,
+  makefile_spec_opt: Option<&MakefileSpec>,
+  previous_spec_opt: Option<&Value>,
+  previous_version_opt: Option<&Version>,
+) -> Result<(), CLIError> {
+  if let Some(makefile_spec) = makefile_spec_opt {
+    record_stage("makefile", makefile_spec.write_to_makefile(cli)).await?;
+  }
+  record_stage("generator-configs", async {
+    let mut rust_generator_configs = OpenAPIRustGeneratorConfigs::new(cli);
+    if let Some(previous_version) = previous_version_opt {
+      rust_generator_configs.packageVersion = previous_version.to_string();
     }
+    rust_generator_configs.copy_spec_file(cli).await?;
+    rust_generator_configs.write_to_yaml_file(cli).await
+  })
+  .await?;
+  if cli.project_config_path_opt.is_some() || cli.spec_command_opt.is_some() {
+    record_stage("project-config", cli.project_config.write_to_yaml_file(cli)).await?;
+  }
+  let task_names = get_generation_task_names(cli);
+  let has_tasks = !task_names.is_empty();
+  for task_name in task_names {
+    run_task(cli, task_name).await?;
   }
+  if has_tasks {
+    record_stage("lock", locks::update_crate_lock(cli)).await?;
+  }
+  let spec_diff_opt = match previous_spec_opt {
+    Some(previous_spec) => Some(
+      record_stage(
+        "spec-diff",
+        diffs::report_crate_spec_changes(cli, previous_spec),
+      )
+      .await?,
+    ),
+    None => None,
+  };
+  record_stage(
+    "version",
+    versions::update_crate_version(cli, previous_version_opt, spec_diff_opt.as_ref()),
+  )
+  .await?;
+  record_stage(
+    "changelog",
+    changelogs::update_crate_changelog(cli, previous_spec_opt, spec_diff_opt.as_ref()),
+  )
+  .await?;
+  Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), CLIError> {
-  let cli = &Cli::new().await?;
-  crate_scaffolds::scaffold_crate(cli).await?;
-  let makefile_spec = MakefileSpec::try_from(cli)?;
-  makefile_spec.write_to_makefile(cli).await?;
-  let rust_generator_configs = OpenAPIRustGeneratorConfigs::new(cli);
-  rust_generator_configs.copy_spec_file(cli).await?;
-  rust_generator_configs.write_to_yaml_file(cli).await?;
-  run_subcommands(cli).await?;
+/// Scaffold the crate and generate it, in a staging dir if enabled
+async fn run(cli: &Cli) -> Result<(), CLIError> {
+  if cli.regenerate {
+    if !cli
+      .get_output_project_subpath(&Paths::CargoTomlFile)
+      .is_file()
+    {
+      return Err(ParameterError::NoCrateToRegenerate(cli.get_output_project_dir()).into());
+    }
+  } else {
+    record_stage("scaffold", crate_scaffolds::scaffold_crate(cli)).await?;
+  }
+  let (previous_spec_opt, previous_version_opt) = match cli.regenerate {
+    true => (
+      diffs::read_crate_spec(cli).await,
+      versions::read_crate_version(cli).await,
+    ),
+    false => (None, None),
+  };
+  let makefile_spec_opt = match cli.should_export_makefile() {
+    true => Some(MakefileSpec::try_from(cli)?),
+    false => None,
+  };
+  // Jobs kill their processes on Ctrl-C, in-process stages like spec fetching stop here
+  let generate_in = |cli| async move {
+    tokio::select! {
+      result = generate(
+        cli,
+        makefile_spec_opt.as_ref(),
+        previous_spec_opt.as_ref(),
+        previous_version_opt.as_ref(),
+      ) => result,
+      _ = CANCELLATION_TOKEN.cancelled() => Err(CLIError::Cancelled),
+    }
+  };
+  if !cli.staging {
+    return generate_in(cli).await;
+  }
+  let transaction = Transaction::try_for_crate_dir(cli.get_output_project_dir())?;
+  record_stage("staging", transaction.begin()).await?;
+  record_staged_dir(transaction.get_work_dir(), cli.get_output_project_dir());
+  let staged_cli = cli.with_output_project_dir(transaction.get_work_dir().to_path_buf());
+  match generate_in(&staged_cli).await {
+    Ok(()) => record_stage("commit", transaction.commit()).await?,
+    Err(e) => {
+      transaction.abort().await?;
+      return Err(e);
+    }
+  }
+  Ok(())
+}
 
+/// Write and print the report of a run
+async fn output_report(
+  cli: &Cli,
+  report: &RunReport,
+) -> Result<(), CLIError> {
+  if let Some(report_file_path) = cli.report_file_path_opt.as_ref() {
+    fs::write(
+      report_file_path,
+      report.to_json_string()?,
+      Some("Run report"),
+    )
+    .await?;
+  }
+  if let Some(ReportFormats::Json) = cli.report_format_opt {
+    println!("{}", report.to_json_string()?);
+  }
   Ok(())
 }
+
+/// Show the diagnostic of an error and get the matching exit code
+fn exit_with_error(
+  e: &CLIError,
+  log_format: LogFormats,
+) -> ExitCode {
+  let diagnostic = Diagnostic::from(e);
+  match log_format {
+    LogFormats::Text => eprintln!("{diagnostic}"),
+    LogFormats::Json => error!(
+      exit_code = diagnostic.exit_code as u8,
+      causes = ?diagnostic.causes,
+      suggestion = diagnostic.suggestion_opt,
+      "{}",
+      diagnostic.message
+    ),
+  }
+  diagnostic.exit_code.into()
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+  tokio::spawn(async {
+    if tokio::signal::ctrl_c().await.is_ok() {
+      CANCELLATION_TOKEN.cancel();
+    }
+  });
+  let cli = &match Cli::new().await {
+    Ok(cli) => cli,
+    Err(e) => return exit_with_error(&e, LogFormats::Text),
+  };
+  init_logging(cli.verbose, cli.quiet, cli.log_format);
+  let result = match cli.command.as_ref() {
+    Some(SubCommands::Doctor { install }) => run_doctor(*install).await.map_err(CLIError::from),
+    Some(SubCommands::Cache { command }) => {
+      run_cache_command(command).await.map_err(CLIError::from)
+    }
+    Some(SubCommands::Batch {
+      manifest_path,
+      concurrency_opt,
+    }) => run_batch(manifest_path, *concurrency_opt)
+      .await
+      .and_then(|summary| {
+        println!("{summary}");
+        summary.into_result()
+      })
+      .map_err(CLIError::from),
+    Some(SubCommands::SpecDiff {
+      old_spec_path,
+      new_spec_path,
+      format,
+    }) => SpecDiff::try_from_paths(old_spec_path, new_spec_path)
+      .await
+      .and_then(|spec_diff| spec_diff.to_formatted_string(*format))
+      .map(|output| println!("{output}"))
+      .map_err(CLIError::from),
+    Some(SubCommands::Lint { spec_path, format }) => {
+      lints::lint_file(spec_path, &cli.project_config.lint)
+        .await
+        .and_then(|report| {
+          println!("{}", report.to_formatted_string(*format)?);
+          report.into_result()
+        })
+        .map_err(CLIError::from)
+    }
+    Some(SubCommands::Rollback) => async {
+      Transaction::try_for_crate_dir(cli.get_output_project_dir())?
+        .rollback()
+        .await
+    }
+    .await
+    .map_err(CLIError::from),
+    _ if cli.plan => GenerationPlan::try_new(cli)
+      .await
+      .map(|plan| println!("{plan}"))
+      .map_err(CLIError::from),
+    _ => {
+      let (result, report) = RunReport::record(run(cli)).await;
+      result.and(output_report(cli, &report).await)
+    }
+  };
+  match result {
+    Ok(()) => ExitCodes::Success.into(),
+    Err(e) => exit_with_error(&e, cli.log_format),
+  }
+}
@@ -0,0 +1,305 @@
+//! Machine-readable reports of a generation run
+//!
+//! Enable with `--report json` (printed to stdout when the run ends) or `--report-file <PATH>`.
+//!
+//! # Format (version 1)
+//!
+//! A single JSON object:
+//!
+//! | Field            | Type                | Description                                           |
+//! |------------------|---------------------|-------------------------------------------------------|
+//! | `format_version` | integer             | [REPORT_FORMAT_VERSION], bumped on breaking changes   |
+//! | `olg_version`    | string              | Version of this crate                                 |
+//! | `started_at`     | RFC 3339 string     | When the run started                                  |
+//! | `finished_at`    | RFC 3339 string     | When the run ended                                    |
+//! | `success`        | boolean             | Whether the run succeeded                             |
+//! | `stages`         | array of [StageRecord]   | Every stage run, in order                        |
+//! | `files_written`  | array of [FileRecord]    | Every file written, in order                     |
+//! | `commands`       | array of [CommandRecord] | Every external command invoked, in order         |
+//! | `warnings`       | array of strings    | Warnings raised during the run                        |
+//! | `spec_diff`      | [SpecDiff] or null  | Spec changes since the last generation, on regenerate |
+//! | `errors`         | array of strings    | Errors raised during the run                          |
+//!
+//! Durations are in milliseconds. Hashes are lowercase hex SHA-256 digests. Files written and
+//! commands run in a staging dir are reported at their path in the crate.
+//! New fields may be added without bumping the version.
+use crate::{
+  diagnostics::format_error_chain,
+  generate::{specs::diffs::SpecDiff, utils::get_this_crate_ver},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  error::Error,
+  future::Future,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+use tracing::{debug, info_span, warn, Instrument};
+
+/// Version of the report format
+pub const REPORT_FORMAT_VERSION: u32 = 1;
+
+tokio::task_local! {
+  /// The report being recorded by the current task
+  static CURRENT_REPORT: Arc<Mutex<RunReport>>;
+}
+
+/// Report output formats
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportFormats {
+  Json,
+}
+
+/// A report of a generation run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunReport {
+  pub format_version: u32,
+  pub olg_version: String,
+  pub started_at: DateTime<Utc>,
+  pub finished_at: DateTime<Utc>,
+  pub success: bool,
+  pub stages: Vec<StageRecord>,
+  pub files_written: Vec<FileRecord>,
+  pub commands: Vec<CommandRecord>,
+  pub warnings: Vec<String>,
+  pub errors: Vec<String>,
+  #[serde(rename = "spec_diff")]
+  pub spec_diff_opt: Option<SpecDiff>,
+  /// Staging dirs, with the crate dirs their paths are reported in
+  #[serde(skip)]
+  staged_dirs: Vec<(PathBuf, PathBuf)>,
+}
+impl Default for RunReport {
+  fn default() -> Self {
+    Self {
+      format_version: REPORT_FORMAT_VERSION,
+      olg_version: get_this_crate_ver().to_string(),
+      started_at: Utc::now(),
+      finished_at: Utc::now(),
+      success: false,
+      stages: vec![],
+      files_written: vec![],
+      commands: vec![],
+      warnings: vec![],
+      errors: vec![],
+      spec_diff_opt: None,
+      staged_dirs: vec![],
+    }
+  }
+}
+impl RunReport {
+  /// Run a future while recording into a new report, returning both
+  pub async fn record<T, E: Error>(
+    future: impl Future<Output = Result<T, E>>
+  ) -> (Result<T, E>, Self) {
+    let report = Arc::new(Mutex::new(Self::default()));
+    let result = CURRENT_REPORT.scope(report.clone(), future).await;
+    let mut report = report.lock().expect("must lock report").clone();
+    if let Err(e) = result.as_ref() {
+      report.errors.push(format_error_chain(e));
+    }
+    report.success = result.is_ok();
+    report.finished_at = Utc::now();
+    (result, report)
+  }
+
+  /// Get the path a file is reported at, in the crate rather than its staging dir
+  fn get_reported_path(
+    &self,
+    path: &Path,
+  ) -> String {
+    self
+      .staged_dirs
+      .iter()
+      .find_map(|(staging_dir, crate_dir)| {
+        let relative_path = path.strip_prefix(staging_dir).ok()?;
+        Some(crate_dir.join(relative_path))
+      })
+      .unwrap_or_else(|| path.to_path_buf())
+      .to_string_lossy()
+      .to_string()
+  }
+
+  /// Serialize as pretty JSON
+  pub fn to_json_string(&self) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(self)
+  }
+}
+
+/// A stage of the run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StageRecord {
+  pub name: String,
+  pub duration_ms: u64,
+  pub success: bool,
+  #[serde(rename = "error")]
+  pub error_opt: Option<String>,
+}
+
+/// A file written during the run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileRecord {
+  pub path: String,
+  pub bytes: usize,
+  pub sha256: String,
+}
+
+/// An external command invoked during the run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CommandRecord {
+  pub command_line: String,
+  pub cwd: String,
+  #[serde(rename = "exit_code")]
+  pub exit_code_opt: Option<i32>,
+  pub duration_ms: u64,
+  pub success: bool,
+}
+
+/// Modify the current report, if any
+fn with_current_report(f: impl FnOnce(&mut RunReport)) {
+  let _ = CURRENT_REPORT.try_with(|report| {
+    if let Ok(mut report) = report.lock() {
+      f(&mut report)
+    }
+  });
+}
+
+/// Get a duration in whole milliseconds
+fn to_millis(duration: Duration) -> u64 {
+  u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Get the lowercase hex SHA-256 digest of some bytes
+pub fn sha256_hex(contents: impl AsRef<[u8]>) -> String {
+  format!("{:x}", Sha256::digest(contents.as_ref()))
+}
+
+/// Run a stage and record its duration and outcome
+pub async fn record_stage<T, E: Error>(
+  name: impl AsRef<str>,
+  future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+  let span = info_span!("stage", stage = name.as_ref());
+  let started = Instant::now();
+  debug!(parent: &span, "Starting stage");
+  let result = future.instrument(span.clone()).await;
+  debug!(parent: &span, elapsed = ?started.elapsed(), success = result.is_ok(), "Finished stage");
+  let record = StageRecord {
+    name: name.as_ref().to_string(),
+    duration_ms: to_millis(started.elapsed()),
+    success: result.is_ok(),
+    error_opt: result.as_ref().err().map(|e| format_error_chain(e)),
+  };
+  with_current_report(|report| report.stages.push(record));
+  result
+}
+
+/// Record a written file
+pub fn record_file_written(
+  path: impl AsRef<Path>,
+  contents: impl AsRef<[u8]>,
+) {
+  let bytes = contents.as_ref().len();
+  let sha256 = sha256_hex(contents);
+  with_current_report(|report| {
+    let record = FileRecord {
+      path: report.get_reported_path(path.as_ref()),
+      bytes,
+      sha256,
+    };
+    report.files_written.push(record)
+  });
+}
+
+/// Record that a crate is generated in a staging dir, to report paths in the crate instead
+pub fn record_staged_dir(
+  staging_dir: impl AsRef<Path>,
+  crate_dir: impl AsRef<Path>,
+) {
+  let staged_dir = (
+    staging_dir.as_ref().to_path_buf(),
+    crate_dir.as_ref().to_path_buf(),
+  );
+  with_current_report(|report| report.staged_dirs.push(staged_dir));
+}
+
+/// Record an invoked command
+pub fn record_command(
+  command_line: impl AsRef<str>,
+  cwd: impl AsRef<Path>,
+  exit_code_opt: Option<i32>,
+  duration: Duration,
+) {
+  with_current_report(|report| {
+    let record = CommandRecord {
+      command_line: command_line.as_ref().to_string(),
+      cwd: report.get_reported_path(cwd.as_ref()),
+      exit_code_opt,
+      duration_ms: to_millis(duration),
+      success: exit_code_opt == Some(0),
+    };
+    report.commands.push(record)
+  });
+}
+
+/// Log and record a warning
+pub fn record_warning(warning: impl AsRef<str>) {
+  warn!("{}", warning.as_ref());
+  with_current_report(|report| report.warnings.push(warning.as_ref().to_string()));
+}
+
+/// Record the spec changes since the last generation
+pub fn record_spec_diff(spec_diff: &SpecDiff) {
+  with_current_report(|report| report.spec_diff_opt = Some(spec_diff.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn report_records_within_scope() {
+    let dir = std::env::temp_dir().join(format!("olg_reports_{}", std::process::id()));
+    fs_err::create_dir_all(&dir).expect("must create dir");
+    let written_path = dir.join("a.txt");
+    let staging_dir = dir.join(".pets.olg-staging");
+    fs_err::create_dir_all(&staging_dir).expect("must create dir");
+    let (result, report) = RunReport::record(async {
+      crate::fs::write(&written_path, "abc", None::<&str>)
+        .await
+        .expect("must write");
+      let _ = crate::fs::write(dir.join("missing").join("b.txt"), "def", None::<&str>).await;
+      record_staged_dir(&staging_dir, dir.join("pets"));
+      crate::fs::write(staging_dir.join("lib.rs"), "", None::<&str>)
+        .await
+        .expect("must write");
+      record_warning("careful");
+      record_stage("failing", async {
+        Err::<(), _>(std::io::Error::other("nope"))
+      })
+      .await
+    })
+    .await;
+    assert!(result.is_err());
+    assert!(!report.success);
+    assert_eq!(report.files_written.len(), 2);
+    assert_eq!(
+      report.files_written[1].path,
+      dir.join("pets").join("lib.rs").to_string_lossy()
+    );
+    assert_eq!(report.files_written[0].path, written_path.to_string_lossy());
+    assert_eq!(report.files_written[0].bytes, 3);
+    assert_eq!(
+      report.files_written[0].sha256,
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(report.warnings, vec!["careful"]);
+    assert_eq!(report.stages[0].error_opt.as_deref(), Some("nope"));
+    assert_eq!(report.errors, vec!["nope"]);
+    fs_err::remove_dir_all(dir).expect("must clean up");
+  }
+}
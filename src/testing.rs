@@ -6,15 +6,30 @@ use std::io::Error as IOError;
 use thiserror::Error;
 
 /// A fake OpenAPI specification
-pub static PETSTORE_YAML: &'static str = include_str!("testing/petstore.yaml");
+pub static PETSTORE_YAML: &str = include_str!("testing/petstore.yaml");
 /// A name for a testing OpenAPI yaml spec file
-pub static TESTING_SPEC_FILE_NAME: &'static str = "petshoppe_test_spec.yaml";
+pub static TESTING_SPEC_FILE_NAME: &str = "petshoppe_test_spec.yaml";
 /// A testing folder name
-pub static TEST_SUBDIR_NAME: &'static str = "testing";
+pub static TEST_SUBDIR_NAME: &str = "testing";
 /// A testing api url
-pub static TEST_API_URL: &'static str = "https://www.petshoppe.example";
+pub static TEST_API_URL: &str = "https://www.petshoppe.example";
 /// A testing api name
-pub static TEST_API_NAME: &'static str = "PetShoppe";
+pub static TEST_API_NAME: &str = "PetShoppe";
+/// Make a [Cli](crate::cli::Cli) for unit tests
+#[cfg(test)]
+pub(crate) fn make_test_cli() -> crate::cli::Cli {
+  use clap::Parser;
+  crate::cli::Cli {
+    inner_cli: crate::cli::InnerCli::parse_from([
+      "olg",
+      "--name=pets",
+      "--api-url=https://www.petshoppe.example",
+      "--spec-file=pets.yaml",
+    ]),
+    generation_timestamp: chrono::Utc::now(),
+    project_config: Default::default(),
+  }
+}
 /// Testing errors
 #[derive(Debug, Error)]
 pub enum TestingError {